@@ -0,0 +1,213 @@
+use std::cmp::Ordering;
+
+use super::header::TextEncoding;
+use super::record::RecordValue;
+
+/// Type affinity of a column, derived from its declared type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Affinity {
+    Integer,
+    Real,
+    Numeric,
+    Text,
+    Blob,
+}
+
+impl Affinity {
+    /// SQLite's rules for the affinity of a declared type, checked in order:
+    /// "INT" gives INTEGER; "CHAR", "CLOB" or "TEXT" give TEXT; "BLOB" or no
+    /// type gives BLOB; "REAL", "FLOA" or "DOUB" give REAL; anything else is
+    /// NUMERIC.
+    pub fn from_declared_type(declared_type: &str) -> Self {
+        let upper = declared_type.to_ascii_uppercase();
+        if upper.contains("INT") {
+            Affinity::Integer
+        } else if upper.contains("CHAR") || upper.contains("CLOB") || upper.contains("TEXT") {
+            Affinity::Text
+        } else if upper.contains("BLOB") || upper.trim().is_empty() {
+            Affinity::Blob
+        } else if upper.contains("REAL") || upper.contains("FLOA") || upper.contains("DOUB") {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Integer | Affinity::Real | Affinity::Numeric)
+    }
+
+    /// Convert a value the way a column with this affinity would store it
+    pub fn apply(self, value: &RecordValue) -> RecordValue {
+        let value = normalize(value);
+        match self {
+            Affinity::Blob => value,
+            Affinity::Text => match value {
+                RecordValue::Int(_) | RecordValue::Float(_) => RecordValue::Text(value_to_text(&value)),
+                other => other,
+            },
+            Affinity::Integer | Affinity::Numeric => match value {
+                RecordValue::Text(s) => parse_numeric_text(&s).map_or(RecordValue::Text(s), integral_float_to_int),
+                RecordValue::Float(f) => integral_float_to_int(RecordValue::Float(f)),
+                other => other,
+            },
+            Affinity::Real => match value {
+                RecordValue::Text(s) => match parse_numeric_text(&s) {
+                    Some(RecordValue::Int(i)) => RecordValue::Float(i as f64),
+                    Some(number) => number,
+                    None => RecordValue::Text(s),
+                },
+                RecordValue::Int(i) => RecordValue::Float(i as f64),
+                other => other,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for Affinity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Affinity::Integer => "INTEGER",
+            Affinity::Real => "REAL",
+            Affinity::Numeric => "NUMERIC",
+            Affinity::Text => "TEXT",
+            Affinity::Blob => "BLOB",
+        })
+    }
+}
+
+/// Apply SQLite's comparison affinity rules to the operands of a comparison,
+/// given the affinity each operand expression carries (only column
+/// references have one). A numeric side converts the other side to a number
+/// where possible; otherwise a TEXT side converts a side with no affinity to
+/// text.
+pub fn comparison_operands(
+    left: &RecordValue,
+    left_affinity: Option<Affinity>,
+    right: &RecordValue,
+    right_affinity: Option<Affinity>,
+) -> (RecordValue, RecordValue) {
+    let has_text_or_none = |affinity: Option<Affinity>| !affinity.is_some_and(Affinity::is_numeric);
+    let left_numeric = left_affinity.is_some_and(Affinity::is_numeric);
+    let right_numeric = right_affinity.is_some_and(Affinity::is_numeric);
+
+    if left_numeric && has_text_or_none(right_affinity) {
+        (normalize(left), Affinity::Numeric.apply(right))
+    } else if right_numeric && has_text_or_none(left_affinity) {
+        (Affinity::Numeric.apply(left), normalize(right))
+    } else if left_affinity == Some(Affinity::Text) && right_affinity.is_none() {
+        (normalize(left), Affinity::Text.apply(right))
+    } else if right_affinity == Some(Affinity::Text) && left_affinity.is_none() {
+        (Affinity::Text.apply(left), normalize(right))
+    } else {
+        (normalize(left), normalize(right))
+    }
+}
+
+/// Order two values the way SQLite does with the BINARY collation: NULL
+/// sorts before numbers, numbers before text and text before blobs. Integers
+/// and reals compare by numeric value, and text by its bytes in the
+/// database's `encoding`.
+pub fn compare_values(left: &RecordValue, right: &RecordValue, encoding: TextEncoding) -> Ordering {
+    let left = normalize(left);
+    let right = normalize(right);
+    match (&left, &right) {
+        (RecordValue::Int(a), RecordValue::Int(b)) => a.cmp(b),
+        (RecordValue::Float(a), RecordValue::Float(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+        (RecordValue::Int(a), RecordValue::Float(b)) => compare_int_float(*a, *b),
+        (RecordValue::Float(a), RecordValue::Int(b)) => compare_int_float(*b, *a).reverse(),
+        (RecordValue::Text(a), RecordValue::Text(b)) => compare_encoded(a, b, encoding),
+        (RecordValue::Blob(a), RecordValue::Blob(b)) => a.cmp(b),
+        _ => storage_class_rank(&left).cmp(&storage_class_rank(&right)),
+    }
+}
+
+/// Like [`compare_values`], comparing text with the named collation: NOCASE
+/// folds ASCII letters to lower case and RTRIM ignores trailing spaces, both
+/// on UTF-8 as SQLite only defines them for that. Any other collation is
+/// BINARY.
+pub fn compare_collated(left: &RecordValue, right: &RecordValue, collation: Option<&str>, encoding: TextEncoding) -> Ordering {
+    match (left, right, collation) {
+        (RecordValue::Text(a), RecordValue::Text(b), Some(collation)) if collation.eq_ignore_ascii_case("nocase") => {
+            a.bytes().map(|c| c.to_ascii_lowercase()).cmp(b.bytes().map(|c| c.to_ascii_lowercase()))
+        }
+        (RecordValue::Text(a), RecordValue::Text(b), Some(collation)) if collation.eq_ignore_ascii_case("rtrim") => {
+            a.trim_end_matches(' ').as_bytes().cmp(b.trim_end_matches(' ').as_bytes())
+        }
+        _ => compare_values(left, right, encoding),
+    }
+}
+
+/// Order two strings by the bytes `encoding` stores them as
+fn compare_encoded(a: &str, b: &str, encoding: TextEncoding) -> Ordering {
+    match encoding {
+        TextEncoding::Utf8 => a.as_bytes().cmp(b.as_bytes()),
+        // Big-endian code units sort like their bytes
+        TextEncoding::Utf16be => a.encode_utf16().cmp(b.encode_utf16()),
+        TextEncoding::Utf16le => a.encode_utf16().map(u16::to_le_bytes).cmp(b.encode_utf16().map(u16::to_le_bytes)),
+    }
+}
+
+fn storage_class_rank(value: &RecordValue) -> u8 {
+    match value {
+        RecordValue::Null => 0,
+        RecordValue::Int(_) | RecordValue::Float(_) | RecordValue::Zero | RecordValue::One => 1,
+        RecordValue::Text(_) => 2,
+        RecordValue::Blob(_) | RecordValue::Reserved(_) => 3,
+    }
+}
+
+/// Compare an integer with a real without losing precision on large values
+fn compare_int_float(int: i64, float: f64) -> Ordering {
+    if float.is_nan() {
+        return Ordering::Greater;
+    }
+    if float < -9.223_372_036_854_776e18 {
+        return Ordering::Greater;
+    }
+    if float >= 9.223_372_036_854_776e18 {
+        return Ordering::Less;
+    }
+    let truncated = float as i64;
+    match int.cmp(&truncated) {
+        Ordering::Equal => 0.0.partial_cmp(&(float - truncated as f64)).unwrap_or(Ordering::Equal),
+        other => other,
+    }
+}
+
+/// Fold the constant serial types into plain integers
+pub fn normalize(value: &RecordValue) -> RecordValue {
+    match value {
+        RecordValue::Zero => RecordValue::Int(0),
+        RecordValue::One => RecordValue::Int(1),
+        other => other.clone(),
+    }
+}
+
+/// Text form of a value as SQLite converts it, e.g. the real 1.0 becomes "1.0"
+pub fn value_to_text(value: &RecordValue) -> String {
+    match value {
+        RecordValue::Float(f) if f.is_finite() && f.fract() == 0.0 && f.abs() < 1e15 => format!("{:.1}", f),
+        other => other.to_display_string(),
+    }
+}
+
+/// The number a whole text value spells, if it is a well-formed integer or
+/// real literal (surrounding spaces allowed)
+fn parse_numeric_text(text: &str) -> Option<RecordValue> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() || !trimmed.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) {
+        return None;
+    }
+    if let Ok(i) = trimmed.parse::<i64>() {
+        return Some(RecordValue::Int(i));
+    }
+    trimmed.parse::<f64>().ok().map(RecordValue::Float)
+}
+
+fn integral_float_to_int(value: RecordValue) -> RecordValue {
+    match value {
+        RecordValue::Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => RecordValue::Int(f as i64),
+        other => other,
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{bail, Result};
+
+use super::cell::{self, PayloadKind};
+use super::database::{INTERIOR_INDEX_PAGE, INTERIOR_TABLE_PAGE, LEAF_INDEX_PAGE, LEAF_TABLE_PAGE};
+use super::varint::{read_varint, write_varint};
+
+/// Decoded view of a B-tree page used by the write path: the page header is
+/// reduced to what matters for rebuilding the page and every cell is kept as
+/// its raw on-page bytes. Serializing always produces a defragmented page.
+#[derive(Debug, Clone)]
+pub struct BTreePage {
+    pub page_type: u8,
+    pub cells: Vec<Vec<u8>>,
+    pub right_child: Option<u32>,
+}
+
+impl BTreePage {
+    pub fn new(page_type: u8) -> Self {
+        let right_child = if is_interior(page_type) { Some(0) } else { None };
+        Self { page_type, cells: Vec::new(), right_child }
+    }
+
+    /// Parse the page whose B-tree header starts at `header_offset`
+    pub fn parse(page_data: &[u8], header_offset: usize, usable_size: usize) -> Result<Self> {
+        if header_offset + 8 > page_data.len() {
+            bail!("Page data too small for page header");
+        }
+
+        let page_type = page_data[header_offset];
+        let header_size = btree_header_size(page_type)?;
+        let cell_count = u16::from_be_bytes([page_data[header_offset + 3], page_data[header_offset + 4]]) as usize;
+
+        let right_child = if is_interior(page_type) {
+            Some(read_u32(page_data, header_offset + 8)?)
+        } else {
+            None
+        };
+
+        let ptr_start = header_offset + header_size;
+        if ptr_start + cell_count * 2 > page_data.len() {
+            bail!("Page data too small to contain all cell pointers");
+        }
+
+        let mut cells = Vec::with_capacity(cell_count);
+        for i in 0..cell_count {
+            let ptr = ptr_start + i * 2;
+            let offset = u16::from_be_bytes([page_data[ptr], page_data[ptr + 1]]) as usize;
+            let size = cell_size(page_data, offset, page_type, usable_size)?;
+            if offset + size > usable_size {
+                bail!("Cell at offset {} extends beyond the usable page area", offset);
+            }
+            cells.push(page_data[offset..offset + size].to_vec());
+        }
+
+        Ok(Self { page_type, cells, right_child })
+    }
+
+    /// Bytes this page needs, including the B-tree header and pointer array
+    pub fn used_space(&self, header_offset: usize) -> usize {
+        let header_size = btree_header_size(self.page_type).unwrap_or(12);
+        let cells: usize = self.cells.iter().map(|c| c.len() + 2).sum();
+        header_offset + header_size + cells
+    }
+
+    pub fn fits(&self, header_offset: usize, usable_size: usize) -> bool {
+        self.used_space(header_offset) <= usable_size
+    }
+
+    /// Write the page into `page_data`, leaving everything before
+    /// `header_offset` (the database header on page 1) and the reserved
+    /// bytes past `usable_size` untouched
+    pub fn write_to(&self, page_data: &mut [u8], header_offset: usize, usable_size: usize) -> Result<()> {
+        if !self.fits(header_offset, usable_size) {
+            bail!("B-tree page content does not fit in {} bytes", usable_size);
+        }
+
+        let header_size = btree_header_size(self.page_type)?;
+        page_data[header_offset..usable_size].fill(0);
+
+        let mut content_start = usable_size;
+        let mut ptr = header_offset + header_size;
+        for cell in &self.cells {
+            content_start -= cell.len();
+            page_data[content_start..content_start + cell.len()].copy_from_slice(cell);
+            page_data[ptr..ptr + 2].copy_from_slice(&(content_start as u16).to_be_bytes());
+            ptr += 2;
+        }
+
+        page_data[header_offset] = self.page_type;
+        // Bytes 1-2: first freeblock (none), 7: fragmented free bytes (none)
+        page_data[header_offset + 3..header_offset + 5].copy_from_slice(&(self.cells.len() as u16).to_be_bytes());
+        // A content start of 65536 is stored as 0, which the u16 cast yields
+        page_data[header_offset + 5..header_offset + 7].copy_from_slice(&(content_start as u16).to_be_bytes());
+        if let Some(right_child) = self.right_child {
+            page_data[header_offset + 8..header_offset + 12].copy_from_slice(&right_child.to_be_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+pub fn is_interior(page_type: u8) -> bool {
+    page_type == INTERIOR_TABLE_PAGE || page_type == INTERIOR_INDEX_PAGE
+}
+
+pub fn btree_header_size(page_type: u8) -> Result<usize> {
+    match page_type {
+        LEAF_TABLE_PAGE | LEAF_INDEX_PAGE => Ok(8),
+        INTERIOR_TABLE_PAGE | INTERIOR_INDEX_PAGE => Ok(12),
+        _ => bail!("Invalid B-tree page type {}", page_type),
+    }
+}
+
+/// Size in bytes of the cell starting at `offset`, including the overflow
+/// page pointer of spilled payloads
+pub fn cell_size(page_data: &[u8], offset: usize, page_type: u8, usable_size: usize) -> Result<usize> {
+    let mut pos = offset;
+    if is_interior(page_type) {
+        pos += 4;
+    }
+
+    match page_type {
+        INTERIOR_TABLE_PAGE => {
+            let (_, bytes_read) = read_varint(page_data, pos)?;
+            Ok(pos + bytes_read - offset)
+        }
+        LEAF_TABLE_PAGE | LEAF_INDEX_PAGE | INTERIOR_INDEX_PAGE => {
+            let (payload_size, bytes_read) = read_varint(page_data, pos)?;
+            pos += bytes_read;
+            let kind = if page_type == LEAF_TABLE_PAGE {
+                let (_, bytes_read) = read_varint(page_data, pos)?;
+                pos += bytes_read;
+                PayloadKind::TableLeaf
+            } else {
+                PayloadKind::Index
+            };
+
+            let payload_size = payload_size as usize;
+            let local_size = cell::local_payload_size(payload_size, usable_size, kind);
+            let overflow_ptr = if local_size < payload_size { 4 } else { 0 };
+            Ok(pos + local_size + overflow_ptr - offset)
+        }
+        _ => bail!("Invalid B-tree page type {}", page_type),
+    }
+}
+
+/// Left child pointer of an interior cell
+pub fn left_child(cell: &[u8]) -> Result<u32> {
+    read_u32(cell, 0)
+}
+
+pub fn set_left_child(cell: &mut [u8], page_number: u32) {
+    cell[..4].copy_from_slice(&page_number.to_be_bytes());
+}
+
+/// Integer key of a table cell: the rowid of a leaf cell or the divider key
+/// of an interior cell
+pub fn table_cell_key(cell: &[u8], page_type: u8) -> Result<i64> {
+    match page_type {
+        INTERIOR_TABLE_PAGE => Ok(read_varint(cell, 4)?.0 as i64),
+        LEAF_TABLE_PAGE => {
+            let (_, bytes_read) = read_varint(cell, 0)?;
+            Ok(read_varint(cell, bytes_read)?.0 as i64)
+        }
+        _ => bail!("Page type {} has no integer keys", page_type),
+    }
+}
+
+/// Build an interior table cell pointing at `left_child` with key `key`
+pub fn interior_table_cell(left_child: u32, key: i64) -> Vec<u8> {
+    let mut cell = left_child.to_be_bytes().to_vec();
+    write_varint(key as u64, &mut cell);
+    cell
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    if offset + 4 > data.len() {
+        bail!("Not enough data to read a 4-byte integer at offset {}", offset);
+    }
+    Ok(u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]))
+}
@@ -0,0 +1,769 @@
+use anyhow::{bail, Result};
+
+use super::btree_page::{self, BTreePage};
+use super::cell::{self, PayloadKind};
+use super::database::{Database, INTERIOR_TABLE_PAGE, LEAF_TABLE_PAGE};
+use super::record::{Record, RecordFormat, RecordValue};
+use super::schema::ColumnInfo;
+use super::varint::{read_varint, write_varint};
+use crate::query::parser::{self, Expr};
+
+
+/// (interior page, child slot) pairs taken while descending a B-tree
+type BTreePath = Vec<(usize, usize)>;
+
+impl Database {
+    /// Insert a row into `table_name` and return its rowid. `column_names`
+    /// lists the columns the values belong to (every column in declaration
+    /// order when `None`); columns that are not mentioned get their DEFAULT
+    /// value, or NULL if they have none.
+    pub fn insert_row(&mut self, table_name: &str, column_names: Option<&[String]>, values: Vec<RecordValue>) -> Result<i64> {
+        let table_info = self.find_table_info(table_name)?;
+        let columns = self.get_col_names(table_name)?;
+        self.check_writable_table(table_name)?;
+
+        let mut row = match column_names {
+            Some(names) => {
+                if names.len() != values.len() {
+                    bail!("{} values for {} columns", values.len(), names.len());
+                }
+                let mut row = columns.iter()
+                    .map(|col| Self::default_value(table_name, col))
+                    .collect::<Result<Vec<_>>>()?;
+                for (name, value) in names.iter().zip(values) {
+                    let index = columns.iter()
+                        .position(|col| col.name.eq_ignore_ascii_case(name))
+                        .ok_or_else(|| anyhow::anyhow!("Table {} has no column named {}", table_name, name))?;
+                    row[index] = value;
+                }
+                row
+            }
+            None => {
+                if values.len() != columns.len() {
+                    bail!("Table {} has {} columns but {} values were supplied", table_name, columns.len(), values.len());
+                }
+                values
+            }
+        };
+
+        Self::apply_column_affinity(&columns, &mut row);
+        let requested_rowid = Self::take_rowid_alias(&columns, &mut row)?;
+        Self::check_not_null(table_name, &columns, &row)?;
+        let rowid = match requested_rowid {
+            Some(rowid) => rowid,
+            None => self.next_rowid(table_info.rootpage)?,
+        };
+
+        self.insert_record(table_info.rootpage, rowid, row)?;
+        Ok(rowid)
+    }
+
+    /// Delete the row with the given rowid; returns whether it existed
+    pub fn delete_row(&mut self, table_name: &str, rowid: i64) -> Result<bool> {
+        let table_info = self.find_table_info(table_name)?;
+        self.check_writable_table(table_name)?;
+        self.delete_table_cell(table_info.rootpage, rowid)
+    }
+
+    /// Overwrite columns of the row with the given rowid and return the
+    /// row's rowid afterwards, which changes when the INTEGER PRIMARY KEY
+    /// column is assigned. Returns `None` if there is no such row.
+    pub fn update_row(&mut self, table_name: &str, rowid: i64, assignments: &[(String, RecordValue)]) -> Result<Option<i64>> {
+        let table_info = self.find_table_info(table_name)?;
+        let columns = self.get_col_names(table_name)?;
+        self.check_writable_table(table_name)?;
+
+        let Some(existing) = self.get_table_row_by_id(table_name, rowid as u64)? else {
+            return Ok(None);
+        };
+
+        let mut row = existing.values;
+        for (name, value) in assignments {
+            let index = columns.iter()
+                .position(|col| col.name.eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow::anyhow!("Table {} has no column named {}", table_name, name))?;
+            row[index] = value.clone();
+        }
+
+        Self::apply_column_affinity(&columns, &mut row);
+        let new_rowid = Self::take_rowid_alias(&columns, &mut row)?.unwrap_or(rowid);
+        Self::check_not_null(table_name, &columns, &row)?;
+        if new_rowid != rowid && self.get_table_row_by_id(table_name, new_rowid as u64)?.is_some() {
+            bail!("UNIQUE constraint failed: rowid {} already exists", new_rowid);
+        }
+
+        // Replacing the cell may change its size, so go through the regular
+        // delete and insert paths and let them rebalance
+        self.delete_table_cell(table_info.rootpage, rowid)?;
+        self.insert_record(table_info.rootpage, new_rowid, row)?;
+        Ok(Some(new_rowid))
+    }
+
+    /// The INTEGER PRIMARY KEY column aliases the rowid and is stored as
+    /// NULL; take its value out of `row` as the requested rowid
+    fn take_rowid_alias(columns: &[ColumnInfo], row: &mut [RecordValue]) -> Result<Option<i64>> {
+        let alias = columns.iter().position(|col| col.is_rowid_alias);
+        match alias.map(|i| std::mem::replace(&mut row[i], RecordValue::Null)) {
+            Some(RecordValue::Int(id)) => Ok(Some(id)),
+            Some(RecordValue::Zero) => Ok(Some(0)),
+            Some(RecordValue::One) => Ok(Some(1)),
+            Some(RecordValue::Null) | None => Ok(None),
+            Some(other) => bail!("datatype mismatch: rowid must be an integer, got {}", other.to_display_string()),
+        }
+    }
+
+    /// Value of an omitted column: its DEFAULT if it has a constant one
+    fn default_value(table_name: &str, column: &ColumnInfo) -> Result<RecordValue> {
+        let Some(default) = &column.default_value else {
+            return Ok(RecordValue::Null);
+        };
+        match parser::parse_expr(default) {
+            Ok(Expr::Literal(value)) => Ok(value),
+            _ => bail!("Unsupported DEFAULT value for {}.{}: {}", table_name, column.name, default),
+        }
+    }
+
+    /// Convert values the way each column's type affinity stores them, so
+    /// that e.g. '42' inserted into an INTEGER column is stored as 42
+    fn apply_column_affinity(columns: &[ColumnInfo], row: &mut [RecordValue]) {
+        for (column, value) in columns.iter().zip(row.iter_mut()) {
+            *value = column.affinity.apply(value);
+        }
+    }
+
+    /// Enforce NOT NULL constraints. The rowid alias column is exempt: it is
+    /// stored as NULL and a missing value means a new rowid is chosen.
+    fn check_not_null(table_name: &str, columns: &[ColumnInfo], row: &[RecordValue]) -> Result<()> {
+        for (column, value) in columns.iter().zip(row) {
+            if column.not_null && !column.is_rowid_alias && matches!(value, RecordValue::Null) {
+                bail!("NOT NULL constraint failed: {}.{}", table_name, column.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode `row` and place it in the table B-tree rooted at `root_page`
+    fn insert_record(&mut self, root_page: usize, rowid: i64, row: Vec<RecordValue>) -> Result<()> {
+        let format = RecordFormat { schema_format: self.header()?.schema_format, encoding: self.text_encoding() };
+        let payload = Record::from_values(row, format).to_bytes();
+        let cell = self.build_table_leaf_cell(rowid, &payload)?;
+        self.insert_table_cell(root_page, rowid, cell)
+    }
+
+    /// Reject tables whose modification would leave other structures stale
+    fn check_writable_table(&mut self, table_name: &str) -> Result<()> {
+        let header = self.header()?;
+        if header.is_wal_mode() {
+            bail!("Writing to WAL-mode databases is not supported");
+        }
+        if header.largest_root_page != 0 {
+            bail!("Writing to auto-vacuum databases is not supported");
+        }
+
+        if self.get_table_schema(table_name)?.without_rowid {
+            bail!("Writing to WITHOUT ROWID table {} is not supported", table_name);
+        }
+
+        let has_index = self.get_all_schema_objects()?
+            .iter()
+            .any(|obj| obj.object_type == "index" && obj.tbl_name == table_name);
+        if has_index {
+            bail!("Writing to table {} is not supported: it has indexes that would need updating", table_name);
+        }
+
+        Ok(())
+    }
+
+    /// One more than the largest rowid in the table, following rightmost pointers
+    fn next_rowid(&mut self, root_page: usize) -> Result<i64> {
+        let mut page_num = root_page;
+        loop {
+            let page = self.load_btree_page(page_num)?;
+            match page.page_type {
+                INTERIOR_TABLE_PAGE => page_num = page.right_child.unwrap_or(0) as usize,
+                LEAF_TABLE_PAGE => {
+                    let max_rowid = match page.cells.last() {
+                        Some(cell) => btree_page::table_cell_key(cell, LEAF_TABLE_PAGE)?,
+                        None => 0,
+                    };
+                    if max_rowid == i64::MAX {
+                        bail!("No rowid left above {}", max_rowid);
+                    }
+                    return Ok(max_rowid.max(0) + 1);
+                }
+                page_type => bail!("Unsupported page type {} for table insert", page_type),
+            }
+        }
+    }
+
+    /// Encode a table leaf cell, moving whatever does not fit locally onto
+    /// freshly allocated overflow pages
+    fn build_table_leaf_cell(&mut self, rowid: i64, payload: &[u8]) -> Result<Vec<u8>> {
+        let mut cell = Vec::new();
+        write_varint(payload.len() as u64, &mut cell);
+        write_varint(rowid as u64, &mut cell);
+
+        let local_size = cell::local_payload_size(payload.len(), self.get_usable_size(), PayloadKind::TableLeaf);
+        cell.extend_from_slice(&payload[..local_size]);
+
+        if local_size < payload.len() {
+            let first_page = self.write_overflow_chain(&payload[local_size..])?;
+            cell.extend_from_slice(&(first_page as u32).to_be_bytes());
+        }
+
+        Ok(cell)
+    }
+
+    /// Store `data` on a chain of overflow pages and return the first page number
+    fn write_overflow_chain(&mut self, data: &[u8]) -> Result<usize> {
+        let page_size = self.get_page_size();
+        let chunks: Vec<&[u8]> = data.chunks(self.get_usable_size() - 4).collect();
+        let pages = chunks.iter()
+            .map(|_| self.allocate_page())
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, chunk) in chunks.iter().enumerate() {
+            let next_page = pages.get(i + 1).copied().unwrap_or(0) as u32;
+            let mut page_data = vec![0; page_size];
+            page_data[..4].copy_from_slice(&next_page.to_be_bytes());
+            page_data[4..4 + chunk.len()].copy_from_slice(chunk);
+            self.put_page(pages[i], page_data);
+        }
+
+        Ok(pages[0])
+    }
+
+    /// Place a leaf cell in the table B-tree rooted at `root_page`
+    fn insert_table_cell(&mut self, root_page: usize, rowid: i64, cell: Vec<u8>) -> Result<()> {
+        let (path, page_num, mut page) = self.find_table_leaf(root_page, rowid)?;
+
+        let mut position = page.cells.len();
+        for (i, leaf_cell) in page.cells.iter().enumerate() {
+            let existing = btree_page::table_cell_key(leaf_cell, LEAF_TABLE_PAGE)?;
+            if existing == rowid {
+                bail!("UNIQUE constraint failed: rowid {} already exists", rowid);
+            }
+            if existing > rowid {
+                position = i;
+                break;
+            }
+        }
+
+        let append = position == page.cells.len();
+        page.cells.insert(position, cell);
+        self.store_balanced(page_num, page, path, append)
+    }
+
+    /// Remove the leaf cell for `rowid` from the table B-tree rooted at
+    /// `root_page`, releasing its overflow pages
+    fn delete_table_cell(&mut self, root_page: usize, rowid: i64) -> Result<bool> {
+        let (path, page_num, mut page) = self.find_table_leaf(root_page, rowid)?;
+
+        let mut position = None;
+        for (i, leaf_cell) in page.cells.iter().enumerate() {
+            if btree_page::table_cell_key(leaf_cell, LEAF_TABLE_PAGE)? == rowid {
+                position = Some(i);
+                break;
+            }
+        }
+
+        let Some(position) = position else {
+            return Ok(false);
+        };
+
+        let cell = page.cells.remove(position);
+        self.free_overflow_chain(&cell)?;
+        self.store_after_delete(page_num, page, path)?;
+        Ok(true)
+    }
+
+    /// Walk from `root_page` down to the leaf that holds (or would hold)
+    /// `rowid`. Returns the (interior page, child slot) pairs taken on the
+    /// way, the leaf page number and the decoded leaf.
+    fn find_table_leaf(&mut self, root_page: usize, rowid: i64) -> Result<(BTreePath, usize, BTreePage)> {
+        let mut path = Vec::new();
+        let mut page_num = root_page;
+        let mut page = self.load_btree_page(page_num)?;
+
+        while page.page_type == INTERIOR_TABLE_PAGE {
+            let mut slot = page.cells.len();
+            for (i, interior_cell) in page.cells.iter().enumerate() {
+                if btree_page::table_cell_key(interior_cell, INTERIOR_TABLE_PAGE)? >= rowid {
+                    slot = i;
+                    break;
+                }
+            }
+
+            let child = match page.cells.get(slot) {
+                Some(interior_cell) => btree_page::left_child(interior_cell)?,
+                None => page.right_child.unwrap_or(0),
+            };
+            path.push((page_num, slot));
+            page_num = child as usize;
+            page = self.load_btree_page(page_num)?;
+        }
+
+        if page.page_type != LEAF_TABLE_PAGE {
+            bail!("Unsupported page type {} for table B-tree", page.page_type);
+        }
+
+        Ok((path, page_num, page))
+    }
+
+    /// Put every overflow page of a table leaf cell on the freelist
+    fn free_overflow_chain(&mut self, cell: &[u8]) -> Result<()> {
+        let (payload_size, _) = read_varint(cell, 0)?;
+        let payload_size = payload_size as usize;
+        let local_size = cell::local_payload_size(payload_size, self.get_usable_size(), PayloadKind::TableLeaf);
+        if local_size == payload_size {
+            return Ok(());
+        }
+
+        let mut next_page = btree_page::read_u32(cell, cell.len() - 4)? as usize;
+        while next_page != 0 {
+            let page_data = self.page_for_write(next_page)?;
+            let following = btree_page::read_u32(&page_data, 0)? as usize;
+            self.free_page(next_page)?;
+            next_page = following;
+        }
+        Ok(())
+    }
+
+    /// Write `page` back after cells were removed from it. Pages that drop
+    /// below a third full are merged with, or share cells with, a sibling;
+    /// parents are fixed up in turn and an emptied interior root absorbs
+    /// its only child so the tree gets shallower.
+    fn store_after_delete(&mut self, mut page_num: usize, mut page: BTreePage, mut path: BTreePath) -> Result<()> {
+        let usable_size = self.get_usable_size();
+        loop {
+            let header_offset = self.get_dbheader_offset(page_num);
+
+            let Some((parent_num, slot)) = path.pop() else {
+                if page.page_type == INTERIOR_TABLE_PAGE && page.cells.is_empty() {
+                    let child_num = page.right_child.unwrap_or(0) as usize;
+                    let child = self.load_btree_page(child_num)?;
+                    if child.fits(header_offset, usable_size) {
+                        self.store_btree_page(page_num, &child)?;
+                        return self.free_page(child_num);
+                    }
+                }
+                return self.store_btree_page(page_num, &page);
+            };
+
+            if !page.fits(header_offset, usable_size) {
+                // A sibling redistribution left the parent with an extra divider
+                path.push((parent_num, slot));
+                return self.store_balanced(page_num, page, path, false);
+            }
+
+            let underfull = page.used_space(header_offset) < usable_size / 3;
+            self.store_btree_page(page_num, &page)?;
+            if !underfull {
+                return Ok(());
+            }
+
+            let mut parent = self.load_btree_page(parent_num)?;
+            self.rebalance_siblings(&mut parent, slot)?;
+            page_num = parent_num;
+            page = parent;
+        }
+    }
+
+    /// Combine the child at `slot` of `parent` with its neighbour: merge them
+    /// into one page when the cells fit, otherwise spread the cells evenly
+    /// again. `parent` is updated but not stored.
+    fn rebalance_siblings(&mut self, parent: &mut BTreePage, slot: usize) -> Result<()> {
+        if parent.cells.is_empty() {
+            return Ok(());
+        }
+
+        let left_slot = slot.min(parent.cells.len() - 1);
+        let left_num = child_at(parent, left_slot)? as usize;
+        let right_num = child_at(parent, left_slot + 1)? as usize;
+        let left = self.load_btree_page(left_num)?;
+        let right = self.load_btree_page(right_num)?;
+
+        let mut combined = BTreePage {
+            page_type: left.page_type,
+            cells: left.cells,
+            right_child: right.right_child,
+        };
+        if combined.page_type == INTERIOR_TABLE_PAGE {
+            // The divider comes down between the two halves, pointing at the left rightmost child
+            let divider = btree_page::table_cell_key(&parent.cells[left_slot], INTERIOR_TABLE_PAGE)?;
+            let left_right_child = left.right_child.unwrap_or(0);
+            combined.cells.push(btree_page::interior_table_cell(left_right_child, divider));
+        }
+        combined.cells.extend(right.cells);
+
+        parent.cells.remove(left_slot);
+
+        if combined.fits(0, self.get_usable_size()) {
+            self.store_btree_page(left_num, &combined)?;
+            self.free_page(right_num)?;
+            set_child_at(parent, left_slot, left_num as u32);
+            return Ok(());
+        }
+
+        let (parts, dividers) = self.split_table_page(combined, false)?;
+        let mut part_pages = vec![left_num as u32, right_num as u32];
+        while part_pages.len() < parts.len() {
+            part_pages.push(self.allocate_page()? as u32);
+        }
+        for (part_page, part) in part_pages.iter().zip(&parts) {
+            self.store_btree_page(*part_page as usize, part)?;
+        }
+
+        set_child_at(parent, left_slot, part_pages[parts.len() - 1]);
+        for (part_page, divider) in part_pages.iter().zip(&dividers).rev() {
+            parent.cells.insert(left_slot, btree_page::interior_table_cell(*part_page, *divider));
+        }
+        Ok(())
+    }
+
+    /// Write `page` back, splitting it (and, in turn, its ancestors) when it
+    /// no longer fits. `path` holds the (page, child slot) pairs leading to it.
+    fn store_balanced(&mut self, mut page_num: usize, mut page: BTreePage, mut path: BTreePath, mut append: bool) -> Result<()> {
+        loop {
+            let header_offset = self.get_dbheader_offset(page_num);
+            if page.fits(header_offset, self.get_usable_size()) {
+                return self.store_btree_page(page_num, &page);
+            }
+
+            let (parts, dividers) = self.split_table_page(page, append)?;
+
+            let Some((parent_num, slot)) = path.pop() else {
+                // The root keeps its page number: its content moves to new
+                // pages and it becomes the interior page above them
+                let mut children = Vec::with_capacity(parts.len());
+                for part in &parts {
+                    let child = self.allocate_page()?;
+                    self.store_btree_page(child, part)?;
+                    children.push(child as u32);
+                }
+
+                let mut root = BTreePage::new(INTERIOR_TABLE_PAGE);
+                root.right_child = children.last().copied();
+                for (child, divider) in children.iter().zip(&dividers) {
+                    root.cells.push(btree_page::interior_table_cell(*child, *divider));
+                }
+                return self.store_btree_page(page_num, &root);
+            };
+
+            // The first part stays on this page, the others get new pages
+            let mut part_pages = vec![page_num as u32];
+            for _ in 1..parts.len() {
+                part_pages.push(self.allocate_page()? as u32);
+            }
+            for (part_page, part) in part_pages.iter().zip(&parts) {
+                self.store_btree_page(*part_page as usize, part)?;
+            }
+
+            let mut parent = self.load_btree_page(parent_num)?;
+            let last_page = *part_pages.last().unwrap_or(&(page_num as u32));
+            append = slot == parent.cells.len();
+            set_child_at(&mut parent, slot, last_page);
+            for (part_page, divider) in part_pages.iter().zip(&dividers).rev() {
+                parent.cells.insert(slot, btree_page::interior_table_cell(*part_page, *divider));
+            }
+
+            page_num = parent_num;
+            page = parent;
+        }
+    }
+
+    /// Divide an overfull table page into consecutive pages that each fit.
+    /// Returns the parts together with the key separating each part from the
+    /// next one (the largest rowid it contains).
+    fn split_table_page(&self, page: BTreePage, append: bool) -> Result<(Vec<BTreePage>, Vec<i64>)> {
+        let capacity = self.get_usable_size() - btree_page::btree_header_size(page.page_type)?;
+        let sizes: Vec<usize> = page.cells.iter().map(|c| c.len() + 2).collect();
+
+        let mut bounds = vec![0];
+        bounds.extend(partition_cells(&sizes, capacity, append));
+        bounds.push(page.cells.len());
+
+        let mut parts = Vec::new();
+        let mut dividers = Vec::new();
+        for window in bounds.windows(2) {
+            let mut cells = page.cells[window[0]..window[1]].to_vec();
+            let is_last = window[1] == page.cells.len();
+
+            if page.page_type == LEAF_TABLE_PAGE {
+                if !is_last {
+                    let last = cells.last().ok_or_else(|| anyhow::anyhow!("Empty page while splitting"))?;
+                    dividers.push(btree_page::table_cell_key(last, LEAF_TABLE_PAGE)?);
+                }
+                parts.push(BTreePage { page_type: LEAF_TABLE_PAGE, cells, right_child: None });
+            } else if is_last {
+                parts.push(BTreePage { page_type: INTERIOR_TABLE_PAGE, cells, right_child: page.right_child });
+            } else {
+                // The last cell moves up: its key becomes the divider and its
+                // child becomes this part's rightmost child
+                let promoted = cells.pop().ok_or_else(|| anyhow::anyhow!("Empty page while splitting"))?;
+                dividers.push(btree_page::table_cell_key(&promoted, INTERIOR_TABLE_PAGE)?);
+                let right_child = Some(btree_page::left_child(&promoted)?);
+                parts.push(BTreePage { page_type: INTERIOR_TABLE_PAGE, cells, right_child });
+            }
+        }
+
+        Ok((parts, dividers))
+    }
+}
+
+/// Page number of the child at `slot` of an interior page; the slot after
+/// the last cell is the rightmost child
+fn child_at(page: &BTreePage, slot: usize) -> Result<u32> {
+    match page.cells.get(slot) {
+        Some(cell) => btree_page::left_child(cell),
+        None => Ok(page.right_child.unwrap_or(0)),
+    }
+}
+
+fn set_child_at(page: &mut BTreePage, slot: usize, child: u32) {
+    match page.cells.get_mut(slot) {
+        Some(cell) => btree_page::set_left_child(cell, child),
+        None => page.right_child = Some(child),
+    }
+}
+
+/// Choose where to cut a run of cells (given their on-page sizes) so every
+/// part fits in `capacity`. Returns the index of the first cell of each part
+/// after the first. Appends move only the new last cell to a fresh page so
+/// sequentially inserted tables end up densely packed.
+fn partition_cells(sizes: &[usize], capacity: usize, append: bool) -> Vec<usize> {
+    let count = sizes.len();
+    if append && count >= 3 && sizes[..count - 1].iter().sum::<usize>() <= capacity {
+        return vec![count - 1];
+    }
+
+    let total: usize = sizes.iter().sum();
+    let target = total.div_ceil(total.div_ceil(capacity).max(2));
+
+    let mut starts = Vec::new();
+    let mut used = 0;
+    for (i, &size) in sizes.iter().enumerate() {
+        if used > 0 && (used + size > capacity || used >= target) {
+            starts.push(i);
+            used = 0;
+        }
+        used += size;
+    }
+
+    if starts.is_empty() && count >= 2 {
+        starts.push(count / 2);
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_support::{image_with_tables, SharedMemory};
+
+    const TABLE: (&str, &str) = ("t", "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT)");
+
+    fn insert(db: &mut Database, id: i64, name: &str) {
+        db.insert_row("t", None, vec![RecordValue::Int(id), RecordValue::Text(name.to_string())]).unwrap();
+    }
+
+    /// (rowid, name) of every row, in rowid order
+    fn rows(db: &mut Database) -> Vec<(u64, String)> {
+        let rows = db.get_table_rows("t").unwrap().rows;
+        rows.into_iter().map(|row| (row.row_id, row.values[1].to_display_string())).collect()
+    }
+
+    /// Levels of the B-tree rooted at `page_num`, following leftmost children
+    fn depth(db: &mut Database, mut page_num: usize) -> usize {
+        let mut depth = 1;
+        loop {
+            let page = db.load_btree_page(page_num).unwrap();
+            if page.page_type != INTERIOR_TABLE_PAGE {
+                return depth;
+            }
+            page_num = child_at(&page, 0).unwrap() as usize;
+            depth += 1;
+        }
+    }
+
+    /// Pages of the B-tree rooted at `page_num`
+    fn tree_pages(db: &mut Database, page_num: usize, pages: &mut Vec<usize>) {
+        pages.push(page_num);
+        let page = db.load_btree_page(page_num).unwrap();
+        if page.page_type == INTERIOR_TABLE_PAGE {
+            for slot in 0..=page.cells.len() {
+                tree_pages(db, child_at(&page, slot).unwrap() as usize, pages);
+            }
+        }
+    }
+
+    /// Trunk and leaf pages of the freelist, checking the trunk page layout
+    fn freelist_pages(db: &mut Database) -> Vec<usize> {
+        let max_leaves = db.get_usable_size() / 4 - 8;
+        let mut pages = Vec::new();
+        let mut trunk = db.header().unwrap().freelist_trunk as usize;
+        while trunk != 0 {
+            pages.push(trunk);
+            let data = db.read_page_data(trunk).unwrap();
+            let leaf_count = btree_page::read_u32(&data, 4).unwrap() as usize;
+            assert!(leaf_count <= max_leaves, "trunk page {} lists {} leaves", trunk, leaf_count);
+            for i in 0..leaf_count {
+                pages.push(btree_page::read_u32(&data, 8 + i * 4).unwrap() as usize);
+            }
+            trunk = btree_page::read_u32(&data, 0).unwrap() as usize;
+        }
+        pages
+    }
+
+    /// Check that every page but the schema page belongs to exactly one of
+    /// the table rooted at page 2 and the freelist, and return the freelist
+    fn check_pages(db: &mut Database) -> Vec<usize> {
+        let mut tree = Vec::new();
+        tree_pages(db, 2, &mut tree);
+        let free = freelist_pages(db);
+        let header = db.header().unwrap();
+        assert_eq!(header.freelist_count as usize, free.len());
+
+        let mut all: Vec<_> = tree.iter().chain(&free).copied().collect();
+        all.sort_unstable();
+        let expected: Vec<_> = (2..=header.page_count as usize).collect();
+        assert_eq!(all, expected, "pages used by the table or on the freelist");
+        free
+    }
+
+    fn name(id: i64) -> String {
+        format!("row {:05} {}", id, "x".repeat(id as usize % 40))
+    }
+
+    #[test]
+    fn inserts_split_leaf_and_interior_pages() {
+        let image = SharedMemory::new(image_with_tables(512, &[TABLE]));
+        let mut db = image.open().unwrap();
+        // Rowids in scattered order, so splits happen in the middle of pages
+        // as well as at the end
+        let count = 2000;
+        for i in 0..count {
+            let id = i * 7919 % count + 1;
+            insert(&mut db, id, &name(id));
+        }
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        assert!(depth(&mut db, 2) >= 3, "expected interior pages below the root");
+        let expected: Vec<_> = (1..=count).map(|id| (id as u64, name(id))).collect();
+        assert_eq!(rows(&mut db), expected);
+    }
+
+    #[test]
+    fn large_rows_spill_onto_overflow_pages() {
+        let image = SharedMemory::new(image_with_tables(512, &[TABLE]));
+        let mut db = image.open().unwrap();
+        let large: String = (0..5000).map(|i| char::from(b'a' + (i % 26) as u8)).collect();
+        insert(&mut db, 1, "small");
+        insert(&mut db, 2, &large);
+        insert(&mut db, 3, "also small");
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        let leaf = db.load_btree_page(2).unwrap();
+        assert!(leaf.cells[1].len() < 512, "expected the payload to use overflow pages");
+        assert_eq!(rows(&mut db), [(1, "small".to_string()), (2, large), (3, "also small".to_string())]);
+    }
+
+    #[test]
+    fn deletes_free_emptied_pages_and_merge_underfull_ones() {
+        let image = SharedMemory::new(image_with_tables(512, &[TABLE]));
+        let mut db = image.open().unwrap();
+        for id in 1..=600 {
+            insert(&mut db, id, &name(id));
+        }
+        db.commit().unwrap();
+        let mut pages_before = Vec::new();
+        tree_pages(&mut db, 2, &mut pages_before);
+
+        // Empty the leaves of the first half, thin out the second half
+        let kept: Vec<i64> = (301..=600).filter(|id| id % 3 == 0).collect();
+        for id in 1..=600 {
+            if !kept.contains(&id) {
+                assert!(db.delete_row("t", id).unwrap());
+            }
+        }
+        assert!(!db.delete_row("t", 1).unwrap());
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        let expected: Vec<_> = kept.iter().map(|&id| (id as u64, name(id))).collect();
+        assert_eq!(rows(&mut db), expected);
+        let free = check_pages(&mut db);
+        let mut tree = Vec::new();
+        tree_pages(&mut db, 2, &mut tree);
+        assert!(tree.len() * 2 < pages_before.len(), "{} pages left of {}", tree.len(), pages_before.len());
+        for &page_num in &tree[1..] {
+            let used = db.load_btree_page(page_num).unwrap().used_space(0);
+            assert!(used >= db.get_usable_size() / 3, "page {} is only {} bytes full", page_num, used);
+        }
+        assert_eq!(free.len() + tree.len(), pages_before.len());
+
+        // Freed pages are reused before the file grows
+        let page_count = db.page_count().unwrap();
+        for id in 1..=100 {
+            insert(&mut db, id, &name(id));
+        }
+        db.commit().unwrap();
+        let mut db = image.open().unwrap();
+        assert_eq!(db.page_count().unwrap(), page_count);
+        assert!(check_pages(&mut db).len() < free.len());
+    }
+
+    #[test]
+    fn deleting_every_row_leaves_an_empty_root_and_frees_the_rest() {
+        let image = SharedMemory::new(image_with_tables(512, &[TABLE]));
+        let mut db = image.open().unwrap();
+        for id in 1..=300 {
+            insert(&mut db, id, &name(id));
+        }
+        for id in (1..=300).rev() {
+            db.delete_row("t", id).unwrap();
+        }
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        assert!(rows(&mut db).is_empty());
+        assert_eq!(db.load_btree_page(2).unwrap().page_type, LEAF_TABLE_PAGE);
+        let page_count = db.page_count().unwrap();
+        assert_eq!(check_pages(&mut db).len(), page_count - 2);
+    }
+
+    #[test]
+    fn update_moves_a_growing_row_onto_overflow_pages_and_back() {
+        let image = SharedMemory::new(image_with_tables(512, &[TABLE]));
+        let mut db = image.open().unwrap();
+        for id in 1..=3 {
+            insert(&mut db, id, &name(id));
+        }
+        db.commit().unwrap();
+
+        let large = "y".repeat(3000);
+        let assignments = [("name".to_string(), RecordValue::Text(large.clone()))];
+        assert_eq!(db.update_row("t", 2, &assignments).unwrap(), Some(2));
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        assert_eq!(rows(&mut db), [(1, name(1)), (2, large), (3, name(3))]);
+        // Besides the schema and table pages, the rest hold the overflow chain
+        let mut tree = Vec::new();
+        tree_pages(&mut db, 2, &mut tree);
+        let page_count = db.page_count().unwrap();
+        assert!(page_count - 1 - tree.len() >= 3000 / 512);
+        assert!(freelist_pages(&mut db).is_empty());
+
+        let assignments = [("name".to_string(), RecordValue::Text("short".to_string()))];
+        db.update_row("t", 2, &assignments).unwrap();
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        assert_eq!(rows(&mut db), [(1, name(1)), (2, "short".to_string()), (3, name(3))]);
+        // Every overflow page went back on the freelist
+        assert_eq!(check_pages(&mut db).len(), page_count - 2);
+    }
+}
@@ -1,7 +1,15 @@
 use anyhow::Result;
-use super::varint::read_varint;
+use super::header::TextEncoding;
 use super::record::Record;
 
+/// Which kind of B-tree cell a payload belongs to; the two kinds use
+/// different thresholds when deciding how much of the payload stays local
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    TableLeaf,
+    Index,
+}
+
 #[derive(Debug)]
 pub struct Cell {
     pub record_size: u64,
@@ -10,15 +18,11 @@ pub struct Cell {
 }
 
 impl Cell {
-    pub fn from_bytes(data: &[u8], offset: usize) -> Result<Self> {
-        let mut pos = offset;
-        let (record_size, bytes_read) = read_varint(data, pos)?;
-        pos += bytes_read;
-        let (row_id, bytes_read) = read_varint(data, pos)?;
-        pos += bytes_read;
-
-        let record_data = &data[pos..pos + record_size as usize];
-        let record = Record::from_bytes(record_data)?;
+    /// Build a cell from its header fields and the fully assembled payload
+    /// (local bytes followed by any overflow pages), decoding TEXT in the
+    /// database's text encoding
+    pub fn from_payload(record_size: u64, row_id: u64, payload: &[u8], encoding: TextEncoding) -> Result<Self> {
+        let record = Record::from_bytes(payload, encoding)?;
 
         Ok(Cell {
             record_size,
@@ -26,4 +30,28 @@ impl Cell {
             record,
         })
     }
-} 
\ No newline at end of file
+}
+
+/// Number of payload bytes stored on the B-tree page itself, following the
+/// spill rules of the SQLite file format. Anything beyond this lives on
+/// overflow pages, and the local bytes are followed by a 4-byte pointer to
+/// the first of them.
+pub fn local_payload_size(payload_size: usize, usable_size: usize, kind: PayloadKind) -> usize {
+    let max_local = match kind {
+        PayloadKind::TableLeaf => usable_size - 35,
+        PayloadKind::Index => ((usable_size - 12) * 64 / 255) - 23,
+    };
+
+    if payload_size <= max_local {
+        return payload_size;
+    }
+
+    let min_local = ((usable_size - 12) * 32 / 255) - 23;
+    let surplus = min_local + (payload_size - min_local) % (usable_size - 4);
+
+    if surplus <= max_local {
+        surplus
+    } else {
+        min_local
+    }
+}
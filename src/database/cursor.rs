@@ -0,0 +1,662 @@
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+
+use super::affinity::{compare_collated, normalize};
+use super::btree_page;
+use super::database::{Database, IndexCell, TableRow, INTERIOR_INDEX_PAGE, INTERIOR_TABLE_PAGE, LEAF_INDEX_PAGE, LEAF_TABLE_PAGE};
+use super::header::TextEncoding;
+use super::page_source::Page;
+use super::record::RecordValue;
+use super::schema::{ColumnInfo, IndexSchema};
+
+/// A page on the path from the root of the B-tree to the cursor's row
+struct Frame {
+    page: Page,
+    /// Offset of the B-tree page header, past the database header on page 1
+    header_offset: usize,
+    page_type: u8,
+    cell_offsets: Vec<usize>,
+    /// The current cell of a leaf page, or the child slot taken in an
+    /// interior page, where slot `cell_offsets.len()` is the right-most child
+    index: usize,
+}
+
+impl Frame {
+    /// Read a page of a B-tree whose pages have the given leaf and interior types
+    fn load(db: &mut Database, page_num: usize, leaf_type: u8, interior_type: u8) -> Result<Frame> {
+        let page = db.read_page_data(page_num)?;
+        let header_offset = db.get_dbheader_offset(page_num);
+        let page_type = match page.get(header_offset) {
+            Some(&page_type) if page_type == leaf_type || page_type == interior_type => page_type,
+            page_type => bail!("Unexpected page type {:?} on page {}", page_type, page_num),
+        };
+        let cell_offsets = db.get_cell_offsets(&page, page_num)?;
+        Ok(Frame { page, header_offset, page_type, cell_offsets, index: 0 })
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.page_type == LEAF_TABLE_PAGE || self.page_type == LEAF_INDEX_PAGE
+    }
+
+    /// The child page an interior frame's slot points to
+    fn child_page(&self, db: &Database) -> Result<usize> {
+        match self.cell_offsets.get(self.index) {
+            Some(&offset) => db.read_page_number_from_cell(&self.page, offset),
+            None => db.read_rightmost_page(&self.page, self.header_offset),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    BeforeFirst,
+    AtRow,
+    AfterLast,
+}
+
+/// A cursor over the rows of a rowid table, in rowid order. It holds only
+/// the pages from the root down to the current row, so a scan of any size
+/// of table runs in constant memory.
+///
+/// A new cursor sits before the first row; iterating it walks the table
+/// forwards, and `prev` walks it backwards. The positioning methods are
+/// `seek_first` and `seek_last` rather than `first` and `last` so that they
+/// are not mistaken for [`Iterator::last`], which consumes the cursor.
+pub struct TableCursor<'a> {
+    db: &'a mut Database,
+    root_page: usize,
+    columns: Vec<ColumnInfo>,
+    stack: Vec<Frame>,
+    position: Position,
+}
+
+impl Database {
+    /// Open a cursor on a rowid table
+    pub fn table_cursor(&mut self, table_name: &str) -> Result<TableCursor<'_>> {
+        let table_info = self.find_table_info(table_name)?;
+        let schema = self.get_table_schema(table_name)?;
+        if schema.without_rowid {
+            bail!("Table {} is a WITHOUT ROWID table and has no rowid order", table_name);
+        }
+        Ok(TableCursor {
+            db: self,
+            root_page: table_info.rootpage,
+            columns: schema.columns,
+            stack: Vec::new(),
+            position: Position::BeforeFirst,
+        })
+    }
+}
+
+impl TableCursor<'_> {
+    /// Move to the first row and return it, or `None` if the table is empty
+    pub fn seek_first(&mut self) -> Result<Option<TableRow>> {
+        self.stack.clear();
+        self.descend(self.root_page, false)?;
+        self.settle(true)
+    }
+
+    /// Move to the last row and return it, or `None` if the table is empty
+    pub fn seek_last(&mut self) -> Result<Option<TableRow>> {
+        self.stack.clear();
+        self.descend(self.root_page, true)?;
+        self.settle(false)
+    }
+
+    /// Move to the row with the smallest rowid at or after `rowid` and
+    /// return it, or `None` if every rowid is smaller
+    pub fn seek(&mut self, rowid: i64) -> Result<Option<TableRow>> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+        loop {
+            let mut frame = Frame::load(self.db, page_num, LEAF_TABLE_PAGE, INTERIOR_TABLE_PAGE)?;
+            // The first cell whose key is at or after `rowid`; in an interior
+            // page the left child of that cell holds every smaller key
+            let mut index = frame.cell_offsets.len();
+            for (i, &offset) in frame.cell_offsets.iter().enumerate() {
+                if btree_page::table_cell_key(&frame.page[offset..], frame.page_type)? >= rowid {
+                    index = i;
+                    break;
+                }
+            }
+            frame.index = index;
+
+            if frame.is_leaf() {
+                let past_end = index == frame.cell_offsets.len();
+                self.stack.push(frame);
+                if !past_end {
+                    self.position = Position::AtRow;
+                    return self.current_row().map(Some);
+                }
+                // Every key here is smaller, so the row is the first one after this leaf
+                return if self.step(true)? {
+                    self.position = Position::AtRow;
+                    self.current_row().map(Some)
+                } else {
+                    self.position = Position::AfterLast;
+                    Ok(None)
+                };
+            }
+
+            page_num = frame.child_page(self.db)?;
+            self.stack.push(frame);
+        }
+    }
+
+    /// Move to the previous row and return it. Before the first row there
+    /// is none; after the last row this is the last row.
+    pub fn prev(&mut self) -> Option<Result<TableRow>> {
+        let row = match self.position {
+            Position::BeforeFirst => return None,
+            Position::AfterLast => self.seek_last(),
+            Position::AtRow => self.advance(false),
+        };
+        self.finish_move(row, Position::BeforeFirst)
+    }
+
+    /// The row under the cursor, if it is on one
+    pub fn current(&mut self) -> Result<Option<TableRow>> {
+        match self.position {
+            Position::AtRow => self.current_row().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Step from the current row to its neighbour
+    fn advance(&mut self, forward: bool) -> Result<Option<TableRow>> {
+        if self.step(forward)? {
+            self.current_row().map(Some)
+        } else {
+            self.position = if forward { Position::AfterLast } else { Position::BeforeFirst };
+            Ok(None)
+        }
+    }
+
+    /// Turn the result of a move into an iterator item. A failed move leaves
+    /// the cursor at `end` so that iteration stops.
+    fn finish_move(&mut self, row: Result<Option<TableRow>>, end: Position) -> Option<Result<TableRow>> {
+        if row.is_err() {
+            self.position = end;
+        }
+        row.transpose()
+    }
+
+    /// After descending to the first or last leaf, make sure the cursor is on
+    /// a row: only an empty table's root leaf has no cells
+    fn settle(&mut self, forward: bool) -> Result<Option<TableRow>> {
+        let on_row = match self.stack.last() {
+            Some(leaf) if !leaf.cell_offsets.is_empty() => true,
+            _ => self.step(forward)?,
+        };
+        if on_row {
+            self.position = Position::AtRow;
+            self.current_row().map(Some)
+        } else {
+            self.position = if forward { Position::AfterLast } else { Position::BeforeFirst };
+            Ok(None)
+        }
+    }
+
+    /// Move the stack to the next or previous row, returning false (with an
+    /// empty stack) if there is none
+    fn step(&mut self, forward: bool) -> Result<bool> {
+        if let Some(leaf) = self.stack.last_mut() {
+            if forward && leaf.index + 1 < leaf.cell_offsets.len() {
+                leaf.index += 1;
+                return Ok(true);
+            }
+            if !forward && leaf.index > 0 && leaf.index <= leaf.cell_offsets.len() {
+                leaf.index -= 1;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+
+        // Climb until an interior page has another child on that side, then
+        // take its nearest leaf
+        while let Some(frame) = self.stack.last_mut() {
+            let moved = if forward && frame.index < frame.cell_offsets.len() {
+                frame.index += 1;
+                true
+            } else if !forward && frame.index > 0 {
+                frame.index -= 1;
+                true
+            } else {
+                false
+            };
+            if !moved {
+                self.stack.pop();
+                continue;
+            }
+
+            let child = self.stack[self.stack.len() - 1].child_page(self.db)?;
+            self.descend(child, !forward)?;
+            match self.stack.last() {
+                Some(leaf) if !leaf.cell_offsets.is_empty() => return Ok(true),
+                _ => {
+                    self.stack.pop();
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Push the path from `page_num` down to its first or last leaf
+    fn descend(&mut self, mut page_num: usize, to_last: bool) -> Result<()> {
+        loop {
+            let mut frame = Frame::load(self.db, page_num, LEAF_TABLE_PAGE, INTERIOR_TABLE_PAGE)?;
+            if frame.is_leaf() {
+                frame.index = if to_last { frame.cell_offsets.len().saturating_sub(1) } else { 0 };
+                self.stack.push(frame);
+                return Ok(());
+            }
+            frame.index = if to_last { frame.cell_offsets.len() } else { 0 };
+            page_num = frame.child_page(self.db)?;
+            self.stack.push(frame);
+        }
+    }
+
+    fn current_row(&mut self) -> Result<TableRow> {
+        let Some(leaf) = self.stack.last() else {
+            bail!("Cursor is not on a row");
+        };
+        let cell = self.db.read_table_cell(&leaf.page, leaf.cell_offsets[leaf.index])?;
+        Ok(self.db.create_table_row(cell, &self.columns))
+    }
+}
+
+impl Iterator for TableCursor<'_> {
+    type Item = Result<TableRow>;
+
+    /// Move to the next row and return it. Before the first row this is
+    /// the first row; after the last row there is none.
+    fn next(&mut self) -> Option<Result<TableRow>> {
+        let row = match self.position {
+            Position::BeforeFirst => self.seek_first(),
+            Position::AfterLast => return None,
+            Position::AtRow => self.advance(true),
+        };
+        self.finish_move(row, Position::AfterLast)
+    }
+}
+
+/// How one key column of an index orders its values
+#[derive(Debug, Clone)]
+struct KeyColumn {
+    collation: Option<String>,
+    descending: bool,
+}
+
+/// A cursor over the entries of an index, or of the B-tree of a WITHOUT
+/// ROWID table, in key order. Keys are compared by SQLite's rules with each
+/// column's collation and direction, so a key given to `seek_ge`, `seek_gt`
+/// or `set_range` may be any prefix of the index's columns.
+///
+/// The cursor can be confined to a range of keys with `set_range`; iterating
+/// then starts at the lower bound and stops at the upper one. A bound that is
+/// a prefix covers every entry starting with it, so the range from
+/// `Included([x])` to `Included([x])` is every entry whose first column is `x`.
+pub struct IndexCursor<'a> {
+    db: &'a mut Database,
+    root_page: usize,
+    key_columns: Vec<KeyColumn>,
+    /// Whether entries end with a rowid, as in every index except the
+    /// B-tree of a WITHOUT ROWID table
+    has_rowid: bool,
+    /// How the database stores text, which BINARY compares the bytes of
+    encoding: TextEncoding,
+    /// Pages from the root to the current entry. The top frame's index is
+    /// the current cell, which in an interior page is an entry too; the
+    /// frames below it hold the child slot taken.
+    stack: Vec<Frame>,
+    position: Position,
+    lower: Bound<Vec<RecordValue>>,
+    upper: Bound<Vec<RecordValue>>,
+}
+
+impl Database {
+    /// Open a cursor on an index. On a WITHOUT ROWID table, each entry's key
+    /// ends with the primary key columns that locate its row.
+    pub fn index_cursor(&mut self, index_name: &str) -> Result<IndexCursor<'_>> {
+        let index_info = self.find_index_info(index_name)?;
+        let Some(sql) = &index_info.sql else {
+            bail!("Index {} has no CREATE INDEX statement", index_name);
+        };
+        let index = IndexSchema::from_create_sql(sql)?;
+        let table = self.get_table_schema(&index.table)?;
+
+        // An indexed column without its own COLLATE uses the table column's
+        let mut key_columns = Vec::with_capacity(index.columns.len());
+        for indexed in &table.index_key_columns(&index) {
+            let Some(column) = table.columns.iter().find(|c| c.name.eq_ignore_ascii_case(&indexed.name)) else {
+                bail!("Index {} refers to unknown column {}", index_name, indexed.name);
+            };
+            key_columns.push(KeyColumn {
+                collation: indexed.collation.clone().or_else(|| column.collation.clone()),
+                descending: indexed.descending,
+            });
+        }
+
+        Ok(IndexCursor::new(self, index_info.rootpage, key_columns, !table.without_rowid))
+    }
+
+    /// Open a cursor on the B-tree of a WITHOUT ROWID table, keyed by its
+    /// primary key. Each entry's key holds every column, primary key first.
+    pub fn primary_key_cursor(&mut self, table_name: &str) -> Result<IndexCursor<'_>> {
+        let table_info = self.find_table_info(table_name)?;
+        let schema = self.get_table_schema(table_name)?;
+        if !schema.without_rowid {
+            bail!("Table {} is not a WITHOUT ROWID table", table_name);
+        }
+
+        let key_columns = schema.primary_key.iter()
+            .map(|key| KeyColumn {
+                collation: key.collation.clone().or_else(|| {
+                    schema.columns.iter()
+                        .find(|c| c.name.eq_ignore_ascii_case(&key.name))
+                        .and_then(|c| c.collation.clone())
+                }),
+                descending: key.descending,
+            })
+            .collect();
+
+        Ok(IndexCursor::new(self, table_info.rootpage, key_columns, false))
+    }
+}
+
+impl<'a> IndexCursor<'a> {
+    fn new(db: &'a mut Database, root_page: usize, key_columns: Vec<KeyColumn>, has_rowid: bool) -> Self {
+        let encoding = db.text_encoding();
+        IndexCursor {
+            db,
+            root_page,
+            key_columns,
+            has_rowid,
+            encoding,
+            stack: Vec::new(),
+            position: Position::BeforeFirst,
+            lower: Bound::Unbounded,
+            upper: Bound::Unbounded,
+        }
+    }
+}
+
+impl IndexCursor<'_> {
+    /// Confine the cursor to the entries between `lower` and `upper`, and
+    /// move it before the first of them
+    pub fn set_range(&mut self, lower: Bound<Vec<RecordValue>>, upper: Bound<Vec<RecordValue>>) {
+        self.lower = lower;
+        self.upper = upper;
+        self.stack.clear();
+        self.position = Position::BeforeFirst;
+    }
+
+    /// Whether the entries end with a rowid, which is then left out of
+    /// their keys
+    pub fn has_rowid(&self) -> bool {
+        self.has_rowid
+    }
+
+    /// Move to the first entry in range and return it
+    pub fn seek_first(&mut self) -> Result<Option<IndexCell>> {
+        let found = match self.lower.clone() {
+            Bound::Included(key) => self.seek_key(&key, false)?,
+            Bound::Excluded(key) => self.seek_key(&key, true)?,
+            Bound::Unbounded => self.descend_from_root(false)?,
+        };
+        self.land(found, true)
+    }
+
+    /// Move to the last entry in range and return it
+    pub fn seek_last(&mut self) -> Result<Option<IndexCell>> {
+        // Find the first entry past the upper bound, then step back from it
+        let past_upper = match self.upper.clone() {
+            Bound::Included(key) => self.seek_key(&key, true)?,
+            Bound::Excluded(key) => self.seek_key(&key, false)?,
+            Bound::Unbounded => false,
+        };
+        let found = if past_upper { self.step(false)? } else { self.descend_from_root(true)? };
+        self.land(found, false)
+    }
+
+    /// Move to the first entry at or after `key` and return it, or `None`
+    /// if there is none before the upper bound
+    pub fn seek_ge(&mut self, key: &[RecordValue]) -> Result<Option<IndexCell>> {
+        let found = self.seek_key(key, false)?;
+        self.land(found, true)
+    }
+
+    /// Move to the first entry after every entry starting with `key` and
+    /// return it, or `None` if there is none before the upper bound
+    pub fn seek_gt(&mut self, key: &[RecordValue]) -> Result<Option<IndexCell>> {
+        let found = self.seek_key(key, true)?;
+        self.land(found, true)
+    }
+
+    /// Move to the previous entry in range and return it. Before the first
+    /// entry there is none; after the last entry this is the last entry.
+    pub fn prev(&mut self) -> Option<Result<IndexCell>> {
+        let entry = match self.position {
+            Position::BeforeFirst => return None,
+            Position::AfterLast => self.seek_last(),
+            Position::AtRow => self.step(false).and_then(|found| self.land(found, false)),
+        };
+        if entry.is_err() {
+            self.position = Position::BeforeFirst;
+        }
+        entry.transpose()
+    }
+
+    /// The entry under the cursor, if it is on one
+    pub fn current(&mut self) -> Result<Option<IndexCell>> {
+        match self.position {
+            Position::AtRow => self.current_entry().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Settle the cursor after a move: on the entry the stack points to if
+    /// the move found one inside the range, otherwise off the end it moved
+    /// towards
+    fn land(&mut self, found: bool, forward: bool) -> Result<Option<IndexCell>> {
+        if found {
+            let entry = self.current_entry()?;
+            let in_range = if forward {
+                self.below_upper(&entry.key)
+            } else {
+                self.above_lower(&entry.key)
+            };
+            if in_range {
+                self.position = Position::AtRow;
+                return Ok(Some(entry));
+            }
+        }
+        self.stack.clear();
+        self.position = if forward { Position::AfterLast } else { Position::BeforeFirst };
+        Ok(None)
+    }
+
+    fn below_upper(&self, key: &[RecordValue]) -> bool {
+        match &self.upper {
+            Bound::Included(bound) => compare_key(&self.key_columns, self.encoding, key, bound) != Ordering::Greater,
+            Bound::Excluded(bound) => compare_key(&self.key_columns, self.encoding, key, bound) == Ordering::Less,
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn above_lower(&self, key: &[RecordValue]) -> bool {
+        match &self.lower {
+            Bound::Included(bound) => compare_key(&self.key_columns, self.encoding, key, bound) != Ordering::Less,
+            Bound::Excluded(bound) => compare_key(&self.key_columns, self.encoding, key, bound) == Ordering::Greater,
+            Bound::Unbounded => true,
+        }
+    }
+
+    /// Point the stack at the first entry after `key`, or at or after it
+    /// when not `strict`, returning false if there is no such entry
+    fn seek_key(&mut self, key: &[RecordValue], strict: bool) -> Result<bool> {
+        self.stack.clear();
+        let mut page_num = self.root_page;
+        loop {
+            let mut frame = Frame::load(self.db, page_num, LEAF_INDEX_PAGE, INTERIOR_INDEX_PAGE)?;
+            let is_leaf = frame.is_leaf();
+            // The first cell sorting after the key; in an interior page its
+            // left child may hold entries that do as well
+            let mut index = frame.cell_offsets.len();
+            for (i, &offset) in frame.cell_offsets.iter().enumerate() {
+                let record_offset = if is_leaf { offset } else { offset + 4 };
+                let fields = self.db.read_index_record(&frame.page, record_offset)?.body;
+                let ordering = compare_key(&self.key_columns, self.encoding, &fields, key);
+                if ordering == Ordering::Greater || (!strict && ordering == Ordering::Equal) {
+                    index = i;
+                    break;
+                }
+            }
+            frame.index = index;
+
+            if is_leaf {
+                let past_end = index == frame.cell_offsets.len();
+                self.stack.push(frame);
+                // Otherwise the entry is the first one after this leaf
+                return if past_end { self.climb(true) } else { Ok(true) };
+            }
+            page_num = frame.child_page(self.db)?;
+            self.stack.push(frame);
+        }
+    }
+
+    /// Point the stack at the first or last entry of the whole index
+    fn descend_from_root(&mut self, to_last: bool) -> Result<bool> {
+        self.stack.clear();
+        self.descend(self.root_page, to_last)?;
+        match self.stack.last() {
+            Some(leaf) if !leaf.cell_offsets.is_empty() => Ok(true),
+            _ => self.climb(!to_last),
+        }
+    }
+
+    /// Move the stack to the next or previous entry, returning false (with
+    /// an empty stack) if there is none
+    fn step(&mut self, forward: bool) -> Result<bool> {
+        let Some(top) = self.stack.last_mut() else {
+            return Ok(false);
+        };
+        if top.is_leaf() {
+            if forward && top.index + 1 < top.cell_offsets.len() {
+                top.index += 1;
+                return Ok(true);
+            }
+            if !forward && top.index > 0 {
+                top.index -= 1;
+                return Ok(true);
+            }
+            return self.climb(forward);
+        }
+
+        // Next to an interior entry are the nearest leaf entries of the
+        // children on either side of it
+        if forward {
+            top.index += 1;
+        }
+        let child = self.stack[self.stack.len() - 1].child_page(self.db)?;
+        self.descend(child, !forward)?;
+        match self.stack.last() {
+            Some(leaf) if !leaf.cell_offsets.is_empty() => Ok(true),
+            _ => self.climb(forward),
+        }
+    }
+
+    /// Leave the top page, which has no more entries on that side, for the
+    /// interior entry next to it
+    fn climb(&mut self, forward: bool) -> Result<bool> {
+        self.stack.pop();
+        while let Some(frame) = self.stack.last_mut() {
+            if forward && frame.index < frame.cell_offsets.len() {
+                // The cell whose left child was just left
+                return Ok(true);
+            }
+            if !forward && frame.index > 0 {
+                frame.index -= 1;
+                return Ok(true);
+            }
+            self.stack.pop();
+        }
+        Ok(false)
+    }
+
+    /// Push the path from `page_num` down to its first or last leaf entry
+    fn descend(&mut self, mut page_num: usize, to_last: bool) -> Result<()> {
+        loop {
+            let mut frame = Frame::load(self.db, page_num, LEAF_INDEX_PAGE, INTERIOR_INDEX_PAGE)?;
+            if frame.is_leaf() {
+                frame.index = if to_last { frame.cell_offsets.len().saturating_sub(1) } else { 0 };
+                self.stack.push(frame);
+                return Ok(());
+            }
+            frame.index = if to_last { frame.cell_offsets.len() } else { 0 };
+            page_num = frame.child_page(self.db)?;
+            self.stack.push(frame);
+        }
+    }
+
+    fn current_entry(&mut self) -> Result<IndexCell> {
+        let Some(frame) = self.stack.last() else {
+            bail!("Cursor is not on an entry");
+        };
+        let offset = frame.cell_offsets[frame.index];
+        let record_offset = if frame.is_leaf() { offset } else { offset + 4 };
+        let mut key: Vec<RecordValue> = self.db.read_index_record(&frame.page, record_offset)?
+            .body
+            .iter()
+            .map(normalize)
+            .collect();
+
+        if !self.has_rowid {
+            return Ok(IndexCell { key, row_id: 0 });
+        }
+        match key.pop() {
+            Some(RecordValue::Int(row_id)) => Ok(IndexCell { key, row_id: row_id as u64 }),
+            _ => bail!("Index entry is missing its rowid"),
+        }
+    }
+}
+
+impl Iterator for IndexCursor<'_> {
+    type Item = Result<IndexCell>;
+
+    /// Move to the next entry in range and return it. Before the first
+    /// entry this is the first entry; after the last entry there is none.
+    fn next(&mut self) -> Option<Result<IndexCell>> {
+        let entry = match self.position {
+            Position::BeforeFirst => self.seek_first(),
+            Position::AfterLast => return None,
+            Position::AtRow => self.step(true).and_then(|found| self.land(found, true)),
+        };
+        if entry.is_err() {
+            self.position = Position::AfterLast;
+        }
+        entry.transpose()
+    }
+}
+
+/// Compare an index entry with a key that may be a prefix of it. Fields are
+/// compared with their column's collation, reversed for DESC columns; fields
+/// past the key columns, like the rowid, compare in BINARY ascending order.
+/// BINARY compares text as `encoding` stores it.
+fn compare_key(columns: &[KeyColumn], encoding: TextEncoding, fields: &[RecordValue], key: &[RecordValue]) -> Ordering {
+    for (i, (field, wanted)) in fields.iter().zip(key).enumerate() {
+        let ordering = match columns.get(i) {
+            Some(column) => {
+                let ordering = compare_collated(field, wanted, column.collation.as_deref(), encoding);
+                if column.descending { ordering.reverse() } else { ordering }
+            }
+            None => compare_collated(field, wanted, None, encoding),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
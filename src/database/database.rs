@@ -1,21 +1,33 @@
 use anyhow::{bail, Result};
-use std::fs::File;
-use std::io::prelude::*;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::ops::Bound;
 
 use crate::{DB_HEADER_SIZE, BTREE_HEADER_SIZE};
-use super::cell::Cell;
-use super::schema::{TableSchema, ColumnInfo};
-use super::record::RecordValue;
+use super::btree_page::{self, BTreePage};
+use super::cell::{self, Cell, PayloadKind};
+use super::header::{DatabaseHeader, TextEncoding};
+use super::journal;
+use super::page_cache::{CacheStats, PageCache, DEFAULT_PAGE_CACHE_CAPACITY};
+use super::page_source::{FileSource, MmapSource, Page, PageSource};
+use super::schema::{TableSchema, ColumnInfo, IndexSchema};
+use super::record::{Record, RecordValue};
+use super::transaction::Transaction;
+use super::wal::WalSource;
 
 // B-tree page types
-const INTERIOR_INDEX_PAGE: u8 = 2;
-const INTERIOR_TABLE_PAGE: u8 = 5;
-const LEAF_INDEX_PAGE: u8 = 10;
-const LEAF_TABLE_PAGE: u8 = 13;
+pub(crate) const INTERIOR_INDEX_PAGE: u8 = 2;
+pub(crate) const INTERIOR_TABLE_PAGE: u8 = 5;
+pub(crate) const LEAF_INDEX_PAGE: u8 = 10;
+pub(crate) const LEAF_TABLE_PAGE: u8 = 13;
 
 #[derive(Debug, Clone)]
 pub struct IndexCell {
-    pub key: String,
+    /// The indexed values, one per index column, with the constant serial
+    /// types folded into integers. For a WITHOUT ROWID table this is every
+    /// column of the row, primary key first.
+    pub key: Vec<RecordValue>,
+    /// Rowid of the indexed row; 0 for a WITHOUT ROWID table
     pub row_id: u64,
 }
 
@@ -32,7 +44,7 @@ impl SchemaObject {
     pub fn from_record(record: &super::record::Record) -> Option<Self> {
         // Schema records have: type, name, tbl_name, rootpage, sql
         if record.body.len() >= 4 {
-            let object_type = match record.body.get(0) {
+            let object_type = match record.body.first() {
                 Some(RecordValue::Text(t)) => t.clone(),
                 _ => return None,
             };
@@ -80,33 +92,120 @@ pub struct TableRow {
 pub struct TableRows {
     pub columns: Vec<ColumnInfo>,
     pub rows: Vec<TableRow>,
+    /// Rows of a WITHOUT ROWID table have no rowid; their `row_id` is 0
+    pub without_rowid: bool,
 }
 
+/// Root page of the sqlite_schema table
+const SCHEMA_ROOT_PAGE: usize = 1;
+
+// Database header fields maintained by the write path
+const HEADER_CHANGE_COUNTER: usize = 24;
+const HEADER_PAGE_COUNT: usize = 28;
+const HEADER_FREELIST_TRUNK: usize = 32;
+const HEADER_FREELIST_COUNT: usize = 36;
+const HEADER_VERSION_VALID_FOR: usize = 92;
+
 pub struct Database {
-    file: File,
+    source: Box<dyn PageSource>,
     page_size: usize,
+    usable_size: usize, // page size minus the reserved bytes at the end of each page
+    text_encoding: TextEncoding,
+    schema: Option<Vec<SchemaObject>>,
+    cache: PageCache,
+    dirty: BTreeMap<usize, Page>, // modified pages not yet written back
+    path: Option<String>,         // database file, for its rollback journal
+    in_transaction: bool,         // an explicit BEGIN is open
 }
 
 impl Database {
     pub fn new(path: &str) -> Result<Self> {
-        let mut file = File::open(path)?;
-        let page_size = Self::read_page_size(&mut file)?;
-        Ok(Self { file, page_size })
+        Self::with_cache_capacity(path, DEFAULT_PAGE_CACHE_CAPACITY)
+    }
+
+    /// Open a database keeping up to `capacity` pages in the page cache (0 disables it)
+    pub fn with_cache_capacity(path: &str, capacity: usize) -> Result<Self> {
+        journal::recover_hot_journal(path)?;
+        let mut db = Self::from_file_source(path, FileSource::open(path)?)?;
+        db.set_cache_capacity(capacity);
+        Ok(db)
+    }
+
+    /// Open a database by memory-mapping the file; pages are served as
+    /// zero-copy slices of the mapping. See `MmapSource::open` for the
+    /// requirements on the underlying file.
+    pub fn open_mmap(path: &str) -> Result<Self> {
+        journal::recover_hot_journal(path)?;
+        Self::from_file_source(path, MmapSource::open(path)?)
+    }
+
+    /// Open a database for reading and writing. Changes stay in memory
+    /// until `commit` is called.
+    pub fn open_rw(path: &str) -> Result<Self> {
+        journal::recover_hot_journal(path)?;
+        Self::from_file_source(path, FileSource::open_rw(path)?)
+    }
+
+    /// Wrap the source of a database file so the pages committed to its
+    /// `-wal` file are seen if it is in WAL mode
+    fn from_file_source(path: &str, mut source: impl PageSource + 'static) -> Result<Self> {
+        let mut db = if DatabaseHeader::read_from(&mut source)?.is_wal_mode() {
+            Self::from_source(WalSource::open(path, source)?)?
+        } else {
+            Self::from_source(source)?
+        };
+        db.path = Some(path.to_string());
+        Ok(db)
     }
 
-    fn read_page_size(file: &mut File) -> Result<usize> {
-        file.seek(std::io::SeekFrom::Start(0))?;
-        let mut header = [0; DB_HEADER_SIZE];
-        file.read_exact(&mut header)?;
+    /// Open a database on top of any page source
+    pub fn from_source(source: impl PageSource + 'static) -> Result<Self> {
+        let mut source: Box<dyn PageSource> = Box::new(source);
+        let header = DatabaseHeader::read_from(source.as_mut())?;
+        Ok(Self {
+            source,
+            page_size: header.page_size,
+            usable_size: header.usable_size(),
+            text_encoding: header.text_encoding,
+            schema: None,
+            cache: PageCache::new(DEFAULT_PAGE_CACHE_CAPACITY),
+            dirty: BTreeMap::new(),
+            path: None,
+            in_transaction: false,
+        })
+    }
 
-        let page_size = u16::from_be_bytes([header[16], header[17]]) as usize;
-        Ok(page_size)
+    /// The database header as of the pending changes
+    pub fn header(&mut self) -> Result<DatabaseHeader> {
+        let page_data = self.read_page_data(1)?;
+        DatabaseHeader::parse(&page_data)
     }
 
     pub fn get_page_size(&self) -> usize {
         self.page_size
     }
 
+    /// Encoding of every TEXT value in the database, fixed when it was created
+    pub fn text_encoding(&self) -> TextEncoding {
+        self.text_encoding
+    }
+
+    /// Bytes of each page that hold B-tree content; extensions such as
+    /// checksum VFSes keep their own data in the reserved bytes after them
+    pub fn get_usable_size(&self) -> usize {
+        self.usable_size
+    }
+
+    /// Resize the page cache, evicting pages if it shrank
+    pub fn set_cache_capacity(&mut self, capacity: usize) {
+        self.cache.set_capacity(capacity);
+    }
+
+    /// Page cache hit/miss counters since the database was opened
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
     pub fn read_page(&mut self, page_number: usize) -> Result<Vec<Cell>> {
         let page_data = self.read_page_data(page_number)?;
         
@@ -114,25 +213,94 @@ impl Database {
         let mut cells = Vec::new();
 
         for cell_offset in cell_offsets {
-            let cell = Cell::from_bytes(&page_data, cell_offset)?;
+            let cell = self.read_table_cell(&page_data, cell_offset)?;
             cells.push(cell);
         }
         Ok(cells)
     }
 
-    fn read_page_data(&mut self, page_number: usize) -> Result<Vec<u8>> {
+    /// Parse a table leaf cell, following its overflow chain if the record spilled
+    pub(crate) fn read_table_cell(&mut self, page_data: &[u8], offset: usize) -> Result<Cell> {
+        let mut pos = offset;
+        let (record_size, bytes_read) = super::varint::read_varint(page_data, pos)?;
+        pos += bytes_read;
+        let (row_id, bytes_read) = super::varint::read_varint(page_data, pos)?;
+        pos += bytes_read;
+
+        let payload = self.read_payload(page_data, pos, record_size as usize, PayloadKind::TableLeaf)?;
+        Cell::from_payload(record_size, row_id, &payload, self.text_encoding)
+    }
+
+    /// Assemble a cell payload starting at `offset`. Payloads that fit on the
+    /// page are borrowed as-is; larger ones are copied together with the
+    /// content of every page in their overflow chain.
+    fn read_payload<'a>(&mut self, page_data: &'a [u8], offset: usize, payload_size: usize, kind: PayloadKind) -> Result<Cow<'a, [u8]>> {
+        let usable_size = self.usable_size;
+        let local_size = cell::local_payload_size(payload_size, usable_size, kind);
+
+        if local_size == payload_size {
+            if offset + payload_size > usable_size {
+                bail!("Cell payload extends beyond the usable page area");
+            }
+            return Ok(Cow::Borrowed(&page_data[offset..offset + payload_size]));
+        }
+
+        if offset + local_size + 4 > usable_size {
+            bail!("Cell payload extends beyond the usable page area");
+        }
+
+        let mut payload = Vec::with_capacity(payload_size);
+        payload.extend_from_slice(&page_data[offset..offset + local_size]);
+        let mut next_page = self.read_page_number_from_cell(page_data, offset + local_size)?;
+
+        while payload.len() < payload_size {
+            if next_page == 0 {
+                bail!("Overflow chain ended after {} of {} payload bytes", payload.len(), payload_size);
+            }
+
+            let overflow_data = self.read_page_data(next_page)?;
+            next_page = self.read_page_number_from_cell(&overflow_data, 0)?;
+
+            let chunk_size = (payload_size - payload.len()).min(usable_size - 4);
+            payload.extend_from_slice(&overflow_data[4..4 + chunk_size]);
+        }
+
+        Ok(Cow::Owned(payload))
+    }
+
+    pub(crate) fn read_page_data(&mut self, page_number: usize) -> Result<Page> {
+        if let Some(page_data) = self.dirty.get(&page_number) {
+            return Ok(page_data.clone());
+        }
+
+        // Zero-copy sources are already as cheap as a cache hit
+        let use_cache = !self.source.is_zero_copy();
+
+        if use_cache {
+            if let Some(page_data) = self.cache.get(page_number) {
+                return Ok(page_data);
+            }
+        }
+
+        if page_number == 0 {
+            bail!("Invalid page number 0");
+        }
+
         let offset = (page_number - 1) * self.page_size;
-        self.file.seek(std::io::SeekFrom::Start(offset as u64))?;
+        let page_data = self.source.read_at(offset as u64, self.page_size)?;
 
-        let mut page_data = vec![0; self.page_size];
-        self.file.read_exact(&mut page_data)?;
+        if use_cache {
+            self.cache.insert(page_number, page_data.clone());
+        }
         Ok(page_data)
     }
 
-    fn get_cell_offsets(&self, page_data: &[u8], page_number: usize) -> Result<Vec<usize>> {
+    pub(crate) fn get_cell_offsets(&self, page_data: &[u8], page_number: usize) -> Result<Vec<usize>> {
         let cell_count = self.get_cell_count(page_data, page_number)?;
         let dbheader_offset = self.get_dbheader_offset(page_number);
-        let ptr_start = dbheader_offset + BTREE_HEADER_SIZE;
+        // Interior pages have a 4-byte right child pointer at the end of the header
+        let header_size = if btree_page::is_interior(page_data[dbheader_offset]) { 12 } else { BTREE_HEADER_SIZE };
+        let ptr_start = dbheader_offset + header_size;
         let ptr_end = ptr_start + cell_count * 2;
 
         if ptr_end > page_data.len() {
@@ -145,6 +313,10 @@ impl Database {
             .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()) as usize)
             .collect();
 
+        if let Some(offset) = cell_offsets.iter().find(|&&offset| offset < ptr_end || offset >= self.usable_size) {
+            bail!("Cell offset {} on page {} is outside the cell content area", offset, page_number);
+        }
+
         Ok(cell_offsets)
     }
 
@@ -164,269 +336,230 @@ impl Database {
     }
 
     /// Calculate the database header offset for a given page number
-    fn get_dbheader_offset(&self, page_number: usize) -> usize {
+    pub(crate) fn get_dbheader_offset(&self, page_number: usize) -> usize {
         if page_number == 1 { DB_HEADER_SIZE } else { 0 }
     }
 
     pub fn get_col_names(&mut self, table_name: &str) -> Result<Vec<ColumnInfo>> {
+        Ok(self.get_table_schema(table_name)?.columns)
+    }
+
+    /// Columns, constraints and options of a table, parsed from its CREATE TABLE statement
+    pub fn get_table_schema(&mut self, table_name: &str) -> Result<TableSchema> {
         let table_info = self.find_table_info(table_name)?;
         
         let sql = table_info
-            .record
-            .get_sql_schema()
+            .sql
             .ok_or_else(|| anyhow::anyhow!("No SQL schema found for table {}", table_name))?;
 
-        let schema = TableSchema::from_create_sql(sql)?;
-        Ok(schema.columns)
+        TableSchema::from_create_sql(&sql)
     }
 
-    pub fn find_table_info(&mut self, table_name: &str) -> Result<Cell> {
-        let cells = self.read_page(1)?;
+    pub fn find_table_info(&mut self, table_name: &str) -> Result<SchemaObject> {
+        let objects = self.load_schema()?;
         
-        for cell in cells {
-            if let Some(name) = cell.record.get_table_name() {
-                if name == table_name {
-                    return Ok(cell);
-                }
-            }
-        }
-        
-        bail!("Table {} not found", table_name)
+        objects
+            .iter()
+            .find(|obj| obj.object_type == "table" && obj.name == table_name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Table {} not found", table_name))
     }
 
+    /// Count the rows of a table by stepping through it, one page at a time
     pub fn count_table_rows(&mut self, table_name: &str) -> Result<usize> {
-        let table_info = self.find_table_info(table_name)?;
-        let page_num = table_info.record.get_page_number()?;
-        let all_cells = self.collect_all_table_cells(page_num)?;
-        Ok(all_cells.len())
+        let mut count = 0;
+        self.visit_table_rows(table_name, false, &mut |_| {
+            count += 1;
+            Ok(true)
+        })?;
+        Ok(count)
     }
 
     pub fn get_table_names(&mut self) -> Result<Vec<String>> {
-        let cells = self.read_page(1)?;
-        let mut tables = Vec::new();
-        
-        for cell in cells {
-            if let Some(table_name) = cell.record.get_table_name() {
-                tables.push(table_name.to_string());
-            }
-        }
+        let objects = self.load_schema()?;
         
-        Ok(tables)
+        Ok(objects
+            .iter()
+            .filter(|obj| obj.object_type == "table")
+            .map(|obj| obj.name.clone())
+            .collect())
     }
 
     /// Get all schema objects (tables, indexes, etc.) from sqlite_master
     pub fn get_all_schema_objects(&mut self) -> Result<Vec<SchemaObject>> {
-        let cells = self.read_page(1)?;
-        let mut objects = Vec::new();
-        
-        for cell in cells {
-            if let Some(schema_obj) = SchemaObject::from_record(&cell.record) {
-                objects.push(schema_obj);
-            }
-        }
-        
-        Ok(objects)
+        Ok(self.load_schema()?.to_vec())
     }
 
-    /// Find an index that can be used for the given table and column
-    pub fn find_index_for_column(&mut self, table_name: &str, column_name: &str) -> Result<Option<SchemaObject>> {
-        let objects = self.get_all_schema_objects()?;
-        
-        for obj in objects {
-            if obj.object_type == "index" && obj.tbl_name == table_name {
-                if let Some(sql) = &obj.sql {
-                    if sql.to_lowercase().contains(&format!("on {} ({})", table_name, column_name)) {
-                        return Ok(Some(obj));
-                    }
-                }
-            }
+    /// Parse sqlite_schema on first use and keep the result for later lookups.
+    /// The schema is a regular table B-tree rooted at page 1, so it is walked
+    /// like any other table once it outgrows a single leaf page.
+    fn load_schema(&mut self) -> Result<&[SchemaObject]> {
+        if self.schema.is_none() {
+            let cells = self.collect_all_table_cells(SCHEMA_ROOT_PAGE)?;
+            let objects = cells
+                .iter()
+                .filter_map(|cell| SchemaObject::from_record(&cell.record))
+                .collect();
+            self.schema = Some(objects);
         }
-        
-        Ok(None)
-    }
 
-    /// Search an index for entries matching the given value and return row IDs
-    pub fn search_index(&mut self, index: &SchemaObject, search_value: &str) -> Result<Vec<u64>> {
-        let mut row_ids = Vec::new();
-        self.traverse_index_for_value(index.rootpage, search_value, &mut row_ids)?;
-        Ok(row_ids)
+        Ok(self.schema.as_deref().unwrap_or_default())
     }
-    
-    fn traverse_index_for_value(&mut self, page_num: usize, search_value: &str, row_ids: &mut Vec<u64>) -> Result<()> {
-        let page_data = self.read_page_data(page_num)?;
-        let dbheader_offset = self.get_dbheader_offset(page_num);
-        
-        if dbheader_offset >= page_data.len() {
-            bail!("Page data too small for page header");
-        }
-        
-        let page_type = page_data[dbheader_offset];
-        
-        match page_type {
-            LEAF_INDEX_PAGE => {
-                // This is a leaf index page - search for matching entries
-                self.search_index_leaf_page_stack(&page_data, page_num, search_value, row_ids)?;
-            }
-            INTERIOR_INDEX_PAGE => {
-                // This is an interior index page - traverse all children that might contain our value
-                let child_pages = self.get_index_child_pages_proper(&page_data, page_num, search_value)?;
-                
-                for child_page in child_pages {
-                    self.traverse_index_for_value(child_page, search_value, row_ids)?;
-                }
-            }
-            _ => {
-                bail!("Unsupported page type {} for index search", page_type);
-            }
-        }
-        
-        Ok(())
+
+    pub fn find_index_info(&mut self, index_name: &str) -> Result<SchemaObject> {
+        let objects = self.load_schema()?;
+
+        objects
+            .iter()
+            .find(|obj| obj.object_type == "index" && obj.name == index_name)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Index {} not found", index_name))
     }
 
-    /// Search a leaf index page for matching entries
-    fn search_index_leaf_page_stack(&self, page_data: &[u8], page_num: usize, search_value: &str, row_ids: &mut Vec<u64>) -> Result<()> {
-        let cell_offsets = self.get_cell_offsets(page_data, page_num)?;
-        
-        for cell_offset in cell_offsets.iter() {
-            if let Ok(cell) = self.read_index_cell(page_data, *cell_offset) {
-                if cell.key == search_value {
-                    row_ids.push(cell.row_id);
-                }
-            }
-        }
-        
-        Ok(())
+    /// The indexes on a table that were created with CREATE INDEX. Indexes
+    /// SQLite makes itself for UNIQUE and PRIMARY KEY constraints have no
+    /// statement to parse and are left out, as are indexes on expressions.
+    pub fn get_index_schemas(&mut self, table_name: &str) -> Result<Vec<IndexSchema>> {
+        let objects = self.load_schema()?;
+
+        Ok(objects
+            .iter()
+            .filter(|obj| obj.object_type == "index" && obj.tbl_name == table_name)
+            .filter_map(|obj| IndexSchema::from_create_sql(obj.sql.as_deref()?).ok())
+            .collect())
     }
 
-    /// Get child pages from an interior index page with smart key-based navigation
-    fn get_index_child_pages_proper(&self, page_data: &[u8], page_num: usize, search_value: &str) -> Result<Vec<usize>> {
-        let dbheader_offset = self.get_dbheader_offset(page_num);
-        let cell_count = self.get_cell_count(page_data, page_num)?;
-        
-        let rightmost_page = self.read_rightmost_page(page_data, dbheader_offset)?;
-        
-        let ptr_start = dbheader_offset + 12;
-        let ptr_end = ptr_start + cell_count * 2;
+    /// Find an index whose first column is the given column, which can be
+    /// used to look up or order rows by it. Partial indexes are skipped as
+    /// they leave rows out.
+    pub fn find_index_for_column(&mut self, table_name: &str, column_name: &str) -> Result<Option<SchemaObject>> {
+        let index = self.get_index_schemas(table_name)?
+            .into_iter()
+            .find(|index| {
+                index.where_clause.is_none()
+                    && index.columns.first().is_some_and(|c| c.name.eq_ignore_ascii_case(column_name))
+            });
 
-        if ptr_end > page_data.len() {
-            bail!("Page data too small to contain all cell pointers");
-        }
-        
-        let pointer_array = &page_data[ptr_start..ptr_end];
-        let cell_offsets: Vec<usize> = pointer_array
-            .chunks_exact(2)
-            .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()) as usize)
-            .collect();
-        
-        let mut child_pages = Vec::new();
-        let mut found_target_range = false;
-        
-        for cell_offset in cell_offsets.iter() {
-            if cell_offset + 4 > page_data.len() {
-                continue;
-            }
-            
-            let child_page = self.read_page_number_from_cell(page_data, *cell_offset)?;
-            
-            if let Ok(index_cell) = self.read_index_cell(page_data, *cell_offset) {
-                if search_value <= index_cell.key.as_str() {
-                    child_pages.push(child_page);
-                    found_target_range = true;
-                    break;
-                }
-            } else {
-                child_pages.push(child_page);
-            }
-        }
-        
-        if !found_target_range {
-            child_pages.push(rightmost_page);
-        }
-        
-        if child_pages.is_empty() {
-            for cell_offset in cell_offsets.iter() {
-                if let Ok(page_number) = self.read_page_number_from_cell(page_data, *cell_offset) {
-                    child_pages.push(page_number);
-                }
-            }
-            child_pages.push(rightmost_page);
+        match index {
+            Some(index) => self.find_index_info(&index.name).map(Some),
+            None => Ok(None),
         }
-        
-        Ok(child_pages)
     }
 
-    /// Read an index cell from the page data
-    fn read_index_cell(&self, page_data: &[u8], offset: usize) -> Result<IndexCell> {
-        let mut pos = offset;
-        
-        let (payload_size, bytes_read) = super::varint::read_varint(page_data, pos)?;
-        pos += bytes_read;
-        
-        let payload_end = pos + payload_size as usize;
-        if payload_end > page_data.len() {
-            bail!("Index cell payload extends beyond page");
-        }
-        
-        let payload_data = &page_data[pos..payload_end];
-        let record = super::record::Record::from_bytes(payload_data)?;
-        
-        if record.body.len() < 2 {
-            bail!("Index cell has fewer than 2 fields: {}", record.body.len());
-        }
-        
-        let key = match record.body.get(0) {
-            Some(RecordValue::Text(s)) => s.clone(),
-            Some(other) => other.to_display_string(),
-            None => bail!("Index cell missing key value"),
-        };
-        
-        let row_id = match record.body.get(1) {
-            Some(RecordValue::Int(id)) => *id as u64,
-            _ => bail!("Index cell missing or invalid row ID"),
-        };
-        
-        Ok(IndexCell { key, row_id })
+    /// Search an index for entries whose first column equals the given value
+    /// and return their row IDs. Keys are compared by SQLite's rules, so the
+    /// value should already have the indexed column's affinity applied.
+    pub fn search_index(&mut self, index: &SchemaObject, search_value: &RecordValue) -> Result<Vec<u64>> {
+        let mut cursor = self.index_cursor(&index.name)?;
+        let key = vec![search_value.clone()];
+        cursor.set_range(Bound::Included(key.clone()), Bound::Included(key));
+        cursor.map(|entry| Ok(entry?.row_id)).collect()
     }
 
-    pub fn get_num_tables(&mut self) -> Result<u16> {
-        let page_data = self.read_page_data(1)?;
-        
-        let bheader_start = DB_HEADER_SIZE;
-        if bheader_start + 5 > page_data.len() {
-            bail!("Page data too small to contain B-tree header");
-        }
-        
-        Ok(u16::from_be_bytes([
-            page_data[bheader_start + 3], 
-            page_data[bheader_start + 4]
-        ]))
+    /// Read the record of an index cell from the page data, starting at its
+    /// payload size varint
+    pub(crate) fn read_index_record(&mut self, page_data: &[u8], offset: usize) -> Result<Record> {
+        let (payload_size, bytes_read) = super::varint::read_varint(page_data, offset)?;
+        let payload_data = self.read_payload(page_data, offset + bytes_read, payload_size as usize, PayloadKind::Index)?;
+        Record::from_bytes(&payload_data, self.text_encoding)
+    }
+
+    pub fn get_num_tables(&mut self) -> Result<usize> {
+        Ok(self.get_table_names()?.len())
     }
 
     /// Read all rows from a table and return them with column information
     pub fn get_table_rows(&mut self, table_name: &str) -> Result<TableRows> {
-        let table_info = self.find_table_info(table_name)?;
-        let columns = self.get_col_names(table_name)?;
-        let page_num = table_info.record.get_page_number()?;
-        
-        let all_cells = self.collect_all_table_cells(page_num)?;
+        let schema = self.get_table_schema(table_name)?;
 
         let mut rows = Vec::new();
-        for cell in all_cells {
-            rows.push(self.create_table_row(cell, &columns));
-        }
+        self.visit_table_rows(table_name, false, &mut |row| {
+            rows.push(row);
+            Ok(true)
+        })?;
 
         Ok(TableRows {
-            columns,
+            columns: schema.columns,
+            rows,
+            without_rowid: schema.without_rowid,
+        })
+    }
+
+    /// Rows of a WITHOUT ROWID table whose first primary key column equals
+    /// `key`, found by searching the table's own B-tree. The value should
+    /// already have the column's affinity applied.
+    pub fn get_table_rows_by_primary_key(&mut self, table_name: &str, key: &RecordValue) -> Result<TableRows> {
+        let key = vec![key.clone()];
+        self.without_rowid_rows(table_name, Bound::Included(key.clone()), Bound::Included(key))
+    }
+
+    /// Rows of a WITHOUT ROWID table whose primary key lies in a range, as
+    /// for [`IndexCursor::set_range`](super::IndexCursor::set_range)
+    pub fn without_rowid_rows(&mut self, table_name: &str, lower: Bound<Vec<RecordValue>>, upper: Bound<Vec<RecordValue>>) -> Result<TableRows> {
+        let schema = self.get_table_schema(table_name)?;
+        let storage_order = schema.storage_order();
+
+        let mut cursor = self.primary_key_cursor(table_name)?;
+        cursor.set_range(lower, upper);
+        let rows = cursor
+            .map(|entry| Ok(Self::without_rowid_row(&storage_order, entry?.key)))
+            .collect::<Result<_>>()?;
+
+        Ok(TableRows {
+            columns: schema.columns,
             rows,
+            without_rowid: true,
         })
     }
 
+    /// WITHOUT ROWID tables are stored as index B-trees whose records hold
+    /// the primary key columns first; put the values back in declaration
+    /// order, given [`TableSchema::storage_order`]
+    pub(crate) fn without_rowid_row(storage_order: &[usize], fields: Vec<RecordValue>) -> TableRow {
+        let mut values = vec![RecordValue::Null; storage_order.len()];
+        for (value, &index) in fields.into_iter().zip(storage_order) {
+            values[index] = value;
+        }
+        TableRow { row_id: 0, values }
+    }
+
+    /// Visit the rows of a table in storage order (by rowid, or by primary key
+    /// for a WITHOUT ROWID table), or in reverse, until `visit` returns false.
+    /// Pages past that point are never read.
+    pub fn visit_table_rows(&mut self, table_name: &str, backwards: bool, visit: &mut dyn FnMut(TableRow) -> Result<bool>) -> Result<()> {
+        let schema = self.get_table_schema(table_name)?;
+
+        if schema.without_rowid {
+            let storage_order = schema.storage_order();
+            let mut cursor = self.primary_key_cursor(table_name)?;
+            let mut entry = if backwards { cursor.seek_last()? } else { cursor.seek_first()? };
+            while let Some(current) = entry {
+                if !visit(Self::without_rowid_row(&storage_order, current.key))? {
+                    break;
+                }
+                entry = if backwards { cursor.prev() } else { cursor.next() }.transpose()?;
+            }
+            return Ok(());
+        }
+
+        let mut cursor = self.table_cursor(table_name)?;
+        let mut row = if backwards { cursor.seek_last()? } else { cursor.seek_first()? };
+        while let Some(current) = row {
+            if !visit(current)? {
+                break;
+            }
+            row = if backwards { cursor.prev() } else { cursor.next() }.transpose()?;
+        }
+        Ok(())
+    }
+
     /// Create a TableRow from a cell and column information
-    fn create_table_row(&self, cell: Cell, columns: &[ColumnInfo]) -> TableRow {
+    pub(crate) fn create_table_row(&self, cell: Cell, columns: &[ColumnInfo]) -> TableRow {
         let mut row = Vec::new();
         
         for (i, column) in columns.iter().enumerate() {
-            if column.is_primary_key {
+            // The rowid alias column is stored as NULL; its value is the rowid
+            if column.is_rowid_alias {
                 row.push(RecordValue::Int(cell.row_id as i64));
             } else {
                 if let Some(value) = cell.record.body.get(i) {
@@ -460,7 +593,7 @@ impl Database {
                 let mut cells = Vec::new();
 
                 for cell_offset in cell_offsets {
-                    let cell = Cell::from_bytes(&page_data, cell_offset)?;
+                    let cell = self.read_table_cell(&page_data, cell_offset)?;
                     cells.push(cell);
                 }
                 
@@ -488,25 +621,12 @@ impl Database {
     /// Get child page numbers from an interior page
     fn get_child_page_numbers(&self, page_data: &[u8], page_num: usize) -> Result<Vec<usize>> {
         let dbheader_offset = self.get_dbheader_offset(page_num);
-        let cell_count = self.get_cell_count(page_data, page_num)?;
-        
-        let ptr_start = dbheader_offset + 12;
-        let ptr_end = ptr_start + cell_count * 2;
-
-        if ptr_end > page_data.len() {
-            bail!("Page data too small to contain all cell pointers");
-        }
         
         let rightmost_page = self.read_rightmost_page(page_data, dbheader_offset)?;
+        let cell_offsets = self.get_cell_offsets(page_data, page_num)?;
         
         let mut child_pages = Vec::new();
         
-        let pointer_array = &page_data[ptr_start..ptr_end];
-        let cell_offsets: Vec<usize> = pointer_array
-            .chunks_exact(2)
-            .map(|chunk| u16::from_be_bytes(chunk.try_into().unwrap()) as usize)
-            .collect();
-        
         for cell_offset in cell_offsets.iter() {
             if let Ok(page_number) = self.read_page_number_from_cell(page_data, *cell_offset) {
                 child_pages.push(page_number);
@@ -520,18 +640,18 @@ impl Database {
 
     /// Get specific column values from a table
     pub fn get_column_values(&mut self, table_name: &str, column_names: &[&str]) -> Result<Vec<Vec<RecordValue>>> {
-        let table_rows = self.get_table_rows(table_name)?;
+        let columns = self.get_table_schema(table_name)?.columns;
         let mut results = Vec::new();
 
         let mut column_indices = Vec::new();
         for col_name in column_names {
-            let index = table_rows.columns.iter()
+            let index = columns.iter()
                 .position(|col| col.name.eq_ignore_ascii_case(col_name))
                 .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in table '{}'", col_name, table_name))?;
             column_indices.push(index);
         }
 
-        for row in table_rows.rows {
+        self.visit_table_rows(table_name, false, &mut |row| {
             let mut row_values = Vec::new();
             for &col_index in &column_indices {
                 if let Some(value) = row.values.get(col_index) {
@@ -541,7 +661,8 @@ impl Database {
                 }
             }
             results.push(row_values);
-        }
+            Ok(true)
+        })?;
 
         Ok(results)
     }
@@ -561,64 +682,19 @@ impl Database {
         Ok(TableRows {
             columns,
             rows,
+            without_rowid: false,
         })
     }
 
     /// Fetch a single row by its row ID using B-tree navigation (much faster than loading all data)
     pub fn get_table_row_by_id(&mut self, table_name: &str, row_id: u64) -> Result<Option<TableRow>> {
-        let table_info = self.find_table_info(table_name)?;
-        let columns = self.get_col_names(table_name)?;
-        let page_num = table_info.record.get_page_number()?;
-        
-        // Navigate directly to the row using B-tree search
-        if let Some(cell) = self.search_table_for_row_id(page_num, row_id)? {
-            Ok(Some(self.create_table_row(cell, &columns)))
-        } else {
-            Ok(None)
-        }
-    }
-    
-    /// Search for a specific row ID in the table B-tree
-    fn search_table_for_row_id(&mut self, page_num: usize, target_row_id: u64) -> Result<Option<Cell>> {
-        let page_data = self.read_page_data(page_num)?;
-        let dbheader_offset = self.get_dbheader_offset(page_num);
-        
-        if dbheader_offset >= page_data.len() {
-            bail!("Page data too small for page header");
-        }
-        
-        let page_type = page_data[dbheader_offset];
-        
-        match page_type {
-            LEAF_TABLE_PAGE => {
-                let cell_offsets = self.get_cell_offsets(&page_data, page_num)?;
-                
-                for cell_offset in cell_offsets {
-                    let cell = Cell::from_bytes(&page_data, cell_offset)?;
-                    if cell.row_id == target_row_id {
-                        return Ok(Some(cell));
-                    }
-                }
-                Ok(None)
-            }
-            INTERIOR_TABLE_PAGE => {
-                let child_pages = self.get_child_page_numbers(&page_data, page_num)?;
-                
-                for child_page in child_pages {
-                    if let Some(cell) = self.search_table_for_row_id(child_page, target_row_id)? {
-                        return Ok(Some(cell));
-                    }
-                }
-                Ok(None)
-            }
-            _ => {
-                bail!("Unsupported page type {} for table search", page_type);
-            }
-        }
+        let row_id = row_id as i64;
+        let mut cursor = self.table_cursor(table_name)?;
+        Ok(cursor.seek(row_id)?.filter(|row| row.row_id as i64 == row_id))
     }
 
     /// Read a 4-byte page number from cell data at the given offset
-    fn read_page_number_from_cell(&self, page_data: &[u8], offset: usize) -> Result<usize> {
+    pub(crate) fn read_page_number_from_cell(&self, page_data: &[u8], offset: usize) -> Result<usize> {
         if offset + 4 > page_data.len() {
             bail!("Not enough data to read page number");
         }
@@ -634,7 +710,7 @@ impl Database {
     }
 
     /// Read the rightmost page pointer from an interior page header
-    fn read_rightmost_page(&self, page_data: &[u8], dbheader_offset: usize) -> Result<usize> {
+    pub(crate) fn read_rightmost_page(&self, page_data: &[u8], dbheader_offset: usize) -> Result<usize> {
         if dbheader_offset + 12 > page_data.len() {
             bail!("Page data too small for interior page header");
         }
@@ -648,4 +724,282 @@ impl Database {
         
         Ok(rightmost_page)
     }
-} 
\ No newline at end of file
+
+    /// Copy of a page's current content (including pending changes) to modify
+    pub(crate) fn page_for_write(&mut self, page_number: usize) -> Result<Vec<u8>> {
+        Ok(self.read_page_data(page_number)?.to_vec())
+    }
+
+    /// Stage a modified page; it is written back on the next `commit`
+    pub(crate) fn put_page(&mut self, page_number: usize, page_data: Vec<u8>) {
+        self.cache.remove(page_number);
+        self.dirty.insert(page_number, Page::from(page_data));
+    }
+
+    pub(crate) fn load_btree_page(&mut self, page_number: usize) -> Result<BTreePage> {
+        let page_data = self.read_page_data(page_number)?;
+        BTreePage::parse(&page_data, self.get_dbheader_offset(page_number), self.usable_size)
+    }
+
+    pub(crate) fn store_btree_page(&mut self, page_number: usize, page: &BTreePage) -> Result<()> {
+        let mut page_data = self.page_for_write(page_number)?;
+        page.write_to(&mut page_data, self.get_dbheader_offset(page_number), self.usable_size)?;
+        self.put_page(page_number, page_data);
+        Ok(())
+    }
+
+    pub(crate) fn read_header_u32(&mut self, offset: usize) -> Result<u32> {
+        let page_data = self.read_page_data(1)?;
+        btree_page::read_u32(&page_data, offset)
+    }
+
+    fn write_header_u32(&mut self, offset: usize, value: u32) -> Result<()> {
+        let mut page_data = self.page_for_write(1)?;
+        page_data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+        self.put_page(1, page_data);
+        Ok(())
+    }
+
+    /// Number of pages in the database, including pending new pages
+    pub(crate) fn page_count(&mut self) -> Result<usize> {
+        let header = self.header()?;
+        let stored = if header.page_count_is_valid() {
+            header.page_count as usize
+        } else {
+            self.source.size()? as usize / self.page_size
+        };
+
+        let last_dirty = self.dirty.keys().next_back().copied().unwrap_or(0);
+        Ok(stored.max(last_dirty))
+    }
+
+    /// Get a zeroed page for new content, reusing a freelist page if there is one
+    pub(crate) fn allocate_page(&mut self) -> Result<usize> {
+        let page_number = match self.read_header_u32(HEADER_FREELIST_TRUNK)? as usize {
+            0 => {
+                let page_number = self.page_count()? + 1;
+                self.write_header_u32(HEADER_PAGE_COUNT, page_number as u32)?;
+                page_number
+            }
+            trunk => self.take_freelist_page(trunk)?,
+        };
+
+        self.put_page(page_number, vec![0; self.page_size]);
+        Ok(page_number)
+    }
+
+    /// Remove one page from the freelist whose first trunk page is `trunk`.
+    /// Leaf pages are handed out first; an empty trunk is itself reused.
+    fn take_freelist_page(&mut self, trunk: usize) -> Result<usize> {
+        let mut trunk_data = self.page_for_write(trunk)?;
+        let leaf_count = btree_page::read_u32(&trunk_data, 4)? as usize;
+
+        let page_number = if leaf_count > 0 {
+            let entry = 8 + (leaf_count - 1) * 4;
+            let leaf = btree_page::read_u32(&trunk_data, entry)? as usize;
+            trunk_data[4..8].copy_from_slice(&(leaf_count as u32 - 1).to_be_bytes());
+            self.put_page(trunk, trunk_data);
+            leaf
+        } else {
+            let next_trunk = btree_page::read_u32(&trunk_data, 0)?;
+            self.write_header_u32(HEADER_FREELIST_TRUNK, next_trunk)?;
+            trunk
+        };
+
+        let free_count = self.read_header_u32(HEADER_FREELIST_COUNT)?;
+        self.write_header_u32(HEADER_FREELIST_COUNT, free_count.saturating_sub(1))?;
+        Ok(page_number)
+    }
+
+    /// Put a page that is no longer used on the freelist. It is added as a
+    /// leaf of the first trunk page while that has room, otherwise it
+    /// becomes the new first trunk.
+    pub(crate) fn free_page(&mut self, page_number: usize) -> Result<()> {
+        // Older SQLite versions only accept this many leaves per trunk page
+        let max_leaves = self.usable_size / 4 - 8;
+        let trunk = self.read_header_u32(HEADER_FREELIST_TRUNK)? as usize;
+
+        let mut added_as_leaf = false;
+        if trunk != 0 {
+            let mut trunk_data = self.page_for_write(trunk)?;
+            let leaf_count = btree_page::read_u32(&trunk_data, 4)? as usize;
+            if leaf_count < max_leaves {
+                let entry = 8 + leaf_count * 4;
+                trunk_data[entry..entry + 4].copy_from_slice(&(page_number as u32).to_be_bytes());
+                trunk_data[4..8].copy_from_slice(&(leaf_count as u32 + 1).to_be_bytes());
+                self.put_page(trunk, trunk_data);
+                self.put_page(page_number, vec![0; self.page_size]);
+                added_as_leaf = true;
+            }
+        }
+
+        if !added_as_leaf {
+            let mut trunk_data = vec![0; self.page_size];
+            trunk_data[..4].copy_from_slice(&(trunk as u32).to_be_bytes());
+            self.put_page(page_number, trunk_data);
+            self.write_header_u32(HEADER_FREELIST_TRUNK, page_number as u32)?;
+        }
+
+        let free_count = self.read_header_u32(HEADER_FREELIST_COUNT)?;
+        self.write_header_u32(HEADER_FREELIST_COUNT, free_count + 1)
+    }
+
+    /// Whether an explicit transaction started by `begin` is open
+    pub fn in_transaction(&self) -> bool {
+        self.in_transaction
+    }
+
+    /// Start an explicit transaction. Until `commit` or `rollback`, changes
+    /// only live in memory.
+    pub fn begin(&mut self) -> Result<()> {
+        if self.in_transaction {
+            bail!("cannot start a transaction within a transaction");
+        }
+        self.in_transaction = true;
+        Ok(())
+    }
+
+    /// Start a transaction that is rolled back unless committed
+    pub fn transaction(&mut self) -> Result<Transaction<'_>> {
+        self.begin()?;
+        Ok(Transaction::new(self))
+    }
+
+    /// Write all pending page changes back to the database file, bumping the
+    /// file change counter, and end the current transaction.
+    ///
+    /// For file-backed databases the original content of every page being
+    /// overwritten is first saved to a rollback journal, which is deleted
+    /// once the database file is synced. If any of this fails the changes
+    /// stay pending and the transaction open, so `rollback` can put the
+    /// file back from the journal.
+    pub fn commit(&mut self) -> Result<()> {
+        if self.dirty.is_empty() {
+            self.in_transaction = false;
+            return Ok(());
+        }
+
+        let page_count = self.page_count()? as u32;
+        let change_counter = self.read_header_u32(HEADER_CHANGE_COUNTER)?.wrapping_add(1);
+        self.write_header_u32(HEADER_CHANGE_COUNTER, change_counter)?;
+        self.write_header_u32(HEADER_VERSION_VALID_FOR, change_counter)?;
+        self.write_header_u32(HEADER_PAGE_COUNT, page_count)?;
+
+        if let Some(path) = &self.path {
+            let original_page_count = self.source.size()? as usize / self.page_size;
+            let mut original_pages = Vec::new();
+            for &page_number in self.dirty.keys().filter(|&&n| n <= original_page_count) {
+                let offset = (page_number - 1) * self.page_size;
+                let page_data = self.source.read_at(offset as u64, self.page_size)?;
+                original_pages.push((page_number, page_data.to_vec()));
+            }
+            journal::write_journal(path, self.page_size, original_page_count, &original_pages)?;
+        }
+
+        for (page_number, page_data) in &self.dirty {
+            let offset = (page_number - 1) * self.page_size;
+            self.source.write_at(offset as u64, page_data)?;
+        }
+        self.source.sync()?;
+
+        if let Some(path) = &self.path {
+            journal::delete_journal(path)?;
+        }
+
+        self.in_transaction = false;
+        for (page_number, page_data) in std::mem::take(&mut self.dirty) {
+            self.cache.insert(page_number, page_data);
+        }
+        Ok(())
+    }
+
+    /// Throw away all changes made since the last `commit` and end the
+    /// current transaction. A journal left behind by a `commit` that failed
+    /// part way is played back; should that fail as well, the journal stays
+    /// hot and is played back when the database is next opened.
+    pub fn rollback(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = journal::recover_hot_journal(path);
+        }
+        self.dirty.clear();
+        self.schema = None;
+        self.in_transaction = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_support::{image_with_tables, SharedMemory};
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// Forwards to a shared memory image, failing every write while `fail` is set
+    struct FailingWrites {
+        image: SharedMemory,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl PageSource for FailingWrites {
+        fn size(&self) -> Result<u64> {
+            self.image.size()
+        }
+
+        fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+            self.image.read_at(offset, len)
+        }
+
+        fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+            if self.fail.get() {
+                bail!("disk I/O error");
+            }
+            self.image.write_at(offset, data)
+        }
+    }
+
+    fn open_failing(image: &SharedMemory) -> (Database, Rc<Cell<bool>>) {
+        let fail = Rc::new(Cell::new(false));
+        let source = FailingWrites { image: image.clone(), fail: Rc::clone(&fail) };
+        (Database::from_source(source).unwrap(), fail)
+    }
+
+    fn row_count(image: &SharedMemory) -> usize {
+        image.open().unwrap().get_table_rows("t").unwrap().rows.len()
+    }
+
+    #[test]
+    fn failed_commit_keeps_the_transaction_open_and_can_be_retried() {
+        let image = SharedMemory::new(image_with_tables(4096, &[("t", "CREATE TABLE t(a)")]));
+        let (mut db, fail) = open_failing(&image);
+
+        db.begin().unwrap();
+        db.insert_row("t", None, vec![RecordValue::Int(7)]).unwrap();
+        fail.set(true);
+        assert!(db.commit().is_err());
+        assert!(db.in_transaction());
+        assert_eq!(db.get_table_rows("t").unwrap().rows.len(), 1);
+        assert_eq!(row_count(&image), 0);
+
+        fail.set(false);
+        db.commit().unwrap();
+        assert!(!db.in_transaction());
+        assert_eq!(row_count(&image), 1);
+    }
+
+    #[test]
+    fn rollback_after_a_failed_commit_discards_the_changes() {
+        let image = SharedMemory::new(image_with_tables(4096, &[("t", "CREATE TABLE t(a)")]));
+        let (mut db, fail) = open_failing(&image);
+
+        let mut tx = db.transaction().unwrap();
+        tx.insert_row("t", None, vec![RecordValue::Int(7)]).unwrap();
+        fail.set(true);
+        assert!(tx.commit().is_err());
+
+        assert!(!db.in_transaction());
+        assert!(db.get_table_rows("t").unwrap().rows.is_empty());
+        fail.set(false);
+        db.commit().unwrap();
+        assert_eq!(row_count(&image), 0);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{bail, Result};
+
+use super::page_source::PageSource;
+use crate::DB_HEADER_SIZE;
+
+/// Every database file starts with this string
+const MAGIC: &[u8; 16] = b"SQLite format 3\0";
+
+/// How TEXT values are stored in the database (header offset 56)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16le,
+    Utf16be,
+}
+
+/// The 100-byte header at the start of page 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DatabaseHeader {
+    pub page_size: usize,
+    pub write_version: u8,            // 1 = legacy rollback journal, 2 = WAL
+    pub read_version: u8,
+    pub reserved_bytes: u8,           // unused space at the end of each page
+    pub max_payload_fraction: u8,
+    pub min_payload_fraction: u8,
+    pub leaf_payload_fraction: u8,
+    pub file_change_counter: u32,
+    pub page_count: u32,              // only valid if version_valid_for matches the change counter
+    pub freelist_trunk: u32,
+    pub freelist_count: u32,
+    pub schema_cookie: u32,
+    pub schema_format: u32,
+    pub default_cache_size: u32,
+    pub largest_root_page: u32,       // non-zero in auto-vacuum databases
+    pub text_encoding: TextEncoding,
+    pub user_version: u32,
+    pub incremental_vacuum: u32,
+    pub application_id: u32,
+    pub version_valid_for: u32,
+    pub sqlite_version: u32,
+}
+
+impl DatabaseHeader {
+    /// Read and parse the header at the start of a page source
+    pub fn read_from(source: &mut dyn PageSource) -> Result<Self> {
+        if source.size()? < DB_HEADER_SIZE as u64 {
+            bail!("File is too small to be a SQLite database");
+        }
+        Self::parse(&source.read_at(0, DB_HEADER_SIZE)?)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < DB_HEADER_SIZE {
+            bail!("Database header is {} bytes, expected {}", data.len(), DB_HEADER_SIZE);
+        }
+        if &data[..16] != MAGIC {
+            bail!("Not a SQLite database: the header does not start with \"SQLite format 3\"");
+        }
+
+        let u32_at = |offset: usize| u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]);
+
+        // 1 stands for 65536, which does not fit in the two-byte field
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            size => size as usize,
+        };
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            bail!("Invalid page size {} in database header", page_size);
+        }
+
+        let reserved_bytes = data[20];
+        if page_size - (reserved_bytes as usize) < 480 {
+            bail!("Invalid database header: {} reserved bytes leave too little usable space in {}-byte pages", reserved_bytes, page_size);
+        }
+
+        if (data[21], data[22], data[23]) != (64, 32, 32) {
+            bail!("Invalid database header: payload fractions must be 64, 32 and 32, got {}, {} and {}", data[21], data[22], data[23]);
+        }
+
+        let text_encoding = match u32_at(56) {
+            // Zero only occurs before the schema is first written; it means UTF-8
+            0 | 1 => TextEncoding::Utf8,
+            2 => TextEncoding::Utf16le,
+            3 => TextEncoding::Utf16be,
+            other => bail!("Invalid text encoding {} in database header", other),
+        };
+
+        Ok(Self {
+            page_size,
+            write_version: data[18],
+            read_version: data[19],
+            reserved_bytes,
+            max_payload_fraction: data[21],
+            min_payload_fraction: data[22],
+            leaf_payload_fraction: data[23],
+            file_change_counter: u32_at(24),
+            page_count: u32_at(28),
+            freelist_trunk: u32_at(32),
+            freelist_count: u32_at(36),
+            schema_cookie: u32_at(40),
+            schema_format: u32_at(44),
+            default_cache_size: u32_at(48),
+            largest_root_page: u32_at(52),
+            text_encoding,
+            user_version: u32_at(60),
+            incremental_vacuum: u32_at(64),
+            application_id: u32_at(68),
+            version_valid_for: u32_at(92),
+            sqlite_version: u32_at(96),
+        })
+    }
+
+    /// Whether the database is in WAL journal mode
+    pub fn is_wal_mode(&self) -> bool {
+        self.write_version == 2 || self.read_version == 2
+    }
+
+    /// Whether the in-header page count can be trusted: it is only kept up
+    /// to date by versions that also set version_valid_for
+    pub fn page_count_is_valid(&self) -> bool {
+        self.page_count > 0 && self.file_change_counter == self.version_valid_for
+    }
+
+    /// Bytes of each page available to b-tree content
+    pub fn usable_size(&self) -> usize {
+        self.page_size - self.reserved_bytes as usize
+    }
+}
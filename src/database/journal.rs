@@ -0,0 +1,180 @@
+use anyhow::{bail, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Every rollback journal header starts with these bytes
+const JOURNAL_MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// The header is padded to one sector; records start right after it
+const SECTOR_SIZE: usize = 512;
+/// Size of the meaningful part of the header
+const JOURNAL_HEADER_SIZE: usize = 28;
+
+/// Path of the rollback journal belonging to a database file
+pub fn journal_path(db_path: &str) -> String {
+    format!("{}-journal", db_path)
+}
+
+/// Write the rollback journal for a transaction: the original content of
+/// every page about to be overwritten, in the format SQLite uses, so either
+/// this crate or SQLite itself can undo a write that was interrupted.
+///
+/// The record count in the header is only filled in after the records are
+/// synced, so a journal torn while being written is never played back.
+pub fn write_journal(db_path: &str, page_size: usize, original_page_count: usize, pages: &[(usize, Vec<u8>)]) -> Result<()> {
+    let nonce = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+        .unwrap_or(0x5eed);
+
+    let mut journal = Vec::with_capacity(SECTOR_SIZE + pages.len() * (page_size + 8));
+    journal.extend_from_slice(&JOURNAL_MAGIC);
+    journal.extend_from_slice(&0u32.to_be_bytes()); // record count, set once synced
+    journal.extend_from_slice(&nonce.to_be_bytes());
+    journal.extend_from_slice(&(original_page_count as u32).to_be_bytes());
+    journal.extend_from_slice(&(SECTOR_SIZE as u32).to_be_bytes());
+    journal.extend_from_slice(&(page_size as u32).to_be_bytes());
+    journal.resize(SECTOR_SIZE, 0);
+
+    for (page_number, page_data) in pages {
+        journal.extend_from_slice(&(*page_number as u32).to_be_bytes());
+        journal.extend_from_slice(page_data);
+        journal.extend_from_slice(&page_checksum(nonce, page_data).to_be_bytes());
+    }
+
+    let mut file = File::create(journal_path(db_path))?;
+    file.write_all(&journal)?;
+    file.sync_all()?;
+
+    file.seek(SeekFrom::Start(8))?;
+    file.write_all(&(pages.len() as u32).to_be_bytes())?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Remove the journal once the transaction is safely in the database file
+pub fn delete_journal(db_path: &str) -> Result<()> {
+    match fs::remove_file(journal_path(db_path)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// If a hot journal is left next to the database, copy its pages back,
+/// restore the original file size and delete it. Returns whether anything
+/// was rolled back.
+///
+/// A journal is a sequence of segments, each a sector-aligned header
+/// followed by its records; SQLite starts a new segment every time it
+/// syncs the journal in the middle of a transaction. Playback ends at the
+/// first invalid header or record whose checksum does not match, as it
+/// does in SQLite.
+///
+/// No file locks are taken, so a journal belonging to a transaction that
+/// another process is still writing is indistinguishable from a hot one.
+pub fn recover_hot_journal(db_path: &str) -> Result<bool> {
+    let journal = match fs::read(journal_path(db_path)) {
+        Ok(journal) => journal,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    // An empty or zeroed journal is what's left after a finished transaction
+    let Some(first) = JournalHeader::parse(&journal, 0) else {
+        delete_journal(db_path)?;
+        return Ok(false);
+    };
+
+    let page_size = first.page_size;
+    let sector_size = first.sector_size;
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two()
+        || sector_size < JOURNAL_HEADER_SIZE || !sector_size.is_power_of_two() {
+        bail!("Hot journal for {} has an invalid header", db_path);
+    }
+
+    let mut db = OpenOptions::new().read(true).write(true).open(db_path)?;
+    db.set_len((first.original_page_count * page_size) as u64)?;
+
+    let record_size = page_size + 8;
+    let mut header_offset = 0;
+    let mut restored = 0;
+    'segments: while let Some(header) = JournalHeader::parse(&journal, header_offset) {
+        let records_start = header_offset + sector_size;
+        let record_count = match header.record_count {
+            // Journals written without syncing have a single segment running to the end
+            0xFFFF_FFFF => journal.len().saturating_sub(records_start) / record_size,
+            n => n as usize,
+        };
+
+        for i in 0..record_count {
+            let start = records_start + i * record_size;
+            let Some(record) = journal.get(start..start + record_size) else {
+                break 'segments;
+            };
+
+            let page_number = u32::from_be_bytes([record[0], record[1], record[2], record[3]]) as usize;
+            let page_data = &record[4..4 + page_size];
+            let checksum = u32::from_be_bytes([record[4 + page_size], record[5 + page_size], record[6 + page_size], record[7 + page_size]]);
+            if page_number == 0 || checksum != page_checksum(header.nonce, page_data) {
+                break 'segments;
+            }
+
+            // Pages past the original end of the file were truncated away above
+            if page_number <= first.original_page_count {
+                db.seek(SeekFrom::Start(((page_number - 1) * page_size) as u64))?;
+                db.write_all(page_data)?;
+                restored += 1;
+            }
+        }
+
+        if header.record_count == 0xFFFF_FFFF {
+            break;
+        }
+        let segment_end = records_start + record_count * record_size;
+        header_offset = segment_end.div_ceil(sector_size) * sector_size;
+    }
+
+    db.sync_all()?;
+    delete_journal(db_path)?;
+    Ok(restored > 0)
+}
+
+/// The fields of one journal segment header
+struct JournalHeader {
+    record_count: u32,
+    nonce: u32,
+    original_page_count: usize,
+    sector_size: usize,
+    page_size: usize,
+}
+
+impl JournalHeader {
+    fn parse(journal: &[u8], offset: usize) -> Option<Self> {
+        let header = journal.get(offset..offset + JOURNAL_HEADER_SIZE)?;
+        if header[..8] != JOURNAL_MAGIC {
+            return None;
+        }
+
+        let field = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+        Some(Self {
+            record_count: field(8),
+            nonce: field(12),
+            original_page_count: field(16) as usize,
+            sector_size: field(20) as usize,
+            page_size: field(24) as usize,
+        })
+    }
+}
+
+/// Journal record checksum: the nonce plus every 200th byte of the page,
+/// counting down from 200 bytes before its end
+fn page_checksum(nonce: u32, page_data: &[u8]) -> u32 {
+    let mut checksum = nonce;
+    let mut i = page_data.len() as isize - 200;
+    while i > 0 {
+        checksum = checksum.wrapping_add(page_data[i as usize] as u32);
+        i -= 200;
+    }
+    checksum
+}
@@ -1,12 +1,32 @@
+pub mod affinity;
+pub(crate) mod btree_page;
+mod btree_write;
 pub mod cell;
+mod cursor;
+#[allow(clippy::module_inception)]
 pub mod database;
+pub mod header;
+pub mod journal;
+pub mod page_cache;
+pub mod page_source;
 pub mod record;
 pub mod schema;
+pub mod transaction;
+#[cfg(test)]
+pub(crate) mod test_support;
 pub mod varint;
+pub mod wal;
 
 // Re-export main types for convenience
 pub use database::Database;
+pub use affinity::Affinity;
 pub use cell::Cell;
-pub use record::{Record, RecordValue};
-pub use schema::{TableSchema, ColumnInfo};
+pub use cursor::{IndexCursor, TableCursor};
+pub use header::{DatabaseHeader, TextEncoding};
+pub use page_cache::{CacheStats, PageCache};
+pub use page_source::{FileSource, MemorySource, MmapSource, Page, PageSource};
+pub use record::{Record, RecordFormat, RecordValue};
+pub use schema::{TableSchema, ColumnInfo, ForeignKey, IndexSchema, IndexedColumn, TableConstraint};
+pub use transaction::Transaction;
+pub use wal::WalSource;
 pub use database::{SchemaObject, TableRow, TableRows, IndexCell}; 
\ No newline at end of file
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::page_source::Page;
+
+/// Number of pages kept in memory when no capacity is given
+pub const DEFAULT_PAGE_CACHE_CAPACITY: usize = 256;
+
+/// Snapshot of page cache counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub capacity: usize,
+    pub cached_pages: usize,
+}
+
+struct CachedPage {
+    data: Page,
+    last_used: u64,
+}
+
+/// Least-recently-used cache of raw page buffers, keyed by page number.
+/// Pages are handed out as `Page` handles sharing one `Arc<[u8]>` buffer, so
+/// callers can hold on to a page while the cache keeps serving (or evicting)
+/// others.
+pub struct PageCache {
+    capacity: usize,
+    pages: HashMap<usize, CachedPage>,
+    recency: BTreeMap<u64, usize>, // last use tick -> page number
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+impl PageCache {
+    /// Create a cache holding at most `capacity` pages; 0 disables caching
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up a page, marking it as most recently used on a hit
+    pub fn get(&mut self, page_number: usize) -> Option<Page> {
+        self.clock += 1;
+        let clock = self.clock;
+
+        match self.pages.get_mut(&page_number) {
+            Some(page) => {
+                self.recency.remove(&page.last_used);
+                self.recency.insert(clock, page_number);
+                page.last_used = clock;
+                self.hits += 1;
+                Some(page.data.clone())
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Store a page, evicting the least recently used ones if the cache is full
+    pub fn insert(&mut self, page_number: usize, data: Page) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.clock += 1;
+        if let Some(old) = self.pages.remove(&page_number) {
+            self.recency.remove(&old.last_used);
+        }
+        self.evict_to(self.capacity - 1);
+
+        self.recency.insert(self.clock, page_number);
+        self.pages.insert(page_number, CachedPage { data, last_used: self.clock });
+    }
+
+    /// Drop a single page, e.g. after it has been modified
+    pub fn remove(&mut self, page_number: usize) {
+        if let Some(old) = self.pages.remove(&page_number) {
+            self.recency.remove(&old.last_used);
+        }
+    }
+
+    /// Change the capacity, evicting pages if it shrank
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict_to(capacity);
+    }
+
+    /// Drop every cached page while keeping the hit/miss counters
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.recency.clear();
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            capacity: self.capacity,
+            cached_pages: self.pages.len(),
+        }
+    }
+
+    fn evict_to(&mut self, max_pages: usize) {
+        while self.pages.len() > max_pages {
+            match self.recency.pop_first() {
+                Some((_, page_number)) => {
+                    self.pages.remove(&page_number);
+                }
+                None => break,
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{bail, Result};
+use memmap2::Mmap;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+/// A read-only view of one page (or any byte range) of the database image.
+/// Cloning is cheap: the bytes are shared with the source or the page cache.
+#[derive(Clone)]
+pub struct Page {
+    data: PageData,
+}
+
+#[derive(Clone)]
+enum PageData {
+    Shared(Arc<[u8]>, Range<usize>),
+    Mapped(Arc<Mmap>, Range<usize>),
+    Memory(Arc<Vec<u8>>, Range<usize>),
+}
+
+impl Deref for Page {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.data {
+            PageData::Shared(buf, range) => &buf[range.clone()],
+            PageData::Mapped(map, range) => &map[range.clone()],
+            PageData::Memory(buf, range) => &buf[range.clone()],
+        }
+    }
+}
+
+impl From<Arc<[u8]>> for Page {
+    fn from(buf: Arc<[u8]>) -> Self {
+        let range = 0..buf.len();
+        Page { data: PageData::Shared(buf, range) }
+    }
+}
+
+impl From<Vec<u8>> for Page {
+    fn from(buf: Vec<u8>) -> Self {
+        Page::from(Arc::<[u8]>::from(buf))
+    }
+}
+
+impl std::fmt::Debug for Page {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Page").field("len", &self.len()).finish()
+    }
+}
+
+/// Backend that database pages are read from
+pub trait PageSource {
+    /// Total size of the database image in bytes
+    fn size(&self) -> Result<u64>;
+
+    /// Read `len` bytes starting at byte `offset`
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page>;
+
+    /// Sources that hand out pages without copying skip the page cache
+    fn is_zero_copy(&self) -> bool {
+        false
+    }
+
+    /// Overwrite bytes starting at `offset`, growing the image if needed
+    fn write_at(&mut self, _offset: u64, _data: &[u8]) -> Result<()> {
+        bail!("Database is opened read-only")
+    }
+
+    /// Make previous writes durable
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Default source: positioned reads from a regular file
+pub struct FileSource {
+    file: File,
+}
+
+impl FileSource {
+    pub fn open(path: &str) -> Result<Self> {
+        Ok(Self { file: File::open(path)? })
+    }
+
+    /// Open the file for reading and writing
+    pub fn open_rw(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Self { file })
+    }
+}
+
+impl PageSource for FileSource {
+    fn size(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+        self.file.seek(std::io::SeekFrom::Start(offset))?;
+
+        let mut buf = vec![0; len];
+        self.file.read_exact(&mut buf)?;
+        Ok(Page::from(buf))
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.file.seek(std::io::SeekFrom::Start(offset))?;
+        self.file.write_all(data)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Maps the whole file into memory and serves pages as slices of the mapping
+pub struct MmapSource {
+    map: Arc<Mmap>,
+}
+
+impl MmapSource {
+    /// Map `path` read-only. The file must not be truncated or rewritten by
+    /// another process while mapped, as is the case for read-only
+    /// analytical databases this backend is meant for.
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only and callers guarantee the file is
+        // not modified for the lifetime of the source (see above)
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map: Arc::new(map) })
+    }
+}
+
+impl PageSource for MmapSource {
+    fn size(&self) -> Result<u64> {
+        Ok(self.map.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+        let range = checked_range(offset, len, self.map.len())?;
+        Ok(Page { data: PageData::Mapped(Arc::clone(&self.map), range) })
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        true
+    }
+}
+
+/// Database image held entirely in memory, mostly useful for tests
+pub struct MemorySource {
+    data: Arc<Vec<u8>>,
+}
+
+impl MemorySource {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data: Arc::new(data) }
+    }
+}
+
+impl PageSource for MemorySource {
+    fn size(&self) -> Result<u64> {
+        Ok(self.data.len() as u64)
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+        let range = checked_range(offset, len, self.data.len())?;
+        Ok(Page { data: PageData::Memory(Arc::clone(&self.data), range) })
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        true
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        // Writes go to the buffer in place; only while pages handed out
+        // earlier still share it is it copied first, so they keep the old bytes
+        let buf = Arc::make_mut(&mut self.data);
+        let start = offset as usize;
+        let end = start + data.len();
+        if end > buf.len() {
+            buf.resize(end, 0);
+        }
+        buf[start..end].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+fn checked_range(offset: u64, len: usize, total: usize) -> Result<Range<usize>> {
+    let start = offset as usize;
+    match start.checked_add(len) {
+        Some(end) if end <= total => Ok(start..end),
+        _ => bail!("Read of {} bytes at offset {} is past the end of the database ({} bytes)", len, offset, total),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_support::{image_with_tables, SharedMemory};
+    use crate::RecordValue;
+
+    #[test]
+    fn memory_source_writes_in_place_and_grows_past_the_end() {
+        let mut source = MemorySource::new(vec![0; 8]);
+        let before = source.read_at(0, 8).unwrap();
+
+        source.write_at(2, &[1, 2]).unwrap();
+        source.write_at(6, &[3, 4, 5, 6]).unwrap();
+
+        assert_eq!(source.size().unwrap(), 10);
+        assert_eq!(&*source.read_at(0, 10).unwrap(), &[0, 0, 1, 2, 0, 0, 3, 4, 5, 6]);
+        // A page read before the writes still sees the old bytes
+        assert_eq!(&*before, &[0; 8]);
+    }
+
+    #[test]
+    fn rows_committed_to_memory_are_read_back_by_a_new_connection() {
+        let image = SharedMemory::new(image_with_tables(4096, &[("t", "CREATE TABLE t(id INTEGER PRIMARY KEY, name TEXT)")]));
+
+        let mut db = image.open().unwrap();
+        for name in ["alpha", "beta", "gamma"] {
+            db.insert_row("t", None, vec![RecordValue::Null, RecordValue::Text(name.to_string())]).unwrap();
+        }
+        db.commit().unwrap();
+
+        let mut db = image.open().unwrap();
+        let rows = db.get_table_rows("t").unwrap().rows;
+        let rows: Vec<_> = rows.iter().map(|row| (row.row_id, row.values[1].to_display_string())).collect();
+        assert_eq!(rows, [(1, "alpha".to_string()), (2, "beta".to_string()), (3, "gamma".to_string())]);
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::{bail, Result};
-use super::varint::read_varint;
+use super::header::TextEncoding;
+use super::varint::{read_varint, varint_len, write_varint};
 
 #[derive(Debug, Clone)]
 pub enum RecordValue {
@@ -14,7 +15,9 @@ pub enum RecordValue {
 }
 
 impl RecordValue {
-    pub fn from_type_and_data(col_type: u64, data: &[u8], offset: usize) -> Result<(Self, usize)> {
+    /// Decode the value of serial type `col_type` at `offset`, reading TEXT
+    /// in the database's text encoding. Returns it with its size in bytes.
+    pub fn from_type_and_data(col_type: u64, data: &[u8], offset: usize, encoding: TextEncoding) -> Result<(Self, usize)> {
         match col_type {
             0 => Ok((RecordValue::Null, 0)),
             1 => Self::read_int(data, offset, 1), // 8-bit twos-complement
@@ -48,8 +51,8 @@ impl RecordValue {
                 if offset + text_len > data.len() {
                     bail!("Not enough data for text");
                 }
-                let text = String::from_utf8_lossy(&data[offset..offset + text_len]);
-                Ok((RecordValue::Text(text.to_string()), text_len))
+                let text = decode_text(&data[offset..offset + text_len], encoding);
+                Ok((RecordValue::Text(text), text_len))
             }
             _ => bail!("Invalid column type: {}", col_type),
         }
@@ -66,8 +69,8 @@ impl RecordValue {
         padded[8 - size..].copy_from_slice(bytes);
 
         if !bytes.is_empty() && bytes[0] & 0x80 != 0 {
-            for i in 0..(8 - size) {
-                padded[i] = 0xFF;
+            for byte in padded.iter_mut().take(8 - size) {
+                *byte = 0xFF;
             }
         }
 
@@ -76,6 +79,113 @@ impl RecordValue {
     }
 }
 
+/// Lowest schema format number that allows serial types 8 and 9
+pub const SCHEMA_FORMAT_INT_CONSTANTS: u32 = 4;
+
+/// The database header settings that decide how values are encoded in a
+/// record. The default is the format of newly created databases.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordFormat {
+    /// Schema format number; from 4 on, 0 and 1 are header-only constants
+    pub schema_format: u32,
+    /// How TEXT values are stored
+    pub encoding: TextEncoding,
+}
+
+impl Default for RecordFormat {
+    fn default() -> Self {
+        Self { schema_format: SCHEMA_FORMAT_INT_CONSTANTS, encoding: TextEncoding::Utf8 }
+    }
+}
+
+impl RecordValue {
+    /// Serial type used to store this value in a record header. Integers get
+    /// the narrowest width that holds them, 0 and 1 are stored as
+    /// header-only constants when the schema format allows, and the length
+    /// of TEXT depends on the text encoding.
+    pub fn serial_type(&self, format: RecordFormat) -> u64 {
+        let constants = format.schema_format >= SCHEMA_FORMAT_INT_CONSTANTS;
+        match self {
+            RecordValue::Null => 0,
+            RecordValue::Int(0) | RecordValue::Zero if constants => 8,
+            RecordValue::Int(1) | RecordValue::One if constants => 9,
+            RecordValue::Int(i) => Self::int_serial_type(*i),
+            RecordValue::Zero | RecordValue::One => 1,
+            RecordValue::Float(_) => 7,
+            RecordValue::Blob(b) => 12 + 2 * b.len() as u64,
+            RecordValue::Text(t) => 13 + 2 * encoded_text_len(t, format.encoding) as u64,
+            RecordValue::Reserved(r) => *r,
+        }
+    }
+
+    fn int_serial_type(value: i64) -> u64 {
+        match value {
+            -0x80..=0x7F => 1,
+            -0x8000..=0x7FFF => 2,
+            -0x80_0000..=0x7F_FFFF => 3,
+            -0x8000_0000..=0x7FFF_FFFF => 4,
+            -0x8000_0000_0000..=0x7FFF_FFFF_FFFF => 5,
+            _ => 6,
+        }
+    }
+
+    /// Number of body bytes a value of the given serial type occupies
+    pub fn body_size(serial_type: u64) -> usize {
+        match serial_type {
+            1..=4 => serial_type as usize,
+            5 => 6,
+            6 | 7 => 8,
+            n if n >= 12 => ((n - 12) / 2) as usize,
+            _ => 0,
+        }
+    }
+
+    /// Append the body bytes of this value encoded as `serial_type`, which
+    /// should come from `serial_type`, storing TEXT in `encoding`
+    pub fn write_body(&self, serial_type: u64, encoding: TextEncoding, out: &mut Vec<u8>) {
+        match self {
+            RecordValue::Int(_) | RecordValue::Zero | RecordValue::One => {
+                let value = match self {
+                    RecordValue::Int(i) => *i,
+                    RecordValue::One => 1,
+                    _ => 0,
+                };
+                let size = Self::body_size(serial_type);
+                out.extend_from_slice(&value.to_be_bytes()[8 - size..]);
+            }
+            RecordValue::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+            RecordValue::Blob(b) => out.extend_from_slice(b),
+            RecordValue::Text(t) => encode_text(t, encoding, out),
+            RecordValue::Null | RecordValue::Reserved(_) => {}
+        }
+    }
+}
+
+/// Decode stored TEXT bytes; invalid sequences become U+FFFD
+fn decode_text(bytes: &[u8], encoding: TextEncoding) -> String {
+    let units = bytes.chunks_exact(2).map(|pair| [pair[0], pair[1]]);
+    match encoding {
+        TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+        TextEncoding::Utf16le => String::from_utf16_lossy(&units.map(u16::from_le_bytes).collect::<Vec<_>>()),
+        TextEncoding::Utf16be => String::from_utf16_lossy(&units.map(u16::from_be_bytes).collect::<Vec<_>>()),
+    }
+}
+
+fn encode_text(text: &str, encoding: TextEncoding, out: &mut Vec<u8>) {
+    match encoding {
+        TextEncoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+        TextEncoding::Utf16le => text.encode_utf16().for_each(|unit| out.extend_from_slice(&unit.to_le_bytes())),
+        TextEncoding::Utf16be => text.encode_utf16().for_each(|unit| out.extend_from_slice(&unit.to_be_bytes())),
+    }
+}
+
+fn encoded_text_len(text: &str, encoding: TextEncoding) -> usize {
+    match encoding {
+        TextEncoding::Utf8 => text.len(),
+        TextEncoding::Utf16le | TextEncoding::Utf16be => text.encode_utf16().count() * 2,
+    }
+}
+
 impl RecordValue {
     /// Format the record value for display
     pub fn to_display_string(&self) -> String {
@@ -123,27 +233,60 @@ impl RecordHeader {
 pub struct Record {
     pub header: RecordHeader,
     pub body: Vec<RecordValue>,
+    pub encoding: TextEncoding, // how TEXT values are stored
 }
 
 impl Record {
-    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+    /// Build a record from column values, computing the header that matches `format`
+    pub fn from_values(body: Vec<RecordValue>, format: RecordFormat) -> Self {
+        let column_types: Vec<u64> = body.iter().map(|v| v.serial_type(format)).collect();
+        let types_len: usize = column_types.iter().map(|&t| varint_len(t)).sum();
+
+        // The header size varint counts itself, so grow it until it is stable
+        let mut size = types_len + 1;
+        while types_len + varint_len(size as u64) > size {
+            size = types_len + varint_len(size as u64);
+        }
+
+        Record {
+            header: RecordHeader { size: size as u64, column_types },
+            body,
+            encoding: format.encoding,
+        }
+    }
+
+    /// Serialize the record into the on-disk format read by `from_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(self.header.size, &mut out);
+        for &col_type in &self.header.column_types {
+            write_varint(col_type, &mut out);
+        }
+        for (value, &col_type) in self.body.iter().zip(&self.header.column_types) {
+            value.write_body(col_type, self.encoding, &mut out);
+        }
+        out
+    }
+
+    /// Decode a record whose TEXT values are stored in `encoding`
+    pub fn from_bytes(data: &[u8], encoding: TextEncoding) -> Result<Self> {
         let header = RecordHeader::from_bytes(data)?;
         let mut body = Vec::new();
 
         let mut data_offset = header.size as usize;
 
         for &col_type in &header.column_types {
-            let (value, bytes_read) = RecordValue::from_type_and_data(col_type, data, data_offset)?;
+            let (value, bytes_read) = RecordValue::from_type_and_data(col_type, data, data_offset, encoding)?;
             body.push(value);
             data_offset += bytes_read;
         }
 
-        Ok(Record { header, body })
+        Ok(Record { header, body, encoding })
     }
 
     /// Get the table name from this record if it represents a table entry
     pub fn get_table_name(&self) -> Option<&str> {
-        if let Some(RecordValue::Text(t)) = self.body.get(0) {
+        if let Some(RecordValue::Text(t)) = self.body.first() {
             if t == "table" {
                 if let Some(RecordValue::Text(tbl)) = self.body.get(2) {
                     return Some(tbl);
@@ -172,4 +315,188 @@ impl Record {
             .ok_or_else(|| anyhow::anyhow!("Invalid or missing page number in record"))?;
         Ok(page_num)
     }
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_support::Rng;
+
+    fn round_trip(values: Vec<RecordValue>, schema_format: u32) -> (Vec<u64>, Vec<RecordValue>) {
+        let record = Record::from_values(values, RecordFormat { schema_format, ..RecordFormat::default() });
+        let bytes = record.to_bytes();
+        let decoded = Record::from_bytes(&bytes, TextEncoding::Utf8).unwrap();
+        assert_eq!(decoded.header.size, record.header.size);
+        (decoded.header.column_types, decoded.body)
+    }
+
+    fn int_value(value: &RecordValue) -> Option<i64> {
+        match value {
+            RecordValue::Int(i) => Some(*i),
+            RecordValue::Zero => Some(0),
+            RecordValue::One => Some(1),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn integers_round_trip_at_every_width() {
+        // (value, serial type, body bytes) at both ends of each width
+        let cases: [(i64, u64, usize); 24] = [
+            (0x7F, 1, 1),
+            (-0x80, 1, 1),
+            (0x80, 2, 2),
+            (-0x81, 2, 2),
+            (0x7FFF, 2, 2),
+            (-0x8000, 2, 2),
+            (0x8000, 3, 3),
+            (-0x8001, 3, 3),
+            (0x7F_FFFF, 3, 3),
+            (-0x80_0000, 3, 3),
+            (0x80_0000, 4, 4),
+            (-0x80_0001, 4, 4),
+            (0x7FFF_FFFF, 4, 4),
+            (-0x8000_0000, 4, 4),
+            (0x8000_0000, 5, 6),
+            (-0x8000_0001, 5, 6),
+            (0x7FFF_FFFF_FFFF, 5, 6),
+            (-0x8000_0000_0000, 5, 6),
+            (0x8000_0000_0000, 6, 8),
+            (-0x8000_0000_0001, 6, 8),
+            (i64::MAX, 6, 8),
+            (i64::MIN, 6, 8),
+            (2, 1, 1),
+            (-1, 1, 1),
+        ];
+        for (value, serial_type, size) in cases {
+            assert_eq!(RecordValue::body_size(serial_type), size);
+            let (types, body) = round_trip(vec![RecordValue::Int(value)], SCHEMA_FORMAT_INT_CONSTANTS);
+            assert_eq!(types, [serial_type], "serial type of {}", value);
+            assert_eq!(int_value(&body[0]), Some(value));
+        }
+    }
+
+    #[test]
+    fn zero_and_one_are_header_constants_from_schema_format_4() {
+        let values = || vec![RecordValue::Int(0), RecordValue::Int(1), RecordValue::Zero, RecordValue::One];
+
+        let (types, body) = round_trip(values(), 4);
+        assert_eq!(types, [8, 9, 8, 9]);
+        assert_eq!(body.iter().map(int_value).collect::<Vec<_>>(), [Some(0), Some(1), Some(0), Some(1)]);
+
+        let (types, body) = round_trip(values(), 3);
+        assert_eq!(types, [1, 1, 1, 1]);
+        assert_eq!(body.iter().map(int_value).collect::<Vec<_>>(), [Some(0), Some(1), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn real_text_blob_and_null_round_trip() {
+        let values = vec![
+            RecordValue::Float(-2.5),
+            RecordValue::Text("héllo".to_string()),
+            RecordValue::Text(String::new()),
+            RecordValue::Blob(vec![0, 1, 0xFF]),
+            RecordValue::Null,
+        ];
+        let (types, body) = round_trip(values, SCHEMA_FORMAT_INT_CONSTANTS);
+        assert_eq!(types, [7, 13 + 2 * 6, 13, 12 + 2 * 3, 0]);
+        assert!(matches!(body[0], RecordValue::Float(f) if f == -2.5));
+        assert!(matches!(&body[1], RecordValue::Text(t) if t == "héllo"));
+        assert!(matches!(&body[2], RecordValue::Text(t) if t.is_empty()));
+        assert!(matches!(&body[3], RecordValue::Blob(b) if b == &[0, 1, 0xFF]));
+        assert!(matches!(body[4], RecordValue::Null));
+    }
+
+    #[test]
+    fn header_size_counts_its_own_varint() {
+        // 127 one-byte serial types leave no room for a one-byte size, and
+        // the two-byte size is part of the header too
+        let record = Record::from_values(vec![RecordValue::Int(2); 127], RecordFormat::default());
+        assert_eq!(record.header.size, 129);
+        let (types, body) = round_trip(record.body, SCHEMA_FORMAT_INT_CONSTANTS);
+        assert_eq!(types.len(), 127);
+        assert!(body.iter().all(|value| int_value(value) == Some(2)));
+    }
+
+    #[test]
+    fn text_is_stored_in_the_format_encoding() {
+        let format = RecordFormat { encoding: TextEncoding::Utf16le, ..RecordFormat::default() };
+        let record = Record::from_values(vec![RecordValue::Text("hé".to_string())], format);
+        assert_eq!(record.header.column_types, [13 + 2 * 4]);
+
+        let decoded = Record::from_bytes(&record.to_bytes(), TextEncoding::Utf16le).unwrap();
+        assert!(matches!(&decoded.body[0], RecordValue::Text(t) if t == "hé"));
+    }
+
+    /// A value of a random storage class, often at the edge of a serial
+    /// type's range
+    fn random_value(rng: &mut Rng) -> RecordValue {
+        // Mostly short, sometimes long enough to need a multi-byte serial type
+        let len = |rng: &mut Rng| (if rng.below(10) == 0 { rng.below(5000) } else { rng.below(40) }) as usize;
+        match rng.below(5) {
+            0 => RecordValue::Null,
+            1 => {
+                let value = match rng.below(3) {
+                    // Next to a power of two, where the widths change
+                    0 => (1i64 << rng.below(64)).wrapping_add(rng.below(3) as i64 - 1),
+                    _ => (rng.next_u64() >> rng.below(64)) as i64,
+                };
+                RecordValue::Int(if rng.below(2) == 0 { value } else { value.wrapping_neg() })
+            }
+            2 => RecordValue::Float(match rng.below(3) {
+                0 => [0.0, -0.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, f64::MIN_POSITIVE, f64::MAX][rng.below(7) as usize],
+                _ => f64::from_bits(rng.next_u64()),
+            }),
+            3 => {
+                let len = len(rng);
+                let text = std::iter::repeat_with(|| match rng.below(2) {
+                    0 => char::from(b' ' + rng.below(95) as u8),
+                    _ => char::from_u32(rng.below(0x11_0000) as u32).unwrap_or('\u{FFFD}'),
+                });
+                RecordValue::Text(text.take(len).collect())
+            }
+            _ => {
+                let len = len(rng);
+                RecordValue::Blob(std::iter::repeat_with(|| rng.next_u64() as u8).take(len).collect())
+            }
+        }
+    }
+
+    #[test]
+    fn random_records_round_trip() {
+        let mut rng = Rng::new(0x5EED_0012);
+        let encodings = [TextEncoding::Utf8, TextEncoding::Utf16le, TextEncoding::Utf16be];
+        for _ in 0..2000 {
+            let format = RecordFormat { schema_format: 1 + rng.below(4) as u32, encoding: encodings[rng.below(3) as usize] };
+            let values: Vec<_> = (0..1 + rng.below(30)).map(|_| random_value(&mut rng)).collect();
+            let record = Record::from_values(values.clone(), format);
+            let decoded = Record::from_bytes(&record.to_bytes(), format.encoding).unwrap();
+            assert_eq!(decoded.header.size, record.header.size);
+            assert_eq!(decoded.header.column_types, record.header.column_types);
+
+            for ((value, serial_type), decoded) in values.iter().zip(&record.header.column_types).zip(&decoded.body) {
+                match (value, decoded) {
+                    (RecordValue::Int(value), decoded) => {
+                        assert_eq!(int_value(decoded), Some(*value));
+                        // The narrowest width that holds the value
+                        let fits = |bytes: usize| bytes == 8 || (-1i64 << (8 * bytes - 1) <= *value && *value < 1i64 << (8 * bytes - 1));
+                        match *serial_type {
+                            8 | 9 => assert!(format.schema_format >= SCHEMA_FORMAT_INT_CONSTANTS),
+                            1 => assert!(fits(1)),
+                            2..=6 => {
+                                let size = RecordValue::body_size(*serial_type);
+                                let narrower = RecordValue::body_size(*serial_type - 1);
+                                assert!(fits(size) && !fits(narrower), "{} stored as serial type {}", value, serial_type);
+                            }
+                            other => panic!("{} stored as serial type {}", value, other),
+                        }
+                    }
+                    (RecordValue::Float(value), RecordValue::Float(decoded)) => assert_eq!(value.to_bits(), decoded.to_bits()),
+                    (RecordValue::Text(value), RecordValue::Text(decoded)) => assert_eq!(value, decoded),
+                    (RecordValue::Blob(value), RecordValue::Blob(decoded)) => assert_eq!(value, decoded),
+                    (RecordValue::Null, RecordValue::Null) => {}
+                    (value, decoded) => panic!("{:?} decoded as {:?}", value, decoded),
+                }
+            }
+        }
+    }
+}
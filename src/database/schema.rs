@@ -1,49 +1,234 @@
 use anyhow::{bail, Result};
 
+use super::affinity::Affinity;
+use crate::query::parser::lexer::{tokenize, SpannedToken, Token};
+
+/// Keywords that start a table constraint rather than a column definition
+const TABLE_CONSTRAINT_KEYWORDS: &[&str] = &["CONSTRAINT", "PRIMARY", "UNIQUE", "CHECK", "FOREIGN"];
+
+/// Keywords that start a column constraint, ending the declared type
+const COLUMN_CONSTRAINT_KEYWORDS: &[&str] = &[
+    "CONSTRAINT", "PRIMARY", "NOT", "NULL", "UNIQUE", "CHECK", "DEFAULT", "COLLATE", "REFERENCES", "GENERATED", "AS",
+];
+
 #[derive(Debug, Clone)]
 pub struct ColumnInfo {
     pub name: String,
     pub index: usize,
     pub is_primary_key: bool,
+    /// Whether the column is another name for the rowid: the single-column
+    /// primary key of a rowid table, declared with type exactly `INTEGER`
+    pub is_rowid_alias: bool,
+    /// Declared type as written, e.g. `VARCHAR(20)`; empty when none was given
+    pub declared_type: String,
+    pub affinity: Affinity,
+    pub not_null: bool,
+    /// SQL text of the DEFAULT value, e.g. `'n/a'`, `-1` or `(datetime('now'))`
+    pub default_value: Option<String>,
+    pub collation: Option<String>,
+    pub is_unique: bool,
+    /// SQL text of the expression of each CHECK constraint on the column
+    pub checks: Vec<String>,
+    pub references: Option<ForeignKey>,
+}
+
+/// Target of a REFERENCES clause
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub table: String,
+    /// Referenced columns; empty means the parent table's primary key
+    pub columns: Vec<String>,
+}
+
+/// A constraint declared after the column definitions
+#[derive(Debug, Clone)]
+pub enum TableConstraint {
+    PrimaryKey { columns: Vec<IndexedColumn> },
+    Unique { columns: Vec<String> },
+    /// SQL text of the CHECK expression
+    Check(String),
+    ForeignKey { columns: Vec<String>, references: ForeignKey },
+}
+
+/// One column of an index key
+#[derive(Debug, Clone)]
+pub struct IndexedColumn {
+    pub name: String,
+    /// Set by an explicit COLLATE; otherwise the table column's collation applies
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+/// An index, parsed from its CREATE INDEX statement
+#[derive(Debug, Clone)]
+pub struct IndexSchema {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    /// SQL text of the WHERE clause of a partial index, which leaves rows out
+    pub where_clause: Option<String>,
+}
+
+impl IndexSchema {
+    /// Parse `CREATE [UNIQUE] INDEX [IF NOT EXISTS] [schema.]name ON table
+    /// (indexed-column, ...) [WHERE expr]`. Indexes on expressions are not
+    /// supported.
+    pub fn from_create_sql(sql: &str) -> Result<Self> {
+        let tokens = tokenize(sql)?;
+        let mut parser = DefinitionParser::new(sql, &tokens);
+        parser.expect_keyword("CREATE")?;
+        let unique = parser.eat_keyword("UNIQUE");
+        parser.expect_keyword("INDEX")?;
+        if parser.eat_keyword("IF") {
+            parser.expect_keyword("NOT")?;
+            parser.expect_keyword("EXISTS")?;
+        }
+        let mut name = parser.name()?;
+        if parser.peek() == Some(&Token::Dot) {
+            parser.next();
+            name = parser.name()?;
+        }
+        parser.expect_keyword("ON")?;
+        let table = parser.name()?;
+        let columns = parser.indexed_columns()?;
+
+        let where_clause = if parser.eat_keyword("WHERE") {
+            match (tokens.get(parser.pos), tokens.last()) {
+                (Some(first), Some(last)) => Some(sql[first.start..last.end].trim_end_matches(';').trim().to_string()),
+                _ => bail!("Expected an expression after WHERE: {}", sql),
+            }
+        } else {
+            None
+        };
+        Ok(IndexSchema { name, table, unique, columns, where_clause })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct TableSchema {
     pub columns: Vec<ColumnInfo>,
+    pub constraints: Vec<TableConstraint>,
+    /// The primary key in key order, from a column or a table constraint
+    pub primary_key: Vec<IndexedColumn>,
+    pub without_rowid: bool,
+    pub strict: bool,
 }
 
 impl TableSchema {
+    /// Parse `CREATE TABLE name (column-def, ..., [table-constraint, ...]) [table-options]`
     pub fn from_create_sql(sql: &str) -> Result<Self> {
-        // Extract column definitions between parentheses
-        let start = sql
-            .find('(')
-            .ok_or_else(|| anyhow::anyhow!("No opening parenthesis found in CREATE TABLE statement"))?;
-        let end = sql
-            .rfind(')')
-            .ok_or_else(|| anyhow::anyhow!("No closing parenthesis found in CREATE TABLE statement"))?;
+        let tokens = tokenize(sql)?;
+        let mut pos = 0;
+        let keyword_at = |pos: usize, keyword: &str| tokens.get(pos).is_some_and(|t| t.token.is_keyword(keyword));
 
-        if start >= end {
-            bail!("Invalid parentheses in CREATE TABLE statement");
+        if !keyword_at(pos, "CREATE") {
+            bail!("Not a CREATE TABLE statement: {}", sql);
+        }
+        pos += 1;
+        if keyword_at(pos, "TEMP") || keyword_at(pos, "TEMPORARY") {
+            pos += 1;
+        }
+        if keyword_at(pos, "VIRTUAL") {
+            bail!("Virtual tables are not supported: {}", sql);
+        }
+        if !keyword_at(pos, "TABLE") {
+            bail!("Not a CREATE TABLE statement: {}", sql);
+        }
+        pos += 1;
+        if keyword_at(pos, "IF") && keyword_at(pos + 1, "NOT") && keyword_at(pos + 2, "EXISTS") {
+            pos += 3;
+        }
+        // [schema.]table
+        pos += 1;
+        if tokens.get(pos).is_some_and(|t| t.token == Token::Dot) {
+            pos += 2;
+        }
+        if tokens.get(pos).map(|t| &t.token) != Some(&Token::LeftParen) {
+            bail!("No column list found in CREATE TABLE statement: {}", sql);
         }
 
-        let columns_def = &sql[start + 1..end];
-        let column_parts: Vec<&str> = columns_def.split(',').collect();
+        // Split the definitions at top-level commas up to the closing parenthesis
+        let mut definitions = Vec::new();
+        let mut depth = 0;
+        let mut definition_start = pos + 1;
+        let body_end = loop {
+            match tokens.get(pos).map(|t| &t.token) {
+                Some(Token::LeftParen) => depth += 1,
+                Some(Token::RightParen) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        definitions.push(&tokens[definition_start..pos]);
+                        break pos;
+                    }
+                }
+                Some(Token::Comma) if depth == 1 => {
+                    definitions.push(&tokens[definition_start..pos]);
+                    definition_start = pos + 1;
+                }
+                Some(_) => {}
+                None => bail!("Unbalanced parentheses in CREATE TABLE statement: {}", sql),
+            }
+            pos += 1;
+        };
 
-        let mut columns = Vec::new();
-        for (index, part) in column_parts.iter().enumerate() {
-            let trimmed = part.trim();
-            let words: Vec<&str> = trimmed.split_whitespace().collect();
-            if !words.is_empty() {
-                let is_primary_key = trimmed.to_lowercase().contains("primary key");
-                columns.push(ColumnInfo {
-                    name: words[0].to_string(),
-                    index,
-                    is_primary_key,
-                });
+        let mut schema = TableSchema { columns: Vec::new(), constraints: Vec::new(), primary_key: Vec::new(), without_rowid: false, strict: false };
+        for definition in definitions {
+            let Some(first) = definition.first() else {
+                bail!("Empty definition in CREATE TABLE statement: {}", sql);
+            };
+            let is_table_constraint = TABLE_CONSTRAINT_KEYWORDS.iter().any(|keyword| first.token.is_keyword(keyword));
+            if is_table_constraint || !schema.constraints.is_empty() {
+                schema.constraints.push(DefinitionParser::new(sql, definition).table_constraint()?);
+            } else {
+                let index = schema.columns.len();
+                let (column, key_column) = DefinitionParser::new(sql, definition).column(index)?;
+                schema.columns.push(column);
+                schema.primary_key.extend(key_column);
             }
         }
 
-        Ok(TableSchema { columns })
+        // Table options: WITHOUT ROWID and STRICT, separated by commas
+        for option in tokens[body_end + 1..].split(|t| t.token == Token::Comma) {
+            match option {
+                [] => {}
+                [without, rowid] if without.token.is_keyword("WITHOUT") && rowid.token.is_keyword("ROWID") => {
+                    schema.without_rowid = true;
+                }
+                [strict] if strict.token.is_keyword("STRICT") => schema.strict = true,
+                [semicolon] if semicolon.token == Token::Semicolon => {}
+                _ => bail!("Unsupported table option in CREATE TABLE statement: {}", sql),
+            }
+        }
+
+        // Columns named in a table-level PRIMARY KEY are part of the key too
+        for constraint in &schema.constraints {
+            if let TableConstraint::PrimaryKey { columns } = constraint {
+                for column in &mut schema.columns {
+                    if columns.iter().any(|key| key.name.eq_ignore_ascii_case(&column.name)) {
+                        column.is_primary_key = true;
+                        column.is_rowid_alias = columns.len() == 1 && column.declared_type.eq_ignore_ascii_case("INTEGER");
+                    }
+                }
+                schema.primary_key = columns.clone();
+            }
+        }
+        // Only a single-column key of a rowid table can alias the rowid
+        if schema.without_rowid || schema.primary_key().len() != 1 {
+            for column in &mut schema.columns {
+                column.is_rowid_alias = false;
+            }
+        }
+        // In a STRICT table, ANY columns keep values exactly as given
+        if schema.strict {
+            for column in &mut schema.columns {
+                if column.declared_type.eq_ignore_ascii_case("ANY") {
+                    column.affinity = Affinity::Blob;
+                }
+            }
+        }
+
+        Ok(schema)
     }
 
     pub fn get_column_index(&self, name: &str) -> Option<usize> {
@@ -52,4 +237,339 @@ impl TableSchema {
             .find(|col| col.name.eq_ignore_ascii_case(name))
             .map(|col| col.index)
     }
-} 
\ No newline at end of file
+
+    /// The column that aliases the rowid, if there is one
+    pub fn rowid_alias(&self) -> Option<&ColumnInfo> {
+        self.columns.iter().find(|col| col.is_rowid_alias)
+    }
+
+    /// Declaration indexes of the columns in the order a WITHOUT ROWID table
+    /// stores them: the primary key columns in key order, then the rest in
+    /// declaration order
+    pub fn storage_order(&self) -> Vec<usize> {
+        let mut order: Vec<usize> = Vec::new();
+        for name in self.primary_key() {
+            if let Some(index) = self.get_column_index(&name) {
+                if !order.contains(&index) {
+                    order.push(index);
+                }
+            }
+        }
+        order.extend(self.columns.iter().map(|col| col.index).filter(|index| !order.contains(index)).collect::<Vec<_>>());
+        order
+    }
+
+    /// Names of the primary key columns, in key order
+    pub fn primary_key(&self) -> Vec<String> {
+        self.primary_key.iter().map(|key| key.name.clone()).collect()
+    }
+
+    /// The collation a key column compares with: its own COLLATE, else the
+    /// table column's, with `None` meaning BINARY
+    fn key_collation<'a>(&'a self, key: &'a IndexedColumn) -> Option<&'a str> {
+        key.collation.as_deref().or_else(|| {
+            self.columns.iter().find(|col| col.name.eq_ignore_ascii_case(&key.name)).and_then(|col| col.collation.as_deref())
+        })
+    }
+
+    /// The columns an index's entries hold: the indexed ones, then for a
+    /// WITHOUT ROWID table each primary key column the index does not hold
+    /// already with the same collation. Those locate the entry's row, as
+    /// the rowid that ends the entries of other indexes does.
+    pub fn index_key_columns(&self, index: &IndexSchema) -> Vec<IndexedColumn> {
+        let mut columns = index.columns.clone();
+        if self.without_rowid {
+            for key in &self.primary_key {
+                if !index.columns.iter().any(|column| self.same_key_column(column, key)) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+        columns
+    }
+
+    /// Where each primary key column sits among the `key_columns` of an
+    /// index entry, as given by [`index_key_columns`](Self::index_key_columns)
+    pub fn primary_key_positions(&self, key_columns: &[IndexedColumn]) -> Vec<usize> {
+        self.primary_key
+            .iter()
+            .filter_map(|key| key_columns.iter().position(|column| self.same_key_column(column, key)))
+            .collect()
+    }
+
+    /// Whether two key columns hold the same column compared the same way
+    fn same_key_column(&self, a: &IndexedColumn, b: &IndexedColumn) -> bool {
+        let binary = |collation: Option<&str>| collation.unwrap_or("BINARY").to_ascii_uppercase();
+        a.name.eq_ignore_ascii_case(&b.name) && binary(self.key_collation(a)) == binary(self.key_collation(b))
+    }
+}
+
+/// Parser for one comma-separated definition inside CREATE TABLE: a column
+/// definition `name [type-name] [column-constraint ...]` or a table constraint
+struct DefinitionParser<'a> {
+    sql: &'a str,
+    tokens: &'a [SpannedToken],
+    pos: usize,
+}
+
+impl<'a> DefinitionParser<'a> {
+    fn new(sql: &'a str, tokens: &'a [SpannedToken]) -> Self {
+        DefinitionParser { sql, tokens, pos: 0 }
+    }
+
+    /// The SQL text of the whole definition, for error messages
+    fn text(&self) -> &'a str {
+        match (self.tokens.first(), self.tokens.last()) {
+            (Some(first), Some(last)) => &self.sql[first.start..last.end],
+            _ => "",
+        }
+    }
+
+    /// A column definition, and the column as a primary key column if it
+    /// has a PRIMARY KEY constraint
+    fn column(mut self, index: usize) -> Result<(ColumnInfo, Option<IndexedColumn>)> {
+        let name = self.name()?;
+        let declared_type = self.type_name();
+        let mut column = ColumnInfo {
+            name,
+            index,
+            is_primary_key: false,
+            is_rowid_alias: false,
+            affinity: Affinity::from_declared_type(&declared_type),
+            declared_type,
+            not_null: false,
+            default_value: None,
+            collation: None,
+            is_unique: false,
+            checks: Vec::new(),
+            references: None,
+        };
+        let mut key_column = None;
+
+        while let Some(token) = self.next() {
+            if token.is_keyword("CONSTRAINT") {
+                self.name()?;
+            } else if token.is_keyword("PRIMARY") {
+                self.expect_keyword("KEY")?;
+                let descending = !self.eat_keyword("ASC") && self.eat_keyword("DESC");
+                self.conflict_clause();
+                self.eat_keyword("AUTOINCREMENT");
+                column.is_primary_key = true;
+                key_column = Some(IndexedColumn { name: column.name.clone(), collation: None, descending });
+                // A quirk kept for compatibility: INTEGER PRIMARY KEY DESC does not alias the rowid
+                column.is_rowid_alias = column.declared_type.eq_ignore_ascii_case("INTEGER") && !descending;
+            } else if token.is_keyword("NOT") {
+                self.expect_keyword("NULL")?;
+                self.conflict_clause();
+                column.not_null = true;
+            } else if token.is_keyword("UNIQUE") {
+                self.conflict_clause();
+                column.is_unique = true;
+            } else if token.is_keyword("CHECK") {
+                column.checks.push(self.parenthesized()?.to_string());
+            } else if token.is_keyword("DEFAULT") {
+                column.default_value = Some(self.default_value()?);
+            } else if token.is_keyword("COLLATE") {
+                column.collation = Some(self.name()?);
+            } else if token.is_keyword("REFERENCES") {
+                column.references = Some(self.foreign_key_clause()?);
+            } else if token.is_keyword("AS") {
+                // Generated column: AS (expr) [STORED | VIRTUAL]
+                self.parenthesized()?;
+                let _ = self.eat_keyword("STORED") || self.eat_keyword("VIRTUAL");
+            }
+            // NULL, GENERATED ALWAYS and anything unrecognised carry no information
+        }
+
+        Ok((column, key_column))
+    }
+
+    /// `[CONSTRAINT name] PRIMARY KEY (...) | UNIQUE (...) | CHECK (...) |
+    /// FOREIGN KEY (...) REFERENCES ...`
+    fn table_constraint(mut self) -> Result<TableConstraint> {
+        if self.eat_keyword("CONSTRAINT") {
+            self.name()?;
+        }
+        let constraint = if self.eat_keyword("PRIMARY") {
+            self.expect_keyword("KEY")?;
+            let columns = self.indexed_columns()?;
+            self.conflict_clause();
+            TableConstraint::PrimaryKey { columns }
+        } else if self.eat_keyword("UNIQUE") {
+            let columns = self.column_names()?;
+            self.conflict_clause();
+            TableConstraint::Unique { columns }
+        } else if self.eat_keyword("CHECK") {
+            TableConstraint::Check(self.parenthesized()?.to_string())
+        } else if self.eat_keyword("FOREIGN") {
+            self.expect_keyword("KEY")?;
+            let columns = self.column_names()?;
+            self.expect_keyword("REFERENCES")?;
+            TableConstraint::ForeignKey { columns, references: self.foreign_key_clause()? }
+        } else {
+            bail!("Unsupported table constraint: {}", self.text());
+        };
+
+        if self.pos < self.tokens.len() {
+            bail!("Unexpected text after table constraint: {}", self.text());
+        }
+        Ok(constraint)
+    }
+
+    /// `(name [COLLATE collation] [ASC | DESC], ...)`
+    fn indexed_columns(&mut self) -> Result<Vec<IndexedColumn>> {
+        if self.next() != Some(&Token::LeftParen) {
+            bail!("Expected a column list: {}", self.text());
+        }
+        let mut columns = Vec::new();
+        loop {
+            let name = self.name()?;
+            let collation = if self.eat_keyword("COLLATE") { Some(self.name()?) } else { None };
+            let descending = self.eat_keyword("DESC");
+            if !descending {
+                self.eat_keyword("ASC");
+            }
+            columns.push(IndexedColumn { name, collation, descending });
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(Token::RightParen) => return Ok(columns),
+                _ => bail!("Only plain columns are supported in a column list: {}", self.text()),
+            }
+        }
+    }
+
+    /// Names of the columns in a column list
+    fn column_names(&mut self) -> Result<Vec<String>> {
+        Ok(self.indexed_columns()?.into_iter().map(|column| column.name).collect())
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().is_some_and(|t| t.is_keyword(keyword)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            bail!("Expected {} in definition: {}", keyword, self.text());
+        }
+        Ok(())
+    }
+
+    fn is_constraint_start(token: &Token) -> bool {
+        COLUMN_CONSTRAINT_KEYWORDS.iter().any(|keyword| token.is_keyword(keyword))
+    }
+
+    /// An identifier, which may be quoted
+    fn name(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Identifier(name) | Token::QuotedIdentifier(name) | Token::String(name)) => Ok(name.clone()),
+            _ => bail!("Expected a name in definition: {}", self.text()),
+        }
+    }
+
+    /// `name ... [(number [, number])]`, as written
+    fn type_name(&mut self) -> String {
+        let start = self.pos;
+        while matches!(self.peek(), Some(token @ Token::Identifier(_)) if !Self::is_constraint_start(token)) {
+            self.pos += 1;
+        }
+        if self.pos > start && self.peek() == Some(&Token::LeftParen) {
+            let _ = self.parenthesized();
+        }
+        if self.pos == start {
+            return String::new();
+        }
+        self.sql[self.tokens[start].start..self.tokens[self.pos - 1].end].to_string()
+    }
+
+    /// The SQL text inside a parenthesised group, which may itself contain parentheses
+    fn parenthesized(&mut self) -> Result<&'a str> {
+        if self.next() != Some(&Token::LeftParen) {
+            bail!("Expected ( in definition: {}", self.text());
+        }
+        let open = self.pos - 1;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::LeftParen) => depth += 1,
+                Some(Token::RightParen) => depth -= 1,
+                Some(_) => {}
+                None => bail!("Unbalanced parentheses in definition: {}", self.text()),
+            }
+        }
+        Ok(self.sql[self.tokens[open].end..self.tokens[self.pos - 1].start].trim())
+    }
+
+    /// `DEFAULT` value: a literal, a signed number, a bare word such as
+    /// CURRENT_TIMESTAMP, or a parenthesised expression
+    fn default_value(&mut self) -> Result<String> {
+        if self.peek() == Some(&Token::LeftParen) {
+            let start = self.tokens[self.pos].start;
+            self.parenthesized()?;
+            return Ok(self.sql[start..self.tokens[self.pos - 1].end].to_string());
+        }
+        let start = self.pos;
+        if matches!(self.peek(), Some(Token::Plus | Token::Minus)) {
+            self.pos += 1;
+        }
+        if self.next().is_none() {
+            bail!("Expected a value after DEFAULT: {}", self.text());
+        }
+        Ok(self.sql[self.tokens[start].start..self.tokens[self.pos - 1].end].to_string())
+    }
+
+    /// `[ON CONFLICT resolution]` after a constraint
+    fn conflict_clause(&mut self) {
+        if self.peek().is_some_and(|t| t.is_keyword("ON"))
+            && self.tokens.get(self.pos + 1).is_some_and(|t| t.token.is_keyword("CONFLICT"))
+        {
+            self.pos += 3;
+        }
+    }
+
+    /// `REFERENCES table [(columns)]` followed by any ON DELETE / ON UPDATE,
+    /// MATCH and DEFERRABLE clauses, which are skipped
+    fn foreign_key_clause(&mut self) -> Result<ForeignKey> {
+        let table = self.name()?;
+        let mut columns = Vec::new();
+        if self.peek() == Some(&Token::LeftParen) {
+            columns = self.column_names()?;
+        }
+        loop {
+            if self.eat_keyword("ON") {
+                // ON DELETE | UPDATE SET NULL | SET DEFAULT | CASCADE | RESTRICT | NO ACTION
+                self.pos += 1;
+                let two_words = self.peek().is_some_and(|t| t.is_keyword("SET") || t.is_keyword("NO"));
+                self.pos += if two_words { 2 } else { 1 };
+            } else if self.eat_keyword("MATCH") {
+                self.name()?;
+            } else if self.eat_keyword("DEFERRABLE")
+                || (self.peek().is_some_and(|t| t.is_keyword("NOT"))
+                    && self.tokens.get(self.pos + 1).is_some_and(|t| t.token.is_keyword("DEFERRABLE")))
+            {
+                self.eat_keyword("NOT");
+                self.eat_keyword("DEFERRABLE");
+                if self.eat_keyword("INITIALLY") {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        Ok(ForeignKey { table, columns })
+    }
+}
@@ -0,0 +1,232 @@
+//! Building blocks for tests that work on small database images in memory
+
+use anyhow::Result;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::btree_page::BTreePage;
+use super::header::{DatabaseHeader, TextEncoding};
+use super::database::{INTERIOR_INDEX_PAGE, LEAF_INDEX_PAGE, LEAF_TABLE_PAGE};
+use super::page_source::{MemorySource, Page, PageSource};
+use super::record::{Record, RecordFormat, RecordValue};
+use super::varint::write_varint;
+use super::Database;
+
+/// A small seeded pseudo-random generator (SplitMix64), so randomized tests
+/// repeat exactly from run to run
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`
+    pub(crate) fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+/// A [`MemorySource`] that stays reachable after a `Database` takes it
+/// over, so what one connection committed can be reopened by another
+#[derive(Clone)]
+pub(crate) struct SharedMemory(Rc<RefCell<MemorySource>>);
+
+impl SharedMemory {
+    pub(crate) fn new(image: Vec<u8>) -> Self {
+        SharedMemory(Rc::new(RefCell::new(MemorySource::new(image))))
+    }
+
+    /// Open a fresh connection to the image
+    pub(crate) fn open(&self) -> Result<Database> {
+        Database::from_source(self.clone())
+    }
+
+    /// A copy of the whole image as it is now
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        let mut source = self.0.borrow_mut();
+        let size = source.size().unwrap() as usize;
+        source.read_at(0, size).unwrap().to_vec()
+    }
+}
+
+impl PageSource for SharedMemory {
+    fn size(&self) -> Result<u64> {
+        self.0.borrow().size()
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+        self.0.borrow_mut().read_at(offset, len)
+    }
+
+    fn is_zero_copy(&self) -> bool {
+        self.0.borrow().is_zero_copy()
+    }
+
+    fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        self.0.borrow_mut().write_at(offset, data)
+    }
+}
+
+/// A database image with `page_size`-byte pages holding an empty table for
+/// each `(name, sql)` pair, rooted at pages 2, 3, ... in order
+pub(crate) fn image_with_tables(page_size: usize, tables: &[(&str, &str)]) -> Vec<u8> {
+    image_with_encoding(page_size, TextEncoding::Utf8, tables)
+}
+
+/// Like [`image_with_tables`], storing text in `encoding`
+pub(crate) fn image_with_encoding(page_size: usize, encoding: TextEncoding, tables: &[(&str, &str)]) -> Vec<u8> {
+    let page_count = tables.len() + 1;
+    let mut image = vec![0; page_size * page_count];
+
+    let header = &mut image[..100];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    header[16..18].copy_from_slice(&(page_size as u16).to_be_bytes());
+    header[18] = 1;
+    header[19] = 1;
+    header[21..24].copy_from_slice(&[64, 32, 32]);
+    header[24..28].copy_from_slice(&1u32.to_be_bytes());
+    header[28..32].copy_from_slice(&(page_count as u32).to_be_bytes());
+    header[40..44].copy_from_slice(&1u32.to_be_bytes());
+    header[44..48].copy_from_slice(&4u32.to_be_bytes());
+    let encoding_number: u32 = match encoding {
+        TextEncoding::Utf8 => 1,
+        TextEncoding::Utf16le => 2,
+        TextEncoding::Utf16be => 3,
+    };
+    header[56..60].copy_from_slice(&encoding_number.to_be_bytes());
+    header[92..96].copy_from_slice(&1u32.to_be_bytes());
+
+    let mut schema = BTreePage::new(LEAF_TABLE_PAGE);
+    for (i, (name, sql)) in tables.iter().enumerate() {
+        let values = vec![
+            RecordValue::Text("table".to_string()),
+            RecordValue::Text(name.to_string()),
+            RecordValue::Text(name.to_string()),
+            RecordValue::Int(i as i64 + 2),
+            RecordValue::Text(sql.to_string()),
+        ];
+        let payload = Record::from_values(values, RecordFormat { encoding, ..RecordFormat::default() }).to_bytes();
+        let mut cell = Vec::new();
+        write_varint(payload.len() as u64, &mut cell);
+        write_varint(i as u64 + 1, &mut cell);
+        cell.extend_from_slice(&payload);
+        schema.cells.push(cell);
+    }
+    schema.write_to(&mut image[..page_size], 100, page_size).unwrap();
+
+    for page in image[page_size..].chunks_mut(page_size) {
+        BTreePage::new(LEAF_TABLE_PAGE).write_to(page, 0, page_size).unwrap();
+    }
+    image
+}
+
+/// Add an index to an image made by [`image_with_tables`], holding
+/// `entries` (each the indexed values followed by the rowid, or by the
+/// primary key on a WITHOUT ROWID table), which must be in index order
+pub(crate) fn add_index(image: &mut Vec<u8>, page_size: usize, (name, table, sql): (&str, &str, &str), entries: Vec<Vec<RecordValue>>) {
+    let root = append_index_tree(image, page_size, entries);
+    add_schema_entry(image, page_size, ["index", name, table], root, sql);
+}
+
+/// Add a WITHOUT ROWID table to an image made by [`image_with_tables`],
+/// holding `rows` (each the primary key columns first), which must be in
+/// primary key order
+pub(crate) fn add_without_rowid_table(image: &mut Vec<u8>, page_size: usize, (name, sql): (&str, &str), rows: Vec<Vec<RecordValue>>) {
+    let root = append_index_tree(image, page_size, rows);
+    add_schema_entry(image, page_size, ["table", name, name], root, sql);
+}
+
+/// Append an index B-tree holding `entries` to the image, the leaves first
+/// and the root last, and return the root's page number
+fn append_index_tree(image: &mut Vec<u8>, page_size: usize, entries: Vec<Vec<RecordValue>>) -> usize {
+    let format = image_format(image);
+    let cells = entries.into_iter().map(|entry| {
+        let payload = Record::from_values(entry, format).to_bytes();
+        let mut cell = Vec::new();
+        write_varint(payload.len() as u64, &mut cell);
+        cell.extend_from_slice(&payload);
+        cell
+    });
+
+    // Every entry is stored once, so the one between two pages moves up a
+    // level, where it points at the page before it
+    let mut level = vec![BTreePage::new(LEAF_INDEX_PAGE)];
+    let mut dividers = Vec::new();
+    for cell in cells {
+        let page = level.last_mut().unwrap();
+        if page.used_space(0) + cell.len() + 2 <= page_size {
+            page.cells.push(cell);
+        } else {
+            dividers.push(cell);
+            level.push(BTreePage::new(LEAF_INDEX_PAGE));
+        }
+    }
+
+    let root = loop {
+        let first_page = image.len() / page_size + 1;
+        for page in &level {
+            let start = image.len();
+            image.resize(start + page_size, 0);
+            page.write_to(&mut image[start..], 0, page_size).unwrap();
+        }
+        if level.len() == 1 {
+            break first_page;
+        }
+
+        let children = (first_page..first_page + level.len()).map(|page| page as u32);
+        let mut parents = vec![BTreePage::new(INTERIOR_INDEX_PAGE)];
+        let mut parent_dividers = Vec::new();
+        for (child, divider) in children.zip(std::mem::take(&mut dividers)) {
+            let page = parents.last_mut().unwrap();
+            let mut cell = child.to_be_bytes().to_vec();
+            cell.extend_from_slice(&divider);
+            if page.used_space(0) + cell.len() + 2 <= page_size {
+                page.cells.push(cell);
+            } else {
+                page.right_child = Some(child);
+                parent_dividers.push(divider);
+                parents.push(BTreePage::new(INTERIOR_INDEX_PAGE));
+            }
+        }
+        parents.last_mut().unwrap().right_child = Some((first_page + level.len() - 1) as u32);
+        level = parents;
+        dividers = parent_dividers;
+    };
+    let page_count = (image.len() / page_size) as u32;
+    image[28..32].copy_from_slice(&page_count.to_be_bytes());
+    root
+}
+
+/// Add a row to the schema table on page 1
+fn add_schema_entry(image: &mut [u8], page_size: usize, [kind, name, table]: [&str; 3], root: usize, sql: &str) {
+    let mut schema = BTreePage::parse(&image[..page_size], 100, page_size).unwrap();
+    let values = vec![
+        RecordValue::Text(kind.to_string()),
+        RecordValue::Text(name.to_string()),
+        RecordValue::Text(table.to_string()),
+        RecordValue::Int(root as i64),
+        RecordValue::Text(sql.to_string()),
+    ];
+    let payload = Record::from_values(values, image_format(image)).to_bytes();
+    let mut cell = Vec::new();
+    write_varint(payload.len() as u64, &mut cell);
+    write_varint(schema.cells.len() as u64 + 1, &mut cell);
+    cell.extend_from_slice(&payload);
+    schema.cells.push(cell);
+    schema.write_to(&mut image[..page_size], 100, page_size).unwrap();
+}
+
+/// The format records take in an image, as its header gives it
+fn image_format(image: &[u8]) -> RecordFormat {
+    let header = DatabaseHeader::parse(&image[..100]).unwrap();
+    RecordFormat { schema_format: header.schema_format, encoding: header.text_encoding }
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use std::ops::{Deref, DerefMut};
+
+use super::database::Database;
+
+/// An open transaction on a `Database`, created by `Database::transaction`.
+/// It derefs to the database so reads and writes go through it, and rolls
+/// the changes back when dropped without calling `commit`.
+pub struct Transaction<'a> {
+    db: &'a mut Database,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(db: &'a mut Database) -> Self {
+        Self { db, finished: false }
+    }
+
+    /// Make the changes permanent. If that fails they are rolled back.
+    pub fn commit(mut self) -> Result<()> {
+        self.db.commit()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Throw the changes away
+    pub fn rollback(mut self) {
+        self.finished = true;
+        self.db.rollback();
+    }
+}
+
+impl Deref for Transaction<'_> {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        self.db
+    }
+}
+
+impl DerefMut for Transaction<'_> {
+    fn deref_mut(&mut self) -> &mut Database {
+        self.db
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.db.rollback();
+        }
+    }
+}
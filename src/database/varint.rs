@@ -29,4 +29,109 @@ pub fn read_varint(data: &[u8], offset: usize) -> Result<(u64, usize)> {
     }
     
     Ok((value, bytes_read))
-} 
\ No newline at end of file
+} 
+/// Append `value` to `out` as a variable-length integer
+/// Returns the number of bytes written
+pub fn write_varint(value: u64, out: &mut Vec<u8>) -> usize {
+    if value > 0x00FF_FFFF_FFFF_FFFF {
+        // 9 bytes: eight 7-bit groups followed by a full 8-bit byte
+        let mut bytes = [0u8; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest & 0x7F) as u8 | 0x80;
+            rest >>= 7;
+        }
+        out.extend_from_slice(&bytes);
+        return 9;
+    }
+
+    let mut groups = [0u8; 8];
+    let mut len = 0;
+    let mut rest = value;
+    loop {
+        groups[len] = (rest & 0x7F) as u8;
+        len += 1;
+        rest >>= 7;
+        if rest == 0 {
+            break;
+        }
+    }
+
+    // High-order group first, with the continuation bit on all but the last byte
+    for i in (0..len).rev() {
+        let continuation = if i > 0 { 0x80 } else { 0 };
+        out.push(groups[i] | continuation);
+    }
+    len
+}
+
+/// Number of bytes `write_varint` uses for `value`
+pub fn varint_len(value: u64) -> usize {
+    match value {
+        v if v > 0x00FF_FFFF_FFFF_FFFF => 9,
+        v => (64 - v.leading_zeros() as usize).max(1).div_ceil(7),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_support::Rng;
+
+    #[test]
+    fn round_trips_at_every_length_boundary() {
+        // The largest value of each length and the smallest of the next one
+        let cases: [(u64, usize); 18] = [
+            (0, 1),
+            (0x7F, 1),
+            (0x80, 2),
+            (0x3FFF, 2),
+            (0x4000, 3),
+            (0x1F_FFFF, 3),
+            (0x20_0000, 4),
+            (0x0FFF_FFFF, 4),
+            (0x1000_0000, 5),
+            (0x07_FFFF_FFFF, 5),
+            (0x08_0000_0000, 6),
+            (0x03FF_FFFF_FFFF, 6),
+            (0x0400_0000_0000, 7),
+            (0x01_FFFF_FFFF_FFFF, 7),
+            (0x02_0000_0000_0000, 8),
+            (0x00FF_FFFF_FFFF_FFFF, 8),
+            (0x0100_0000_0000_0000, 9),
+            (u64::MAX, 9),
+        ];
+        for (value, len) in cases {
+            // Start past a prefix and leave a trailing byte to check the reader stops in time
+            let mut out = vec![0xFF];
+            assert_eq!(write_varint(value, &mut out), len, "length written for {:#x}", value);
+            assert_eq!(varint_len(value), len, "varint_len of {:#x}", value);
+            out.push(0xFF);
+            assert_eq!(read_varint(&out, 1).unwrap(), (value, len), "round trip of {:#x}", value);
+        }
+    }
+
+    #[test]
+    fn random_values_round_trip() {
+        let mut rng = Rng::new(0x5EED_0006);
+        for _ in 0..20_000 {
+            // Shifting by a random amount makes every length about as likely
+            let value = rng.next_u64() >> rng.below(64);
+            // Seven bits per byte, except that the ninth byte holds eight
+            let bits = 64 - value.leading_zeros() as usize;
+            let len = if bits > 56 { 9 } else { bits.div_ceil(7).max(1) };
+
+            let mut out = vec![0xFF];
+            assert_eq!(write_varint(value, &mut out), len, "length written for {:#x}", value);
+            assert_eq!(varint_len(value), len, "varint_len of {:#x}", value);
+            out.push(0xFF);
+            assert_eq!(read_varint(&out, 1).unwrap(), (value, len), "round trip of {:#x}", value);
+        }
+    }
+
+    #[test]
+    fn truncated_varint_is_an_error() {
+        assert!(read_varint(&[0x81, 0x80], 0).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use super::page_source::{Page, PageSource};
+
+/// WAL header magic; the low bit selects big-endian checksum words
+const WAL_MAGIC_LE: u32 = 0x377f0682;
+const WAL_MAGIC_BE: u32 = 0x377f0683;
+const WAL_HEADER_SIZE: usize = 32;
+const WAL_FRAME_HEADER_SIZE: usize = 24;
+
+/// Path of the write-ahead log belonging to a database file
+pub fn wal_path(db_path: &str) -> String {
+    format!("{}-wal", db_path)
+}
+
+/// Serves pages of a WAL-mode database: the latest committed frame of a
+/// page in the `-wal` file wins over the copy in the main file.
+///
+/// Frames are only used up to the last commit frame whose salts and
+/// cumulative checksum are valid, so a partially written transaction at
+/// the end of the log is ignored, as SQLite does. A missing or invalid log
+/// leaves the main file as is.
+pub struct WalSource {
+    db: Box<dyn PageSource>,
+    wal: Option<File>,
+    page_size: usize,
+    frames: HashMap<usize, u64>, // page number -> offset of its page data in the log
+    db_pages: Option<usize>,     // database size in pages after the last commit
+}
+
+impl WalSource {
+    pub fn open(db_path: &str, db: impl PageSource + 'static) -> Result<Self> {
+        let mut source = Self {
+            db: Box::new(db),
+            wal: None,
+            page_size: 0,
+            frames: HashMap::new(),
+            db_pages: None,
+        };
+
+        match File::open(wal_path(db_path)) {
+            Ok(wal) => source.load_frames(wal)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(source)
+    }
+
+    /// Number of distinct pages served from the log
+    pub fn wal_page_count(&self) -> usize {
+        self.frames.len()
+    }
+
+    fn load_frames(&mut self, mut wal: File) -> Result<()> {
+        let wal_size = wal.metadata()?.len() as usize;
+        let mut header = [0u8; WAL_HEADER_SIZE];
+        if wal_size < WAL_HEADER_SIZE || wal.read_exact(&mut header).is_err() {
+            return Ok(());
+        }
+
+        let word = |bytes: &[u8], at: usize| u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+        let big_endian = match word(&header, 0) {
+            WAL_MAGIC_LE => false,
+            WAL_MAGIC_BE => true,
+            _ => return Ok(()),
+        };
+        let page_size = word(&header, 8) as usize;
+        if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+            return Ok(());
+        }
+
+        let salts = [word(&header, 16), word(&header, 20)];
+        let mut checksum = wal_checksum(&header[..24], big_endian, (0, 0));
+        if checksum != (word(&header, 24), word(&header, 28)) {
+            return Ok(());
+        }
+
+        let frame_size = WAL_FRAME_HEADER_SIZE + page_size;
+        let mut frame = vec![0u8; frame_size];
+        let mut pending = Vec::new();
+        let mut offset = WAL_HEADER_SIZE;
+        while offset + frame_size <= wal_size {
+            wal.read_exact(&mut frame)?;
+            if [word(&frame, 8), word(&frame, 12)] != salts {
+                break;
+            }
+            checksum = wal_checksum(&frame[..8], big_endian, checksum);
+            checksum = wal_checksum(&frame[WAL_FRAME_HEADER_SIZE..], big_endian, checksum);
+            if checksum != (word(&frame, 16), word(&frame, 20)) {
+                break;
+            }
+
+            let page_number = word(&frame, 0) as usize;
+            pending.push((page_number, (offset + WAL_FRAME_HEADER_SIZE) as u64));
+
+            // A non-zero database size marks the last frame of a transaction
+            let db_pages = word(&frame, 4) as usize;
+            if db_pages > 0 {
+                self.frames.extend(pending.drain(..));
+                self.db_pages = Some(db_pages);
+            }
+            offset += frame_size;
+        }
+
+        if self.db_pages.is_some() {
+            self.page_size = page_size;
+            self.wal = Some(wal);
+        }
+        Ok(())
+    }
+}
+
+impl PageSource for WalSource {
+    fn size(&self) -> Result<u64> {
+        match self.db_pages {
+            Some(pages) => Ok((pages * self.page_size) as u64),
+            None => self.db.size(),
+        }
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+        let Some(wal) = self.wal.as_mut() else {
+            return self.db.read_at(offset, len);
+        };
+
+        let page_number = offset as usize / self.page_size + 1;
+        let Some(&frame_offset) = self.frames.get(&page_number) else {
+            return self.db.read_at(offset, len);
+        };
+
+        let offset_in_page = offset as usize % self.page_size;
+        if offset_in_page + len > self.page_size {
+            bail!("Read of {} bytes at offset {} crosses a page boundary", len, offset);
+        }
+
+        wal.seek(SeekFrom::Start(frame_offset + offset_in_page as u64))?;
+        let mut buf = vec![0; len];
+        wal.read_exact(&mut buf)?;
+        Ok(Page::from(buf))
+    }
+
+    fn write_at(&mut self, _offset: u64, _data: &[u8]) -> Result<()> {
+        bail!("Writing to WAL-mode databases is not supported")
+    }
+}
+
+/// The WAL's running checksum over 32-bit words, in the byte order chosen
+/// by the header magic
+fn wal_checksum(data: &[u8], big_endian: bool, (mut s0, mut s1): (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    };
+
+    for pair in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&pair[..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&pair[4..])).wrapping_add(s0);
+    }
+    (s0, s1)
+}
@@ -7,6 +7,6 @@ pub const DB_HEADER_SIZE: usize = 100;
 pub const BTREE_HEADER_SIZE: usize = 8;
 
 // Re-export main types for convenience
-pub use database::{Database, TableRow, TableRows, RecordValue, Cell, TableSchema, ColumnInfo};
+pub use database::{Database, IndexCursor, TableCursor, TableRow, TableRows, RecordValue, Cell, TableSchema, ColumnInfo};
 pub use query::execute_command;
 pub use ui::{run_tui, App};
@@ -6,7 +6,7 @@ fn main() -> Result<()> {
     let args = std::env::args().collect::<Vec<_>>();
     
     match args.len() {
-        0 | 1 => bail!("Missing <database path>. Usage: {} <database_path> [command]", args.get(0).unwrap_or(&"program".to_string())),
+        0 | 1 => bail!("Missing <database path>. Usage: {} <database_path> [command]", args.first().unwrap_or(&"program".to_string())),
         2 => {
             // Only database path provided - launch TUI
             let database_path = &args[1];
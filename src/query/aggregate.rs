@@ -0,0 +1,336 @@
+use std::collections::HashSet;
+
+use anyhow::{bail, Result};
+
+use super::eval::{self, RowScope};
+use super::parser::Expr;
+use crate::database::affinity::{compare_collated, normalize, value_to_text};
+use crate::RecordValue;
+
+/// Whether a call to `name` with `arg_count` arguments is an aggregate.
+/// `min` and `max` with more than one argument are the scalar functions.
+pub fn is_aggregate(name: &str, arg_count: usize) -> bool {
+    match name {
+        "count" | "sum" | "avg" | "total" | "group_concat" => true,
+        "min" | "max" => arg_count == 1,
+        _ => false,
+    }
+}
+
+/// The name of the first aggregate function called anywhere in `expr`
+pub fn find_aggregate(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Function { name, args, .. } if is_aggregate(name, args.len()) => Some(name),
+        Expr::Function { args, .. } => args.iter().find_map(find_aggregate),
+        Expr::Unary { expr, .. } => find_aggregate(expr),
+        Expr::Binary { left, right, .. } => find_aggregate(left).or_else(|| find_aggregate(right)),
+        Expr::Between { expr, low, high, .. } => {
+            find_aggregate(expr).or_else(|| find_aggregate(low)).or_else(|| find_aggregate(high))
+        }
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) => None,
+    }
+}
+
+/// Add the aggregate calls in `expr` to `calls`, checking that each is
+/// called the way SQLite allows
+pub fn collect_aggregates<'a>(expr: &'a Expr, calls: &mut Vec<&'a Expr>) -> Result<()> {
+    match expr {
+        Expr::Function { name, args, distinct, star } if is_aggregate(name, args.len()) => {
+            if let Some(inner) = args.iter().find_map(find_aggregate) {
+                bail!("misuse of aggregate function {}()", inner);
+            }
+            let arg_count_ok = match name.as_str() {
+                "count" => *star || args.len() == 1,
+                "group_concat" => !*star && (1..=2).contains(&args.len()),
+                _ => !*star && args.len() == 1,
+            };
+            if !arg_count_ok {
+                bail!("wrong number of arguments to function {}()", name);
+            }
+            if *distinct && args.len() != 1 {
+                bail!("DISTINCT aggregates must have exactly one argument");
+            }
+            calls.push(expr);
+            Ok(())
+        }
+        Expr::Function { args, .. } => args.iter().try_for_each(|arg| collect_aggregates(arg, calls)),
+        Expr::Unary { expr, .. } => collect_aggregates(expr, calls),
+        Expr::Binary { left, right, .. } => {
+            collect_aggregates(left, calls)?;
+            collect_aggregates(right, calls)
+        }
+        Expr::Between { expr, low, high, .. } => {
+            collect_aggregates(expr, calls)?;
+            collect_aggregates(low, calls)?;
+            collect_aggregates(high, calls)
+        }
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) => Ok(()),
+    }
+}
+
+/// The running state of one aggregate call over the rows of a group
+pub struct Accumulator<'a> {
+    args: &'a [Expr],
+    state: State,
+    /// Collation of the argument, used by `min`, `max` and DISTINCT
+    collation: Option<&'a str>,
+    /// Values already counted, for DISTINCT
+    seen: Option<HashSet<DistinctKey>>,
+}
+
+enum State {
+    Count(i64),
+    /// `count(*)`, which counts rows rather than values
+    CountRows(i64),
+    Sum { sum: Sum, finish: SumFinish },
+    Min(Option<RecordValue>),
+    Max(Option<RecordValue>),
+    GroupConcat(Option<String>),
+}
+
+#[derive(Clone, Copy)]
+enum SumFinish {
+    Sum,
+    Avg,
+    Total,
+}
+
+impl<'a> Accumulator<'a> {
+    /// Start an aggregate for `call`, which [`collect_aggregates`] accepted
+    pub fn new(call: &'a Expr, collation: Option<&'a str>) -> Result<Self> {
+        let Expr::Function { name, args, distinct, star } = call else {
+            bail!("Not an aggregate call");
+        };
+        let state = match name.as_str() {
+            "count" if *star => State::CountRows(0),
+            "count" => State::Count(0),
+            "sum" => State::Sum { sum: Sum::default(), finish: SumFinish::Sum },
+            "avg" => State::Sum { sum: Sum::default(), finish: SumFinish::Avg },
+            "total" => State::Sum { sum: Sum::default(), finish: SumFinish::Total },
+            "min" => State::Min(None),
+            "max" => State::Max(None),
+            "group_concat" => State::GroupConcat(None),
+            _ => bail!("no such function: {}", name),
+        };
+        let seen = distinct.then(HashSet::new);
+        Ok(Accumulator { args, state, collation, seen })
+    }
+
+    /// Add one row of the group. Returns true when `min` or `max` took this
+    /// row's value, as bare columns then come from this row.
+    pub fn step(&mut self, scope: &RowScope) -> Result<bool> {
+        if let State::CountRows(count) = &mut self.state {
+            *count += 1;
+            return Ok(false);
+        }
+
+        // Every other aggregate skips NULL arguments
+        let value = normalize(&eval::evaluate(&self.args[0], scope)?);
+        if matches!(value, RecordValue::Null) {
+            return Ok(false);
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert(DistinctKey::new(&value, self.collation)) {
+                return Ok(false);
+            }
+        }
+
+        match &mut self.state {
+            State::CountRows(_) => unreachable!(),
+            State::Count(count) => *count += 1,
+            State::Sum { sum, .. } => sum.step(&value),
+            State::Min(current) => {
+                if !current.as_ref().is_some_and(|current| compare_collated(&value, current, self.collation, scope.encoding).is_ge()) {
+                    *current = Some(value);
+                    return Ok(true);
+                }
+            }
+            State::Max(current) => {
+                if !current.as_ref().is_some_and(|current| compare_collated(&value, current, self.collation, scope.encoding).is_le()) {
+                    *current = Some(value);
+                    return Ok(true);
+                }
+            }
+            State::GroupConcat(text) => {
+                match text {
+                    None => *text = Some(value_to_text(&value)),
+                    Some(text) => {
+                        // The separator is evaluated for the row whose value it comes before
+                        let separator = match self.args.get(1) {
+                            Some(separator) => match eval::evaluate(separator, scope)? {
+                                RecordValue::Null => String::new(),
+                                separator => value_to_text(&separator),
+                            },
+                            None => ",".to_string(),
+                        };
+                        text.push_str(&separator);
+                        text.push_str(&value_to_text(&value));
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// The aggregate's value over the rows added so far
+    pub fn finish(&self) -> Result<RecordValue> {
+        Ok(match &self.state {
+            State::Count(count) | State::CountRows(count) => RecordValue::Int(*count),
+            State::Sum { sum, finish: SumFinish::Sum } => sum.sum()?,
+            State::Sum { sum, finish: SumFinish::Avg } => match sum.count {
+                0 => RecordValue::Null,
+                count => RecordValue::Float(sum.real() / count as f64),
+            },
+            State::Sum { sum, finish: SumFinish::Total } => RecordValue::Float(sum.real()),
+            State::Min(value) | State::Max(value) => value.clone().unwrap_or(RecordValue::Null),
+            State::GroupConcat(text) => text.clone().map_or(RecordValue::Null, RecordValue::Text),
+        })
+    }
+}
+
+/// SQLite's running sum for `sum`, `avg` and `total`: exact while every
+/// value is an integer, then Kahan-Babuska-Neumaier compensated once a real
+/// appears or the integer sum overflows
+#[derive(Default)]
+struct Sum {
+    count: i64,
+    int_sum: i64,
+    approx: bool,
+    /// The integer sum overflowed and no real has been added since
+    overflow: bool,
+    real_sum: f64,
+    error: f64,
+}
+
+/// Integers at least this large lose precision as reals, so they are added
+/// in two parts
+const EXACT_REAL_LIMIT: i64 = 4_503_599_627_370_496;
+
+impl Sum {
+    fn step(&mut self, value: &RecordValue) {
+        self.count += 1;
+        match (numeric_type(value), self.approx) {
+            (RecordValue::Int(n), false) => match self.int_sum.checked_add(n) {
+                Some(sum) => self.int_sum = sum,
+                None => {
+                    self.overflow = true;
+                    self.start_approx();
+                    self.add_int(n);
+                }
+            },
+            (RecordValue::Int(n), true) => self.add_int(n),
+            (other, approx) => {
+                if !approx {
+                    self.start_approx();
+                }
+                self.overflow = false;
+                self.add_real(eval::real_value(&other));
+            }
+        }
+    }
+
+    fn start_approx(&mut self) {
+        self.approx = true;
+        let (big, small) = split_int(self.int_sum);
+        self.real_sum = big as f64;
+        self.error = small as f64;
+    }
+
+    fn add_int(&mut self, n: i64) {
+        let (big, small) = split_int(n);
+        self.add_real(big as f64);
+        if small != 0 {
+            self.add_real(small as f64);
+        }
+    }
+
+    fn add_real(&mut self, value: f64) {
+        let sum = self.real_sum + value;
+        if self.real_sum.abs() > value.abs() {
+            self.error += (self.real_sum - sum) + value;
+        } else {
+            self.error += (value - sum) + self.real_sum;
+        }
+        self.real_sum = sum;
+    }
+
+    fn real(&self) -> f64 {
+        if !self.approx {
+            self.int_sum as f64
+        } else if self.error.is_finite() {
+            self.real_sum + self.error
+        } else {
+            self.real_sum
+        }
+    }
+
+    fn sum(&self) -> Result<RecordValue> {
+        Ok(match (self.count, self.approx) {
+            (0, _) => RecordValue::Null,
+            (_, false) => RecordValue::Int(self.int_sum),
+            _ if self.overflow => bail!("integer overflow"),
+            _ => RecordValue::Float(self.real()),
+        })
+    }
+}
+
+/// Split an integer too large to be exact as a real into a part that is,
+/// and a small remainder
+fn split_int(n: i64) -> (i64, i64) {
+    if n <= -EXACT_REAL_LIMIT || n >= EXACT_REAL_LIMIT {
+        let small = n % 16384;
+        (n - small, small)
+    } else {
+        (n, 0)
+    }
+}
+
+/// A value as a number if it is one or is text that reads entirely as one,
+/// like SQLite's `sqlite3_value_numeric_type`; other values are left alone
+fn numeric_type(value: &RecordValue) -> RecordValue {
+    let RecordValue::Text(text) = value else {
+        return value.clone();
+    };
+    let trimmed = text.trim();
+    if let Ok(n) = trimmed.parse::<i64>() {
+        return RecordValue::Int(n);
+    }
+    match trimmed.parse::<f64>() {
+        Ok(f) if trimmed.bytes().any(|b| b.is_ascii_digit()) => RecordValue::Float(f),
+        _ => value.clone(),
+    }
+}
+
+/// A value as DISTINCT and GROUP BY compare it: integers and reals by
+/// numeric value, text under its collation
+#[derive(PartialEq, Eq, Hash)]
+pub enum DistinctKey {
+    Null,
+    Int(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl DistinctKey {
+    pub fn new(value: &RecordValue, collation: Option<&str>) -> Self {
+        match value {
+            RecordValue::Int(n) => DistinctKey::Int(*n),
+            RecordValue::Float(f) if f.fract() == 0.0 && *f >= -9.223_372_036_854_776e18 && *f < 9.223_372_036_854_776e18 => {
+                DistinctKey::Int(*f as i64)
+            }
+            RecordValue::Float(f) => DistinctKey::Real(f.to_bits()),
+            RecordValue::Text(text) => DistinctKey::Text(match collation {
+                Some(collation) if collation.eq_ignore_ascii_case("nocase") => text.to_ascii_lowercase(),
+                Some(collation) if collation.eq_ignore_ascii_case("rtrim") => text.trim_end_matches(' ').to_string(),
+                _ => text.clone(),
+            }),
+            RecordValue::Blob(blob) => DistinctKey::Blob(blob.clone()),
+            RecordValue::Zero => DistinctKey::Int(0),
+            RecordValue::One => DistinctKey::Int(1),
+            RecordValue::Null => DistinctKey::Null,
+            // Reserved serial types never hold a value
+            RecordValue::Reserved(_) => DistinctKey::Blob(Vec::new()),
+        }
+    }
+}
@@ -1,104 +1,7 @@
-use anyhow::{bail, Result};
-use crate::{Database, TableRows, RecordValue};
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum ComparisonOperator {
-    Equal,
-    NotEqual,
-    LessThan,
-    LessThanOrEqual,
-    GreaterThan,
-    GreaterThanOrEqual,
-}
-
-#[derive(Debug, Clone)]
-pub struct WhereCondition {
-    pub column_name: String,
-    pub operator: ComparisonOperator,
-    pub value: String,
-}
-
-impl WhereCondition {
-    pub fn parse(where_clause: &str) -> Result<Self> {
-        let where_clause = where_clause.trim();
-        
-        // Handle inequality operators first (longer patterns first to avoid conflicts)
-        if let Some(pos) = where_clause.find(" != ") {
-            let column_name = where_clause[..pos].trim().to_string();
-            let value = where_clause[pos + 4..].trim();
-            let value = Self::parse_value(value)?;
-            return Ok(WhereCondition {
-                column_name,
-                operator: ComparisonOperator::NotEqual,
-                value,
-            });
-        } else if let Some(pos) = where_clause.find("!=") {
-            // Handle cases without spaces around !=
-            let column_name = where_clause[..pos].trim().to_string();
-            let value = where_clause[pos + 2..].trim();
-            let value = Self::parse_value(value)?;
-            return Ok(WhereCondition {
-                column_name,
-                operator: ComparisonOperator::NotEqual,
-                value,
-            });
-        }
-        
-        // Handle equality operators (after inequality to avoid conflicts)
-        if let Some(pos) = where_clause.find(" = ") {
-            let column_name = where_clause[..pos].trim().to_string();
-            let value = where_clause[pos + 3..].trim();
-            let value = Self::parse_value(value)?;
-            return Ok(WhereCondition {
-                column_name,
-                operator: ComparisonOperator::Equal,
-                value,
-            });
-        } else if let Some(pos) = where_clause.find('=') {
-            // Handle cases without spaces around =
-            let column_name = where_clause[..pos].trim().to_string();
-            let value = where_clause[pos + 1..].trim();
-            let value = Self::parse_value(value)?;
-            return Ok(WhereCondition {
-                column_name,
-                operator: ComparisonOperator::Equal,
-                value,
-            });
-        }
-        
-        bail!("Unsupported WHERE clause format: {}", where_clause);
-    }
-    
-    fn parse_value(value: &str) -> Result<String> {
-        let value = value.trim();
-        if value.starts_with('\'') && value.ends_with('\'') {
-            // Remove single quotes for string values
-            Ok(value[1..value.len()-1].to_string())
-        } else if value.starts_with('"') && value.ends_with('"') {
-            // Remove double quotes for string values
-            Ok(value[1..value.len()-1].to_string())
-        } else {
-            // Check if it's a valid number (integer or float)
-            if value.parse::<i64>().is_ok() || value.parse::<f64>().is_ok() {
-                // Allow unquoted numeric values
-                Ok(value.to_string())
-            } else {
-                // Reject unquoted string values
-                bail!("String values must be quoted. Use 'value' or \"value\" instead of {}", value)
-            }
-        }
-    }
-    
-    pub fn matches(&self, record_value: &RecordValue) -> bool {
-        let value_str = record_value.to_display_string();
-        match self.operator {
-            ComparisonOperator::Equal => value_str == self.value,
-            ComparisonOperator::NotEqual => value_str != self.value,
-            // Add more operators as needed
-            _ => false,
-        }
-    }
-}
+use anyhow::Result;
+use crate::Database;
+use super::executor::{self, ResultSet};
+use super::parser::{self, ResultColumn, Select, Statement};
 
 pub fn execute_command(database_path: &str, command: &str) -> Result<()> {
     match command {
@@ -115,6 +18,10 @@ fn handle_dbinfo(database_path: &str) -> Result<()> {
     println!("database page size: {}", db.get_page_size());
     println!("number of tables: {}", db.get_num_tables()?);
     
+    let stats = db.cache_stats();
+    println!("page cache: {} hits, {} misses ({}/{} pages)", 
+        stats.hits, stats.misses, stats.cached_pages, stats.capacity);
+    
     Ok(())
 }
 
@@ -144,253 +51,68 @@ fn handle_schema(database_path: &str) -> Result<()> {
     Ok(())
 }
 
-fn handle_sql_query(database_path: &str, query: &str) -> Result<()> {
-    let mut query_parts = query.split_whitespace();
-    
-    match query_parts.next().map(|s| s.to_lowercase()).as_deref() {
-        Some("select") => handle_select_query(database_path, query),
-        _ => bail!("Unsupported SQL command: {}", query),
-    }
-}
-
-fn handle_select_query(database_path: &str, query: &str) -> Result<()> {
-    // Skip the "SELECT" keyword (case insensitive)
-    let query_after_select = if query.to_lowercase().starts_with("select ") {
-        &query[7..] // Skip "SELECT "
+/// Run one or more `;`-separated statements on a single connection, so an
+/// explicit `BEGIN ... COMMIT` can span several of them. A transaction left
+/// open at the end is rolled back.
+fn handle_sql_query(database_path: &str, sql: &str) -> Result<()> {
+    let statements = parser::parse(sql)?;
+    let read_only = statements.iter().all(|s| matches!(s, Statement::Select(_)));
+    let mut db = if read_only {
+        Database::new(database_path)?
     } else {
-        bail!("Query must start with SELECT");
+        Database::open_rw(database_path)?
     };
-    
-    let query_lower = query_after_select.to_lowercase();
-    
-    if query_lower.starts_with("count(*)") {
-        let mut parts = query_after_select.split_whitespace();
-        parts.next(); // skip "count(*)"
-        handle_select_count_from_remaining(database_path, &mut parts)
-    } else if query_lower.starts_with("*") {
-        let mut parts = query_after_select.split_whitespace();
-        parts.next(); // skip "*"
-        handle_select_all_from_remaining(database_path, &mut parts)
-    } else {
-        // Handle specific column selection with case preservation
-        handle_select_columns(database_path, query_after_select)
+
+    let result = run_statements(&mut db, &statements);
+    if db.in_transaction() {
+        db.rollback();
     }
+    result
 }
 
-fn handle_select_count_from_remaining(database_path: &str, query_parts: &mut std::str::SplitWhitespace) -> Result<()> {
-    match query_parts.next().map(|s| s.to_lowercase()).as_deref() {
-        Some("from") => {
-            let table_name = query_parts.next()
-                .ok_or_else(|| anyhow::anyhow!("Missing table name in SELECT COUNT(*) FROM query"))?;
-            
-            let mut db = Database::new(database_path)?;
-            let count = db.count_table_rows(table_name)?;
-            println!("{}", count);
-            Ok(())
-        }
-        _ => bail!("Expected FROM after SELECT COUNT(*)"),
-    }
+/// Execute `;`-separated statements in order, printing query results and
+/// stopping at the first error
+pub fn execute_sql(db: &mut Database, sql: &str) -> Result<()> {
+    run_statements(db, &parser::parse(sql)?)
 }
 
-fn handle_select_all_from_remaining(database_path: &str, query_parts: &mut std::str::SplitWhitespace) -> Result<()> {
-    match query_parts.next().map(|s| s.to_lowercase()).as_deref() {
-        Some("from") => {
-            let remaining_parts: Vec<&str> = query_parts.collect();
-            let (table_name, where_condition) = parse_table_and_where(&remaining_parts)?;
-            
-            let mut db = Database::new(database_path)?;
-            let table_data = db.get_table_rows(table_name)?;
-            
-            if table_data.columns.is_empty() {
-                bail!("Table {} not found or has no columns", table_name);
-            }
-            
-            // Apply WHERE filter if present
-            let final_data = if let Some(condition) = where_condition {
-                apply_where_filter(&table_data, &condition)?
-            } else {
-                table_data
-            };
-            
-            display_table_data(&final_data);
-            Ok(())
-        }
-        _ => bail!("Expected FROM after SELECT *"),
-    }
+/// Execute a single SQL statement, printing its result if it is a query
+pub fn execute_statement(db: &mut Database, statement: &str) -> Result<()> {
+    run_statements(db, &[parser::parse_statement(statement)?])
 }
 
-fn handle_select_columns(database_path: &str, query_str: &str) -> Result<()> {
-    // Parse column1, column2, ... FROM table [WHERE condition] format
-    // Use case-insensitive matching for keywords but preserve case for values
-    let query_lower = query_str.to_lowercase();
-    let from_pos = query_lower.find(" from ")
-        .ok_or_else(|| anyhow::anyhow!("Missing FROM clause in SELECT query"))?;
-    
-    let columns_str = query_str[..from_pos].trim();
-    let from_and_where = query_str[from_pos + 6..].trim(); // Skip " from "
-    
-    // Check if there's a WHERE clause (case-insensitive)
-    let from_and_where_lower = from_and_where.to_lowercase();
-    let (table_name, where_condition) = if let Some(where_pos) = from_and_where_lower.find(" where ") {
-        let table_name = from_and_where[..where_pos].trim();
-        let where_clause = from_and_where[where_pos + 7..].trim(); // Preserve case for WHERE clause
-        let condition = WhereCondition::parse(where_clause)?;
-        (table_name, Some(condition))
-    } else {
-        (from_and_where, None)
-    };
-    
-    // Parse column names (handle comma-separated)
-    let column_names: Vec<&str> = columns_str
-        .split(',')
-        .map(|s| s.trim())
-        .collect();
-    
-    let mut db = Database::new(database_path)?;
-    
-    // Try to use index scanning if there's a WHERE condition
-    let table_data = if let Some(ref condition) = where_condition {
-        // Check if we can use an index for this condition
-        if condition.operator == ComparisonOperator::Equal {
-            if let Ok(Some(index)) = db.find_index_for_column(table_name, &condition.column_name) {
-                // Use index scanning
-                let row_ids = db.search_index(&index, &condition.value)?;
-                
-                // Get only the rows with matching IDs
-                db.get_table_rows_by_ids(table_name, &row_ids)?
-            } else {
-                // Fall back to full table scan
-                let all_data = db.get_table_rows(table_name)?;
-                apply_where_filter(&all_data, condition)?
-            }
-        } else {
-            // Non-equality conditions: use full table scan
-            let all_data = db.get_table_rows(table_name)?;
-            apply_where_filter(&all_data, condition)?
+fn run_statements(db: &mut Database, statements: &[Statement]) -> Result<()> {
+    for statement in statements {
+        if let (Some(result), Statement::Select(select)) = (executor::execute(db, statement)?, statement) {
+            display_result(select, &result);
         }
-    } else {
-        // No WHERE clause: get all data
-        db.get_table_rows(table_name)?
-    };
-    
-    if table_data.columns.is_empty() {
-        bail!("Table {} not found or has no columns", table_name);
     }
-    
-    // Extract specific columns
-    let column_values = extract_columns(&table_data, &column_names)?;
-    
-    // Display column headers
-    println!("{}", column_names.join("|"));
-    
-    // Display data rows
-    for row in column_values {
-        let row_values: Vec<String> = row.iter()
-            .map(|val| val.to_display_string())
-            .collect();
-        println!("{}", row_values.join("|"));
-    }
-    
     Ok(())
 }
 
-/// Apply WHERE filter to table data
-fn apply_where_filter(table_data: &TableRows, condition: &WhereCondition) -> Result<TableRows> {
-    // Find the column index for the condition
-    let column_index = table_data.columns.iter()
-        .position(|col| col.name.eq_ignore_ascii_case(&condition.column_name))
-        .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in table", condition.column_name))?;
-    
-    // Filter rows based on the condition
-    let filtered_rows: Vec<_> = table_data.rows.iter()
-        .filter(|row| {
-            if let Some(value) = row.values.get(column_index) {
-                condition.matches(value)
-            } else {
-                false
-            }
-        })
-        .cloned()
-        .collect();
-    
-    Ok(TableRows {
-        columns: table_data.columns.clone(),
-        rows: filtered_rows,
-    })
-}
-
-/// Extract specific columns from table data
-fn extract_columns(table_data: &TableRows, column_names: &[&str]) -> Result<Vec<Vec<RecordValue>>> {
-    let mut column_indices = Vec::new();
-    
-    // Find indices for the requested columns
-    for col_name in column_names {
-        let index = table_data.columns.iter()
-            .position(|col| col.name.eq_ignore_ascii_case(col_name))
-            .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in table", col_name))?;
-        column_indices.push(index);
-    }
-    
-    // Extract values for the specified columns
-    let mut results = Vec::new();
-    for row in &table_data.rows {
-        let mut row_values = Vec::new();
-        for &col_index in &column_indices {
-            if let Some(value) = row.values.get(col_index) {
-                row_values.push(value.clone());
-            } else {
-                row_values.push(RecordValue::Null);
-            }
+/// Print query results: a bare count for `SELECT COUNT(*)`, a header with
+/// a separator line for `SELECT *`, and a header for other column lists
+fn display_result(select: &Select, result: &ResultSet) {
+    if select.is_count_star() {
+        for row in &result.rows {
+            println!("{}", row[0].to_display_string());
         }
-        results.push(row_values);
+        return;
     }
-    
-    Ok(results)
-}
 
-/// Display table data in a formatted way
-fn display_table_data(table_data: &TableRows) {
-    // Print column headers
-    let headers: Vec<String> = table_data.columns.iter()
-        .map(|col| col.name.clone())
-        .collect();
-    println!("{}", headers.join("|"));
-    
-    // Print separator
-    let separator = headers.iter()
-        .map(|h| "-".repeat(h.len().max(10)))
-        .collect::<Vec<_>>()
-        .join("|");
-    println!("{}", separator);
-    
-    // Print data rows
-    for row in &table_data.rows {
-        let row_values: Vec<String> = row.values.iter()
+    println!("{}", result.columns.join("|"));
+    if matches!(select.columns[..], [ResultColumn::Star]) {
+        let separator = result.columns.iter()
+            .map(|h| "-".repeat(h.len().max(10)))
+            .collect::<Vec<_>>()
+            .join("|");
+        println!("{}", separator);
+    }
+
+    for row in &result.rows {
+        let row_values: Vec<String> = row.iter()
             .map(|val| val.to_display_string())
             .collect();
         println!("{}", row_values.join("|"));
     }
 }
-
-/// Parse table name and WHERE condition from query parts
-fn parse_table_and_where<'a>(parts: &'a [&str]) -> Result<(&'a str, Option<WhereCondition>)> {
-    if parts.is_empty() {
-        bail!("Missing table name in SELECT query");
-    }
-    
-    // Convert to lowercase for keyword matching but preserve original case
-    let parts_lower: Vec<String> = parts.iter().map(|s| s.to_lowercase()).collect();
-    
-    // Check if there's a WHERE clause (case-insensitive)
-    if let Some(where_pos) = parts_lower.iter().position(|part| part == "where") {
-        if where_pos == 0 {
-            bail!("Missing table name before WHERE clause");
-        }
-        let table_name = parts[0];
-        let where_clause = parts[where_pos + 1..].join(" ");
-        let condition = WhereCondition::parse(&where_clause)?;
-        Ok((table_name, Some(condition)))
-    } else {
-        Ok((parts[0], None))
-    }
-} 
\ No newline at end of file
@@ -0,0 +1,513 @@
+use anyhow::{bail, Result};
+
+use std::cmp::Ordering;
+
+use super::aggregate;
+use super::parser::{BinaryOp, Expr, TableRef, UnaryOp};
+use crate::database::affinity::{self, normalize, Affinity};
+use crate::database::TextEncoding;
+use crate::{ColumnInfo, RecordValue, TableRow, TableSchema};
+
+/// A table in FROM, as expressions see it: the name it goes by and its columns
+#[derive(Clone, Copy)]
+pub struct Source<'a> {
+    pub table: &'a TableRef,
+    pub columns: &'a [ColumnInfo],
+    /// False for WITHOUT ROWID tables, which have no rowid pseudo-columns
+    pub has_rowid: bool,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(table: &'a TableRef, schema: &'a TableSchema) -> Self {
+        Source { table, columns: &schema.columns, has_rowid: !schema.without_rowid }
+    }
+}
+
+/// A row of each table in FROM together with the names they can be
+/// referenced by
+pub struct RowScope<'a> {
+    pub sources: &'a [Source<'a>],
+    /// The row of each source. A LEFT JOIN that found no match has `None`,
+    /// whose columns are all NULL.
+    pub rows: &'a [Option<TableRow>],
+    /// Values of the aggregate calls of a grouped query, for the group this
+    /// row represents, keyed by the call's node in the query
+    pub aggregates: &'a [(&'a Expr, RecordValue)],
+    /// How the database stores text, which comparisons order it by
+    pub encoding: TextEncoding,
+}
+
+impl RowScope<'_> {
+    fn column_value(&self, table: Option<&str>, name: &str) -> Result<RecordValue> {
+        let (source, slot) = resolve_column(self.sources, table, name)?;
+        let Some(row) = &self.rows[source] else {
+            return Ok(RecordValue::Null);
+        };
+        Ok(match slot {
+            ColumnSlot::Value(index) => row.values.get(index).cloned().unwrap_or(RecordValue::Null),
+            ColumnSlot::RowId => RecordValue::Int(row.row_id as i64),
+        })
+    }
+
+    fn column_affinity(&self, table: Option<&str>, name: &str) -> Result<Affinity> {
+        Ok(match resolve_column(self.sources, table, name)? {
+            (source, ColumnSlot::Value(index)) => self.sources[source].columns[index].affinity,
+            (_, ColumnSlot::RowId) => Affinity::Integer,
+        })
+    }
+}
+
+/// Where the value of a column reference comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnSlot {
+    /// The column at this index of the table's columns
+    Value(usize),
+    /// The rowid, through one of its names that no column uses
+    RowId,
+}
+
+/// Whether `name` is one of the names SQLite gives the rowid
+pub fn is_rowid_name(name: &str) -> bool {
+    ["rowid", "oid", "_rowid_"].iter().any(|rowid| rowid.eq_ignore_ascii_case(name))
+}
+
+/// Resolve the column referenced as `[table.]name` to the source it belongs
+/// to and its slot there. Without a table name the column must be in exactly
+/// one source. A declared column takes precedence over the rowid's names;
+/// otherwise, in a table with a rowid, `rowid`, `oid` and `_rowid_` refer to
+/// the INTEGER PRIMARY KEY column if there is one, or to the rowid.
+pub fn resolve_column(sources: &[Source], table: Option<&str>, name: &str) -> Result<(usize, ColumnSlot)> {
+    let mut found = None;
+    for (position, source) in sources.iter().enumerate() {
+        if table.is_some_and(|qualifier| !source.table.is_named(qualifier)) {
+            continue;
+        }
+        let Some(slot) = find_column(source, name) else {
+            continue;
+        };
+        if found.is_some() {
+            match table {
+                Some(qualifier) => bail!("ambiguous column name: {}.{}", qualifier, name),
+                None => bail!("ambiguous column name: {}", name),
+            }
+        }
+        found = Some((position, slot));
+    }
+    match (found, table) {
+        (Some(found), _) => Ok(found),
+        (None, Some(qualifier)) => bail!("no such column: {}.{}", qualifier, name),
+        (None, None) => bail!("no such column: {}", name),
+    }
+}
+
+fn find_column(source: &Source, name: &str) -> Option<ColumnSlot> {
+    if let Some(index) = source.columns.iter().position(|col| col.name.eq_ignore_ascii_case(name)) {
+        return Some(ColumnSlot::Value(index));
+    }
+    if source.has_rowid && is_rowid_name(name) {
+        return Some(match source.columns.iter().position(|col| col.is_rowid_alias) {
+            Some(index) => ColumnSlot::Value(index),
+            None => ColumnSlot::RowId,
+        });
+    }
+    None
+}
+
+/// Check that every column `expr` references exists, so a bad WHERE clause
+/// is reported even when the table has no rows
+pub fn check_columns(expr: &Expr, sources: &[Source]) -> Result<()> {
+    match expr {
+        Expr::Column { table, name } => resolve_column(sources, table.as_deref(), name).map(|_| ()),
+        Expr::Unary { expr, .. } => check_columns(expr, sources),
+        Expr::Binary { left, right, .. } => {
+            check_columns(left, sources)?;
+            check_columns(right, sources)
+        }
+        Expr::Between { expr, low, high, .. } => {
+            check_columns(expr, sources)?;
+            check_columns(low, sources)?;
+            check_columns(high, sources)
+        }
+        Expr::Function { args, .. } => args.iter().try_for_each(|arg| check_columns(arg, sources)),
+        Expr::Literal(_) | Expr::Parameter(_) => Ok(()),
+    }
+}
+
+/// Whether a WHERE clause selects the row. Like SQLite, a NULL result counts
+/// as not matching.
+pub fn matches(expr: &Expr, scope: &RowScope) -> Result<bool> {
+    Ok(truth_value(&evaluate(expr, scope)?) == Some(true))
+}
+
+/// Evaluate an expression that may not refer to any column, such as LIMIT
+pub fn evaluate_constant(expr: &Expr, encoding: TextEncoding) -> Result<RecordValue> {
+    check_columns(expr, &[])?;
+    evaluate(expr, &RowScope { sources: &[], rows: &[], aggregates: &[], encoding })
+}
+
+/// Evaluate `expr` against one row
+pub fn evaluate(expr: &Expr, scope: &RowScope) -> Result<RecordValue> {
+    match expr {
+        Expr::Literal(value) => Ok(value.clone()),
+        Expr::Column { table, name } => scope.column_value(table.as_deref(), name),
+        Expr::Parameter(name) => bail!("Unbound parameter: {}", name),
+        Expr::Unary { op, expr } => evaluate_unary(*op, evaluate(expr, scope)?),
+        Expr::Binary { left, op: BinaryOp::And, right } => {
+            // Three-valued AND: false wins over NULL, and the right side is
+            // only evaluated when the left side does not already decide it
+            let left = truth_value(&evaluate(left, scope)?);
+            if left == Some(false) {
+                return Ok(RecordValue::Int(0));
+            }
+            let right = truth_value(&evaluate(right, scope)?);
+            Ok(match (left, right) {
+                (_, Some(false)) => RecordValue::Int(0),
+                (Some(true), Some(true)) => RecordValue::Int(1),
+                _ => RecordValue::Null,
+            })
+        }
+        Expr::Binary { left, op: BinaryOp::Or, right } => {
+            // Three-valued OR: true wins over NULL
+            let left = truth_value(&evaluate(left, scope)?);
+            if left == Some(true) {
+                return Ok(RecordValue::Int(1));
+            }
+            let right = truth_value(&evaluate(right, scope)?);
+            Ok(match (left, right) {
+                (_, Some(true)) => RecordValue::Int(1),
+                (Some(false), Some(false)) => RecordValue::Int(0),
+                _ => RecordValue::Null,
+            })
+        }
+        Expr::Binary { left, op, right } if is_comparison(*op) => {
+            let (left_value, right_value) = affinity::comparison_operands(
+                &evaluate(left, scope)?,
+                expr_affinity(left, scope)?,
+                &evaluate(right, scope)?,
+                expr_affinity(right, scope)?,
+            );
+            Ok(compare(&left_value, *op, &right_value, scope.encoding))
+        }
+        Expr::Binary { left, op, right } => {
+            evaluate_binary(evaluate(left, scope)?, *op, evaluate(right, scope)?)
+        }
+        Expr::Between { expr, low, high, negated } => {
+            // `x BETWEEN a AND b` is `x >= a AND x <= b`, with x evaluated once
+            let value = evaluate(expr, scope)?;
+            let affinity = expr_affinity(expr, scope)?;
+            let bound = |bound: &Expr, op: BinaryOp| -> Result<Option<bool>> {
+                let (left, right) =
+                    affinity::comparison_operands(&value, affinity, &evaluate(bound, scope)?, expr_affinity(bound, scope)?);
+                Ok(truth_value(&compare(&left, op, &right, scope.encoding)))
+            };
+            let above_low = bound(low, BinaryOp::GtEq)?;
+            let below_high = bound(high, BinaryOp::LtEq)?;
+            let between = match (above_low, below_high) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            };
+            Ok(match between {
+                Some(between) => bool_value(between != *negated),
+                None => RecordValue::Null,
+            })
+        }
+        Expr::Function { name, args, .. } => {
+            if let Some((_, value)) = scope.aggregates.iter().find(|(call, _)| std::ptr::eq(*call, expr)) {
+                return Ok(value.clone());
+            }
+            if aggregate::is_aggregate(name, args.len()) {
+                bail!("misuse of aggregate function {}()", name);
+            }
+            bail!("no such function: {}", name)
+        }
+    }
+}
+
+/// SQLite's truth value of a result: NULL is unknown, anything else is true
+/// when it converts to a non-zero number
+pub fn truth_value(value: &RecordValue) -> Option<bool> {
+    match normalize(value) {
+        RecordValue::Null => None,
+        RecordValue::Int(i) => Some(i != 0),
+        RecordValue::Float(f) => Some(f != 0.0),
+        other => Some(to_number(&other).is_some_and(|n| n != Number::Int(0) && n != Number::Float(0.0))),
+    }
+}
+
+/// Affinity an expression carries into a comparison: only column references
+/// have one
+fn expr_affinity(expr: &Expr, scope: &RowScope) -> Result<Option<Affinity>> {
+    match expr {
+        Expr::Column { table, name } => scope.column_affinity(table.as_deref(), name).map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn is_comparison(op: BinaryOp) -> bool {
+    matches!(
+        op,
+        BinaryOp::Eq | BinaryOp::NotEq | BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq | BinaryOp::Is | BinaryOp::IsNot
+    )
+}
+
+/// Compare two values after affinity conversion, text as `encoding` stores
+/// it. A NULL operand makes the result NULL, except for `IS` and `IS NOT`,
+/// where NULL equals NULL.
+pub fn compare(left: &RecordValue, op: BinaryOp, right: &RecordValue, encoding: TextEncoding) -> RecordValue {
+    let null_operand = matches!(left, RecordValue::Null) || matches!(right, RecordValue::Null);
+    match op {
+        BinaryOp::Is | BinaryOp::IsNot if null_operand => {
+            let same = matches!(left, RecordValue::Null) && matches!(right, RecordValue::Null);
+            bool_value(same == (op == BinaryOp::Is))
+        }
+        _ if null_operand => RecordValue::Null,
+        _ => {
+            let ordering = affinity::compare_values(left, right, encoding);
+            bool_value(match op {
+                BinaryOp::Eq | BinaryOp::Is => ordering == Ordering::Equal,
+                BinaryOp::NotEq | BinaryOp::IsNot => ordering != Ordering::Equal,
+                BinaryOp::Lt => ordering == Ordering::Less,
+                BinaryOp::LtEq => ordering != Ordering::Greater,
+                BinaryOp::Gt => ordering == Ordering::Greater,
+                BinaryOp::GtEq => ordering != Ordering::Less,
+                _ => unreachable!("not a comparison operator"),
+            })
+        }
+    }
+}
+
+fn evaluate_unary(op: UnaryOp, value: RecordValue) -> Result<RecordValue> {
+    if matches!(value, RecordValue::Null) {
+        return Ok(RecordValue::Null);
+    }
+    Ok(match op {
+        UnaryOp::Not => bool_value(truth_value(&value) == Some(false)),
+        UnaryOp::Plus => value,
+        UnaryOp::Negate => match numeric_value(&value) {
+            Number::Int(i) => i.checked_neg().map_or(RecordValue::Float(-(i as f64)), RecordValue::Int),
+            Number::Float(f) => RecordValue::Float(-f),
+        },
+        UnaryOp::BitNot => RecordValue::Int(!integer_value(&value)),
+    })
+}
+
+fn evaluate_binary(left: RecordValue, op: BinaryOp, right: RecordValue) -> Result<RecordValue> {
+    let left = normalize(&left);
+    let right = normalize(&right);
+    if matches!(left, RecordValue::Null) || matches!(right, RecordValue::Null) {
+        return Ok(RecordValue::Null);
+    }
+
+    Ok(match op {
+        BinaryOp::Concat => RecordValue::Text(affinity::value_to_text(&left) + &affinity::value_to_text(&right)),
+        BinaryOp::BitAnd => RecordValue::Int(integer_value(&left) & integer_value(&right)),
+        BinaryOp::BitOr => RecordValue::Int(integer_value(&left) | integer_value(&right)),
+        BinaryOp::ShiftLeft => shift(integer_value(&left), integer_value(&right)),
+        BinaryOp::ShiftRight => shift(integer_value(&left), integer_value(&right).saturating_neg()),
+        BinaryOp::Add | BinaryOp::Subtract | BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder => {
+            arithmetic(numeric_value(&left), op, numeric_value(&right))
+        }
+        _ => unreachable!("handled by evaluate"),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+fn arithmetic(left: Number, op: BinaryOp, right: Number) -> RecordValue {
+    if let (Number::Int(a), Number::Int(b)) = (left, right) {
+        let result = match op {
+            BinaryOp::Add => a.checked_add(b),
+            BinaryOp::Subtract => a.checked_sub(b),
+            BinaryOp::Multiply => a.checked_mul(b),
+            BinaryOp::Divide | BinaryOp::Remainder if b == 0 => return RecordValue::Null,
+            BinaryOp::Divide => a.checked_div(b),
+            BinaryOp::Remainder => Some(a.checked_rem(b).unwrap_or(0)),
+            _ => unreachable!(),
+        };
+        // Integer overflow falls back to floating point, as in SQLite
+        if let Some(result) = result {
+            return RecordValue::Int(result);
+        }
+    }
+
+    let a = as_f64(left);
+    let b = as_f64(right);
+    match op {
+        BinaryOp::Add => RecordValue::Float(a + b),
+        BinaryOp::Subtract => RecordValue::Float(a - b),
+        BinaryOp::Multiply => RecordValue::Float(a * b),
+        BinaryOp::Divide if b == 0.0 => RecordValue::Null,
+        BinaryOp::Divide => RecordValue::Float(a / b),
+        BinaryOp::Remainder => {
+            // SQLite's % works on the integer parts of its operands
+            let (a, b) = (a as i64, b as i64);
+            if b == 0 {
+                RecordValue::Null
+            } else {
+                RecordValue::Float(a.checked_rem(b).unwrap_or(0) as f64)
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn shift(value: i64, amount: i64) -> RecordValue {
+    RecordValue::Int(match amount {
+        64.. => 0,
+        1..=63 => value << amount,
+        0 => value,
+        -63..=-1 => value >> -amount,
+        _ => if value < 0 { -1 } else { 0 },
+    })
+}
+
+fn as_f64(number: Number) -> f64 {
+    match number {
+        Number::Int(i) => i as f64,
+        Number::Float(f) => f,
+    }
+}
+
+fn bool_value(value: bool) -> RecordValue {
+    RecordValue::Int(value as i64)
+}
+
+/// Numeric value used by arithmetic: text converts through its longest
+/// numeric prefix, and anything without one is 0
+fn numeric_value(value: &RecordValue) -> Number {
+    to_number(value).unwrap_or(Number::Int(0))
+}
+
+/// A value as a real, the way SQLite reads one from any value
+pub fn real_value(value: &RecordValue) -> f64 {
+    as_f64(numeric_value(value))
+}
+
+fn integer_value(value: &RecordValue) -> i64 {
+    match numeric_value(value) {
+        Number::Int(i) => i,
+        Number::Float(f) => f as i64,
+    }
+}
+
+fn to_number(value: &RecordValue) -> Option<Number> {
+    match normalize(value) {
+        RecordValue::Int(i) => Some(Number::Int(i)),
+        RecordValue::Float(f) => Some(Number::Float(f)),
+        RecordValue::Text(s) => numeric_prefix(&s),
+        RecordValue::Blob(b) => numeric_prefix(&String::from_utf8_lossy(&b)),
+        _ => None,
+    }
+}
+
+/// Parse the longest prefix of `text` that looks like a number
+fn numeric_prefix(text: &str) -> Option<Number> {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let mut end = 0;
+    if matches!(bytes.first(), Some(b'+' | b'-')) {
+        end += 1;
+    }
+    let digits_start = end;
+    while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+        end += 1;
+    }
+    let mut is_float = false;
+    if bytes.get(end) == Some(&b'.') {
+        is_float = true;
+        end += 1;
+        while bytes.get(end).is_some_and(u8::is_ascii_digit) {
+            end += 1;
+        }
+    }
+    if end == digits_start || (is_float && end == digits_start + 1) {
+        return None;
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let mut exponent_end = end + 1;
+        if matches!(bytes.get(exponent_end), Some(b'+' | b'-')) {
+            exponent_end += 1;
+        }
+        if bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+            while bytes.get(exponent_end).is_some_and(u8::is_ascii_digit) {
+                exponent_end += 1;
+            }
+            is_float = true;
+            end = exponent_end;
+        }
+    }
+
+    let prefix = &text[..end];
+    if !is_float {
+        if let Ok(i) = prefix.parse() {
+            return Some(Number::Int(i));
+        }
+    }
+    prefix.parse().ok().map(Number::Float)
+}
+
+/// A `column op value` term of a WHERE or ON clause, which an index or the
+/// table's own B-tree can answer with a seek once the value is known
+#[derive(Debug, Clone, Copy)]
+pub struct ColumnTerm<'a> {
+    pub table: Option<&'a str>,
+    pub column: &'a str,
+    /// `Eq`, `Lt`, `LtEq`, `Gt` or `GtEq`, with the column on the left
+    pub op: BinaryOp,
+    pub value: &'a Expr,
+}
+
+/// The `column op expr` comparisons joined by the top-level ANDs of a
+/// clause, each of which every matching row satisfies. A comparison is
+/// turned around when the column is on the right, and one between two
+/// columns gives a term for each. `column BETWEEN a AND b` gives a term for
+/// each bound. Comparisons with NULL never match, so they are left out.
+pub fn column_terms(expr: &Expr) -> Vec<ColumnTerm<'_>> {
+    let mut terms = Vec::new();
+    collect_column_terms(expr, &mut terms);
+    terms
+}
+
+fn collect_column_terms<'a>(expr: &'a Expr, terms: &mut Vec<ColumnTerm<'a>>) {
+    let is_null = |expr: &Expr| matches!(expr, Expr::Literal(RecordValue::Null));
+    match expr {
+        Expr::Binary { left, op: BinaryOp::And, right } => {
+            collect_column_terms(left, terms);
+            collect_column_terms(right, terms);
+        }
+        Expr::Binary { left, op, right } => {
+            let flipped = match op {
+                BinaryOp::Eq => BinaryOp::Eq,
+                BinaryOp::Lt => BinaryOp::Gt,
+                BinaryOp::LtEq => BinaryOp::GtEq,
+                BinaryOp::Gt => BinaryOp::Lt,
+                BinaryOp::GtEq => BinaryOp::LtEq,
+                _ => return,
+            };
+            if let Expr::Column { table, name } = left.as_ref() {
+                if !is_null(right) {
+                    terms.push(ColumnTerm { table: table.as_deref(), column: name, op: *op, value: right });
+                }
+            }
+            if let Expr::Column { table, name } = right.as_ref() {
+                if !is_null(left) {
+                    terms.push(ColumnTerm { table: table.as_deref(), column: name, op: flipped, value: left });
+                }
+            }
+        }
+        Expr::Between { expr, low, high, negated: false } => {
+            let Expr::Column { table, name } = expr.as_ref() else { return };
+            for (bound, op) in [(low, BinaryOp::GtEq), (high, BinaryOp::LtEq)] {
+                if !is_null(bound) {
+                    terms.push(ColumnTerm { table: table.as_deref(), column: name, op, value: bound });
+                }
+            }
+        }
+        _ => {}
+    }
+}
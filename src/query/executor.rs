@@ -0,0 +1,1638 @@
+use std::cmp::Ordering;
+use std::collections::hash_map::{Entry, HashMap};
+use std::ops::Bound;
+
+use anyhow::{bail, Result};
+
+use super::aggregate::{self, Accumulator, DistinctKey};
+use super::eval::{self, ColumnSlot, RowScope, Source};
+use super::parser::{
+    BinaryOp, DeleteStatement, Expr, InsertStatement, Join, JoinKind, OrderingTerm, ResultColumn, Select, Statement, TableRef,
+    UpdateStatement,
+};
+use crate::database::affinity::compare_collated;
+use crate::database::{Affinity, IndexCell, IndexSchema, IndexedColumn, SchemaObject, TextEncoding};
+use crate::{Database, RecordValue, TableRow, TableRows, TableSchema};
+
+/// Column names and rows produced by a query
+#[derive(Debug, Clone)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<RecordValue>>,
+}
+
+/// Execute a parsed statement. SELECT returns its rows; other statements
+/// return `None`.
+///
+/// Outside an explicit transaction each write is committed on its own; a
+/// failed write rolls back the whole transaction it ran in.
+pub fn execute(db: &mut Database, statement: &Statement) -> Result<Option<ResultSet>> {
+    match statement {
+        Statement::Select(select) => return execute_select(db, select).map(Some),
+        Statement::Insert(insert) => run_write(db, |db| execute_insert(db, insert))?,
+        Statement::Delete(delete) => run_write(db, |db| execute_delete(db, delete))?,
+        Statement::Update(update) => run_write(db, |db| execute_update(db, update))?,
+        Statement::Begin => db.begin()?,
+        Statement::Commit => {
+            if !db.in_transaction() {
+                bail!("cannot commit - no transaction is active");
+            }
+            db.commit()?
+        }
+        Statement::Rollback => {
+            if !db.in_transaction() {
+                bail!("cannot rollback - no transaction is active");
+            }
+            db.rollback()
+        }
+    }
+    Ok(None)
+}
+
+pub fn execute_select(db: &mut Database, select: &Select) -> Result<ResultSet> {
+    let Some(table) = &select.from else {
+        bail!("SELECT without FROM is not supported");
+    };
+    let where_clause = select.where_clause.as_ref();
+    let encoding = db.text_encoding();
+
+    let (offset, limit) = row_window(select, encoding)?;
+
+    if select.is_count_star() {
+        let count = match where_clause {
+            None => db.count_table_rows(&table.name)?,
+            Some(where_clause) => count_matching_rows(db, table, where_clause)?,
+        };
+        let header = match &select.columns[0] {
+            ResultColumn::Expr { alias: Some(alias), .. } => alias.clone(),
+            ResultColumn::Expr { text, .. } => text.clone(),
+            _ => unreachable!(),
+        };
+        let rows = vec![vec![RecordValue::Int(count as i64)]];
+        return Ok(ResultSet { columns: vec![header], rows: rows.into_iter().skip(offset).take(limit.unwrap_or(usize::MAX)).collect() });
+    }
+
+    // The tables in FROM, in the order they are joined
+    let tables: Vec<&TableRef> = std::iter::once(table).chain(select.joins.iter().map(|join| &join.table)).collect();
+    let mut schemas = Vec::with_capacity(tables.len());
+    for table in &tables {
+        let schema = db.get_table_schema(&table.name)?;
+        if schema.columns.is_empty() {
+            bail!("Table {} not found or has no columns", table.name);
+        }
+        schemas.push(schema);
+    }
+    let sources: Vec<_> = tables.iter().zip(&schemas).map(|(table, schema)| Source::new(table, schema)).collect();
+
+    // Work out the header and source of each result column
+    let mut columns = Vec::new();
+    let mut outputs = Vec::new();
+    // The table column behind each result column that is a plain column reference
+    let mut origins = Vec::new();
+    let mut aliases = Vec::new();
+    for column in &select.columns {
+        let expanded: Vec<usize> = match column {
+            ResultColumn::Star => (0..sources.len()).collect(),
+            ResultColumn::TableStar(qualifier) => match sources.iter().position(|source| source.table.is_named(qualifier)) {
+                Some(position) => vec![position],
+                None => bail!("no such table: {}", qualifier),
+            },
+            ResultColumn::Expr { expr, alias, text } => {
+                eval::check_columns(expr, &sources)?;
+                let origin = match expr {
+                    Expr::Column { table: qualifier, name } => Some(eval::resolve_column(&sources, qualifier.as_deref(), name)?),
+                    _ => None,
+                };
+                let header = match (alias, origin) {
+                    (Some(alias), _) => {
+                        aliases.push((alias.as_str(), columns.len()));
+                        alias.clone()
+                    }
+                    // Like SQLite, headers use the declared column name rather than the spelling in the query
+                    (None, Some((source, ColumnSlot::Value(index)))) => sources[source].columns[index].name.clone(),
+                    (None, Some((_, ColumnSlot::RowId))) => "rowid".to_string(),
+                    (None, None) => text.clone(),
+                };
+                columns.push(header);
+                origins.push(origin);
+                outputs.push(Output::Expr(expr));
+                continue;
+            }
+        };
+        for position in expanded {
+            let source = &sources[position];
+            columns.extend(source.columns.iter().map(|c| c.name.clone()));
+            origins.extend((0..source.columns.len()).map(|i| Some((position, ColumnSlot::Value(i)))));
+            outputs.push(Output::AllColumns(position));
+        }
+    }
+
+    if let Some(name) = where_clause.and_then(aggregate::find_aggregate) {
+        bail!("misuse of aggregate: {}()", name);
+    }
+
+    let mut sort_keys = Vec::with_capacity(select.order_by.len());
+    let mut sort_columns = Vec::with_capacity(select.order_by.len());
+    let mut collations = Vec::with_capacity(select.order_by.len());
+    for (position, term) in select.order_by.iter().enumerate() {
+        let key = sort_key(term, position, &aliases, columns.len())?;
+        let column = match key {
+            SortKey::Output(index) => origins[index],
+            SortKey::Expr(expr) => {
+                eval::check_columns(expr, &sources)?;
+                match expr {
+                    Expr::Column { table: qualifier, name } => Some(eval::resolve_column(&sources, qualifier.as_deref(), name)?),
+                    _ => None,
+                }
+            }
+        };
+        // A column sorts with its declared collation
+        let collation = match column {
+            Some((source, ColumnSlot::Value(index))) => sources[source].columns[index].collation.as_deref(),
+            _ => None,
+        };
+        sort_keys.push(key);
+        // Only a query of a single table reads its rows in a B-tree's order, so the slot is enough
+        sort_columns.push(column.map(|(_, slot)| slot));
+        collations.push(collation);
+    }
+
+    // GROUP BY and HAVING may name result columns by alias, and GROUP BY by position too
+    let column_exprs = result_column_exprs(select, &sources);
+    let group_by = select.group_by.iter().enumerate()
+        .map(|(position, expr)| group_term(expr, position, &sources, &aliases, &column_exprs))
+        .collect::<Result<Vec<_>>>()?;
+    let having = match &select.having {
+        Some(having) => {
+            let having = resolve_aliases(having, &sources, &aliases, &column_exprs);
+            eval::check_columns(&having, &sources)?;
+            Some(having)
+        }
+        None => None,
+    };
+
+    let mut calls = Vec::new();
+    for output in &outputs {
+        if let Output::Expr(expr) = output {
+            aggregate::collect_aggregates(expr, &mut calls)?;
+        }
+    }
+    if let Some(having) = &having {
+        aggregate::collect_aggregates(having, &mut calls)?;
+    }
+    for key in &sort_keys {
+        if let SortKey::Expr(expr) = key {
+            aggregate::collect_aggregates(expr, &mut calls)?;
+        }
+    }
+    let grouped = !group_by.is_empty() || !calls.is_empty();
+    if having.is_some() && !grouped {
+        bail!("HAVING clause on a non-aggregate query");
+    }
+
+    let mut rows = Vec::new();
+    let mut keys = Vec::new();
+    let needs_sort;
+    {
+        // Work out the result columns and sort keys of one row, or one group
+        let mut emit = |scope: &RowScope| -> Result<()> {
+            let mut values = Vec::with_capacity(columns.len());
+            for output in &outputs {
+                match output {
+                    Output::AllColumns(source) => {
+                        let count = scope.sources[*source].columns.len();
+                        match &scope.rows[*source] {
+                            Some(row) => values.extend((0..count).map(|i| row.values.get(i).cloned().unwrap_or(RecordValue::Null))),
+                            None => values.extend(std::iter::repeat(RecordValue::Null).take(count)),
+                        }
+                    }
+                    Output::Expr(expr) => values.push(eval::evaluate(expr, scope)?),
+                }
+            }
+            if !select.order_by.is_empty() {
+                let mut row_keys = Vec::with_capacity(sort_keys.len());
+                for key in &sort_keys {
+                    row_keys.push(match key {
+                        SortKey::Output(index) => values[*index].clone(),
+                        SortKey::Expr(expr) => eval::evaluate(expr, scope)?,
+                    });
+                }
+                keys.push(row_keys);
+            }
+            rows.push(values);
+            Ok(())
+        };
+
+        if grouped {
+            let group_collations: Vec<_> = group_by.iter().map(|expr| column_collation(expr, &sources)).collect();
+            let call_collations: Vec<_> = calls.iter()
+                .map(|call| match call {
+                    Expr::Function { args, .. } => args.first().and_then(|arg| column_collation(arg, &sources)),
+                    _ => None,
+                })
+                .collect();
+            let new_group = |key: Vec<RecordValue>| -> Result<Group> {
+                let accumulators = calls.iter().zip(&call_collations)
+                    .map(|(call, collation)| Accumulator::new(call, *collation))
+                    .collect::<Result<_>>()?;
+                Ok(Group { key, accumulators, bare_row: None })
+            };
+            // With a single min() or max(), bare columns come from the row holding that value
+            let min_max_row = matches!(&calls[..], [Expr::Function { name, .. }] if name == "min" || name == "max");
+
+            // Without GROUP BY every row is in one group, which is there even when there are no rows
+            let mut groups = Vec::new();
+            if group_by.is_empty() {
+                groups.push(new_group(Vec::new())?);
+            }
+            {
+                let mut group_positions = HashMap::new();
+                let mut add_row = |row: &[Option<TableRow>]| -> Result<()> {
+                    let scope = RowScope { sources: &sources, rows: row, aggregates: &[], encoding };
+                    let position = if group_by.is_empty() {
+                        0
+                    } else {
+                        let key = group_by.iter().map(|expr| eval::evaluate(expr, &scope)).collect::<Result<Vec<_>>>()?;
+                        let distinct: Vec<_> = key.iter().zip(&group_collations).map(|(value, collation)| DistinctKey::new(value, *collation)).collect();
+                        match group_positions.entry(distinct) {
+                            Entry::Occupied(entry) => *entry.get(),
+                            Entry::Vacant(entry) => {
+                                groups.push(new_group(key)?);
+                                *entry.insert(groups.len() - 1)
+                            }
+                        }
+                    };
+
+                    let group = &mut groups[position];
+                    // Otherwise bare columns come from the group's first row
+                    if group.bare_row.is_none() {
+                        group.bare_row = Some(row.to_vec());
+                    }
+                    for accumulator in &mut group.accumulators {
+                        if accumulator.step(&scope)? && min_max_row {
+                            group.bare_row = Some(row.to_vec());
+                        }
+                    }
+                    Ok(())
+                };
+                if select.joins.is_empty() {
+                    visit_matching_rows(db, table, where_clause, &ScanOrder::Unordered, false, None, &mut |row| add_row(&[Some(row)]))?;
+                } else {
+                    visit_joined_rows(db, &sources, &schemas, &select.joins, where_clause, &mut |row| add_row(row).map(|()| true))?;
+                }
+            }
+
+            // Groups come out in ascending order of their GROUP BY values, as SQLite's sorter gives them
+            groups.sort_by(|a, b| compare_group_keys(&a.key, &b.key, &group_collations, encoding));
+            let null_row = vec![None; sources.len()];
+            for group in groups {
+                let mut aggregates = Vec::with_capacity(calls.len());
+                for (call, accumulator) in calls.iter().zip(&group.accumulators) {
+                    aggregates.push((*call, accumulator.finish()?));
+                }
+                let row = group.bare_row.as_deref().unwrap_or(&null_row);
+                let scope = RowScope { sources: &sources, rows: row, aggregates: &aggregates, encoding };
+                if having.as_ref().map_or(Ok(true), |having| eval::matches(having, &scope))? {
+                    emit(&scope)?;
+                }
+            }
+            needs_sort = !select.order_by.is_empty();
+        } else if !select.joins.is_empty() {
+            // Without a sort the join can stop as soon as the last row needed is found
+            let join_limit = match limit {
+                Some(limit) if select.order_by.is_empty() => Some(offset.saturating_add(limit)),
+                _ => None,
+            };
+            let joined = join_rows(db, &sources, &schemas, &select.joins, where_clause, join_limit)?;
+            for row in joined.chunks(sources.len()) {
+                emit(&RowScope { sources: &sources, rows: row, aggregates: &[], encoding })?;
+            }
+            needs_sort = !select.order_by.is_empty();
+        } else {
+            let planned = plan_order(db, &table.name, &schemas[0], &select.order_by, &sort_columns)?;
+            let (wanted, backwards) = planned.unwrap_or((ScanOrder::Unordered, false));
+            // Without a sort the scan can stop as soon as the last row needed is found
+            let scan_limit = match limit {
+                Some(limit) if select.order_by.is_empty() || wanted != ScanOrder::Unordered => Some(offset.saturating_add(limit)),
+                _ => None,
+            };
+            let (table_data, order) = scan_rows(db, table, where_clause, &wanted, backwards, scan_limit)?;
+            needs_sort = !select.order_by.is_empty() && (wanted == ScanOrder::Unordered || order != wanted);
+
+            for row in table_data.rows {
+                emit(&RowScope { sources: &sources, rows: &[Some(row)], aggregates: &[], encoding })?;
+            }
+        }
+    }
+
+    if needs_sort {
+        let mut keyed: Vec<_> = keys.into_iter().zip(rows).collect();
+        keyed.sort_by(|(a, _), (b, _)| compare_sort_keys(&select.order_by, &collations, encoding, a, b));
+        rows = keyed.into_iter().map(|(_, values)| values).collect();
+    }
+
+    let rows = rows.into_iter().skip(offset).take(limit.unwrap_or(usize::MAX)).collect();
+    Ok(ResultSet { columns, rows })
+}
+
+/// The OFFSET and LIMIT of a SELECT. A negative LIMIT means no limit and a
+/// negative OFFSET counts as zero, as in SQLite.
+fn row_window(select: &Select, encoding: TextEncoding) -> Result<(usize, Option<usize>)> {
+    let integer = |expr: &Expr| -> Result<i64> {
+        match Affinity::Numeric.apply(&eval::evaluate_constant(expr, encoding)?) {
+            RecordValue::Int(n) => Ok(n),
+            _ => bail!("datatype mismatch"),
+        }
+    };
+    let offset = match &select.offset {
+        Some(expr) => integer(expr)?.max(0) as usize,
+        None => 0,
+    };
+    let limit = match &select.limit {
+        Some(expr) => usize::try_from(integer(expr)?).ok(),
+        None => None,
+    };
+    Ok((offset, limit))
+}
+
+/// What a result column reads from each row
+enum Output<'a> {
+    /// Every column of the table at this position in FROM, for `*` and `table.*`
+    AllColumns(usize),
+    Expr(&'a Expr),
+}
+
+/// What an ORDER BY term sorts on
+#[derive(Clone, Copy)]
+enum SortKey<'a> {
+    /// A result column, named by its position or alias
+    Output(usize),
+    Expr(&'a Expr),
+}
+
+/// Resolve an ORDER BY term the way SQLite does: an integer constant K is the
+/// Kth result column and a bare name matching a result alias is that column.
+/// Anything else is an expression over the table's row.
+fn sort_key<'a>(term: &'a OrderingTerm, position: usize, aliases: &[(&str, usize)], column_count: usize) -> Result<SortKey<'a>> {
+    match &term.expr {
+        Expr::Literal(RecordValue::Int(k)) => {
+            if *k < 1 || *k as usize > column_count {
+                bail!("{} ORDER BY term out of range - should be between 1 and {}", ordinal(position + 1), column_count);
+            }
+            Ok(SortKey::Output(*k as usize - 1))
+        }
+        Expr::Column { table: None, name } => Ok(aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(name))
+            .map_or(SortKey::Expr(&term.expr), |&(_, index)| SortKey::Output(index))),
+        expr => Ok(SortKey::Expr(expr)),
+    }
+}
+
+/// "1st", "2nd", "3rd", "4th", ...
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// Order two rows' sort keys by the ORDER BY terms, each compared with
+/// SQLite's cross-type order (NULL, numbers, text, blobs) and its collation
+fn compare_sort_keys(
+    terms: &[OrderingTerm],
+    collations: &[Option<&str>],
+    encoding: TextEncoding,
+    left: &[RecordValue],
+    right: &[RecordValue],
+) -> Ordering {
+    for (((term, collation), a), b) in terms.iter().zip(collations).zip(left).zip(right) {
+        let ordering = match (a, b) {
+            (RecordValue::Null, RecordValue::Null) => Ordering::Equal,
+            (RecordValue::Null, _) if term.nulls_first() => Ordering::Less,
+            (RecordValue::Null, _) => Ordering::Greater,
+            (_, RecordValue::Null) if term.nulls_first() => Ordering::Greater,
+            (_, RecordValue::Null) => Ordering::Less,
+            _ if term.descending => compare_collated(a, b, *collation, encoding).reverse(),
+            _ => compare_collated(a, b, *collation, encoding),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// The expression behind each result column, with `*` and `table.*`
+/// expanded to qualified references to the tables' columns
+fn result_column_exprs(select: &Select, sources: &[Source]) -> Vec<Expr> {
+    let columns_of = |source: &Source| -> Vec<Expr> {
+        let qualifier = source.table.alias.as_ref().unwrap_or(&source.table.name);
+        source.columns.iter().map(|c| Expr::Column { table: Some(qualifier.clone()), name: c.name.clone() }).collect()
+    };
+    let mut exprs = Vec::new();
+    for column in &select.columns {
+        match column {
+            ResultColumn::Star => exprs.extend(sources.iter().flat_map(columns_of)),
+            ResultColumn::TableStar(qualifier) => {
+                if let Some(source) = sources.iter().find(|source| source.table.is_named(qualifier)) {
+                    exprs.extend(columns_of(source));
+                }
+            }
+            ResultColumn::Expr { expr, .. } => exprs.push(expr.clone()),
+        }
+    }
+    exprs
+}
+
+/// Resolve a GROUP BY term: an integer constant K is the expression of the
+/// Kth result column, and aliases stand for their result column's expression
+fn group_term(expr: &Expr, position: usize, sources: &[Source], aliases: &[(&str, usize)], column_exprs: &[Expr]) -> Result<Expr> {
+    let term = match expr {
+        Expr::Literal(RecordValue::Int(k)) => {
+            if *k < 1 || *k as usize > column_exprs.len() {
+                bail!("{} GROUP BY term out of range - should be between 1 and {}", ordinal(position + 1), column_exprs.len());
+            }
+            column_exprs[*k as usize - 1].clone()
+        }
+        expr => resolve_aliases(expr, sources, aliases, column_exprs),
+    };
+    if aggregate::find_aggregate(&term).is_some() {
+        bail!("aggregate functions are not allowed in the GROUP BY clause");
+    }
+    eval::check_columns(&term, sources)?;
+    Ok(term)
+}
+
+/// Copy `expr` with each bare name that is not a table column but is a
+/// result alias replaced by that result column's expression
+fn resolve_aliases(expr: &Expr, sources: &[Source], aliases: &[(&str, usize)], column_exprs: &[Expr]) -> Expr {
+    let resolve = |expr: &Expr| Box::new(resolve_aliases(expr, sources, aliases, column_exprs));
+    match expr {
+        Expr::Column { table: None, name } => {
+            let is_column = eval::resolve_column(sources, None, name).is_ok();
+            match aliases.iter().find(|(alias, _)| alias.eq_ignore_ascii_case(name)) {
+                Some(&(_, index)) if !is_column => column_exprs[index].clone(),
+                _ => expr.clone(),
+            }
+        }
+        Expr::Unary { op, expr } => Expr::Unary { op: *op, expr: resolve(expr) },
+        Expr::Binary { left, op, right } => Expr::Binary { left: resolve(left), op: *op, right: resolve(right) },
+        Expr::Between { expr, low, high, negated } => {
+            Expr::Between { expr: resolve(expr), low: resolve(low), high: resolve(high), negated: *negated }
+        }
+        Expr::Function { name, args, distinct, star } => Expr::Function {
+            name: name.clone(),
+            args: args.iter().map(|arg| *resolve(arg)).collect(),
+            distinct: *distinct,
+            star: *star,
+        },
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Parameter(_) => expr.clone(),
+    }
+}
+
+/// The collation an expression compares with: a column's declared one
+fn column_collation<'a>(expr: &Expr, sources: &[Source<'a>]) -> Option<&'a str> {
+    let Expr::Column { table, name } = expr else {
+        return None;
+    };
+    match eval::resolve_column(sources, table.as_deref(), name) {
+        Ok((source, ColumnSlot::Value(index))) => sources[source].columns[index].collation.as_deref(),
+        _ => None,
+    }
+}
+
+/// The rows of one GROUP BY group read so far, as the state of each
+/// aggregate call
+struct Group<'a> {
+    /// The group's GROUP BY values
+    key: Vec<RecordValue>,
+    accumulators: Vec<Accumulator<'a>>,
+    /// The row, holding a row of every source, that bare columns read
+    bare_row: Option<Vec<Option<TableRow>>>,
+}
+
+/// Order two groups by their GROUP BY values, each under its collation
+fn compare_group_keys(a: &[RecordValue], b: &[RecordValue], collations: &[Option<&str>], encoding: TextEncoding) -> Ordering {
+    a.iter().zip(b).zip(collations)
+        .map(|((a, b), collation)| compare_collated(a, b, *collation, encoding))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// The order a scan returns rows in, as far as the planner relies on it
+#[derive(Debug, Clone)]
+enum ScanOrder {
+    Unordered,
+    /// Ascending rowid, the order of a rowid table's own B-tree
+    RowId,
+    /// Key order of an index, ties broken by rowid
+    Index(SchemaObject),
+}
+
+impl PartialEq for ScanOrder {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ScanOrder::Unordered, ScanOrder::Unordered) | (ScanOrder::RowId, ScanOrder::RowId) => true,
+            (ScanOrder::Index(a), ScanOrder::Index(b)) => a.name == b.name,
+            _ => false,
+        }
+    }
+}
+
+/// A B-tree order that already gives the rows in ORDER BY order, and whether
+/// it has to be read backwards. `columns` holds the table column each term
+/// sorts on, if it is a plain column reference.
+///
+/// Sorting on the rowid (or its alias) first follows the table itself, and
+/// since rowids are unique any later terms never matter. Sorting on the
+/// leading columns of an index follows that index, and once every index
+/// column is used the rowid may follow, as it breaks ties between equal
+/// keys. Each term must run the index column's direction, or every term the
+/// opposite one, NULLs (which only an index holds) must keep their default
+/// place, and the index must collate like the column.
+fn plan_order(db: &mut Database, table_name: &str, schema: &TableSchema, terms: &[OrderingTerm], columns: &[Option<ColumnSlot>]) -> Result<Option<(ScanOrder, bool)>> {
+    let Some(first) = terms.first() else {
+        return Ok(None);
+    };
+    if schema.without_rowid {
+        return Ok(None);
+    }
+    let is_rowid = |column: Option<ColumnSlot>| match column {
+        Some(ColumnSlot::RowId) => true,
+        Some(ColumnSlot::Value(index)) => schema.columns[index].is_rowid_alias,
+        None => false,
+    };
+
+    if is_rowid(columns[0]) {
+        return Ok(Some((ScanOrder::RowId, first.descending)));
+    }
+    if terms.iter().any(|term| term.nulls_first() == term.descending) {
+        return Ok(None);
+    }
+
+    for index in db.get_index_schemas(table_name)? {
+        // A partial index leaves rows out
+        if index.where_clause.is_some() || terms.len() > index.columns.len() + 1 {
+            continue;
+        }
+        let backwards = first.descending != index.columns[0].descending;
+        let follows = terms.iter().zip(columns).enumerate().all(|(i, (term, column))| {
+            let Some(key) = index.columns.get(i) else {
+                // Past the index columns only the rowid, ascending in the index, is left
+                return is_rowid(*column) && term.descending == backwards;
+            };
+            let Some(ColumnSlot::Value(position)) = column else {
+                return false;
+            };
+            let table_column = &schema.columns[*position];
+            let same_collation = match &key.collation {
+                Some(collation) => table_column.collation.as_deref().unwrap_or("binary").eq_ignore_ascii_case(collation),
+                None => true,
+            };
+            table_column.name.eq_ignore_ascii_case(&key.name)
+                && same_collation
+                && (term.descending != key.descending) == backwards
+        });
+        if follows {
+            let index = db.find_index_info(&index.name)?;
+            return Ok(Some((ScanOrder::Index(index), backwards)));
+        }
+    }
+    Ok(None)
+}
+
+/// Rows of `table` matching the optional WHERE clause. When its top-level
+/// AND terms compare the rowid, the primary key of a WITHOUT ROWID table or
+/// the leading columns of an index with constants, only the rows in that
+/// range of the B-tree are read and checked against the whole clause.
+fn scan_table(db: &mut Database, table: &TableRef, where_clause: Option<&Expr>) -> Result<TableRows> {
+    Ok(scan_rows(db, table, where_clause, &ScanOrder::Unordered, false, None)?.0)
+}
+
+/// Like [`scan_table`], also returning the order of the rows. Rows are read
+/// in the `wanted` order where possible, `backwards` reversing it, and
+/// reading stops once `limit` rows have matched. A lookup that cannot give
+/// the wanted order returns all the rows it found, in the table's order.
+fn scan_rows(
+    db: &mut Database,
+    table: &TableRef,
+    where_clause: Option<&Expr>,
+    wanted: &ScanOrder,
+    backwards: bool,
+    limit: Option<usize>,
+) -> Result<(TableRows, ScanOrder)> {
+    let mut rows = Vec::new();
+    let (schema, order) = visit_matching_rows(db, table, where_clause, wanted, backwards, limit, &mut |row| {
+        rows.push(row);
+        Ok(())
+    })?;
+    Ok((TableRows { columns: schema.columns, rows, without_rowid: schema.without_rowid }, order))
+}
+
+/// Number of rows of `table` matching a WHERE clause, counted as they are
+/// read rather than collected
+fn count_matching_rows(db: &mut Database, table: &TableRef, where_clause: &Expr) -> Result<usize> {
+    let mut count = 0;
+    visit_matching_rows(db, table, Some(where_clause), &ScanOrder::Unordered, false, None, &mut |_| {
+        count += 1;
+        Ok(())
+    })?;
+    Ok(count)
+}
+
+/// Hand the rows [`scan_rows`] would return to `found` as they are read.
+/// Returns the table's schema and the order the rows came in.
+fn visit_matching_rows(
+    db: &mut Database,
+    table: &TableRef,
+    where_clause: Option<&Expr>,
+    wanted: &ScanOrder,
+    backwards: bool,
+    limit: Option<usize>,
+    found: &mut dyn FnMut(TableRow) -> Result<()>,
+) -> Result<(TableSchema, ScanOrder)> {
+    let schema = db.get_table_schema(&table.name)?;
+    let encoding = db.text_encoding();
+    let has_rowid = !schema.without_rowid;
+    let sources = [Source::new(table, &schema)];
+    // Every way of reading a rowid table below yields ascending rowids,
+    // unless it follows an index
+    let table_order = if has_rowid { ScanOrder::RowId } else { ScanOrder::Unordered };
+
+    let lookup = match where_clause {
+        Some(where_clause) => {
+            eval::check_columns(where_clause, &sources)?;
+            plan_lookup(db, table, &schema, where_clause)?
+        }
+        None => None,
+    };
+    let order = match (&lookup, wanted) {
+        (Some(Lookup::Index { name, .. }), ScanOrder::Index(index)) if *name == index.name => wanted.clone(),
+        (None, ScanOrder::Index(_)) => wanted.clone(),
+        // Without a wanted order, follow the index like SQLite does, so a
+        // LIMIT can stop the scan
+        (Some(Lookup::Index { name, .. }), ScanOrder::Unordered) => ScanOrder::Index(db.find_index_info(name)?),
+        _ => table_order.clone(),
+    };
+    // Stopping early or reading backwards only helps when the rows come in the wanted order
+    let in_wanted_order = order == *wanted;
+    let limit = if in_wanted_order || *wanted == ScanOrder::Unordered { limit } else { None };
+    let backwards = backwards && in_wanted_order;
+
+    let mut matched = 0;
+    if limit != Some(0) {
+        let mut visit = |row: TableRow| -> Result<bool> {
+            let row = [Some(row)];
+            let scope = RowScope { sources: &sources, rows: &row, aggregates: &[], encoding };
+            if where_clause.map_or(Ok(true), |where_clause| eval::matches(where_clause, &scope))? {
+                if let [Some(row)] = row {
+                    found(row)?;
+                }
+                matched += 1;
+            }
+            Ok(!limit.is_some_and(|limit| matched >= limit))
+        };
+        match (lookup, wanted) {
+            (Some(lookup), _) => {
+                let in_index_order = order != table_order;
+                visit_lookup(db, &table.name, &schema, lookup, in_index_order, backwards, &mut visit)?;
+            }
+            (None, ScanOrder::Index(index)) => {
+                let lookup = Lookup::Index { name: index.name.clone(), lower: Bound::Unbounded, upper: Bound::Unbounded };
+                visit_lookup(db, &table.name, &schema, lookup, true, backwards, &mut visit)?;
+            }
+            (None, _) => db.visit_table_rows(&table.name, backwards, &mut visit)?,
+        }
+    }
+
+    Ok((schema, order))
+}
+
+/// A range of one B-tree that holds every row a WHERE clause can match
+enum Lookup {
+    /// Rowids of a rowid table
+    RowIds(Bound<i64>, Bound<i64>),
+    /// Keys of a WITHOUT ROWID table
+    PrimaryKey(Bound<Vec<RecordValue>>, Bound<Vec<RecordValue>>),
+    /// Keys of an index
+    Index { name: String, lower: Bound<Vec<RecordValue>>, upper: Bound<Vec<RecordValue>> },
+}
+
+/// The lower and upper bound of a range of B-tree keys
+type KeyBounds = (Bound<Vec<RecordValue>>, Bound<Vec<RecordValue>>);
+
+/// Pick the B-tree range to read for a WHERE clause, if any of its terms
+/// compare a column with a constant
+fn plan_lookup(db: &mut Database, table: &TableRef, schema: &TableSchema, where_clause: &Expr) -> Result<Option<Lookup>> {
+    let sources = [Source::new(table, schema)];
+    // Keys are stored with the column's affinity, so convert each value the same way
+    let terms: Vec<_> = eval::column_terms(where_clause)
+        .into_iter()
+        .filter_map(|term| {
+            let Expr::Literal(value) = term.value else {
+                return None;
+            };
+            let (_, slot) = eval::resolve_column(&sources, term.table, term.column).ok()?;
+            let value = match slot {
+                ColumnSlot::Value(index) => schema.columns[index].affinity.apply(value),
+                ColumnSlot::RowId => Affinity::Integer.apply(value),
+            };
+            Some((slot, term.op, value))
+        })
+        .collect();
+    if terms.is_empty() {
+        return Ok(None);
+    }
+    let indexes = db.get_index_schemas(&table.name)?;
+    Ok(choose_lookup(schema, &indexes, &terms))
+}
+
+/// The B-tree range that `column op value` terms narrow the rows down to,
+/// if any. The rowid comes first, then the primary key of a WITHOUT ROWID
+/// table, then the index whose leading columns the most terms fix.
+fn choose_lookup(schema: &TableSchema, indexes: &[IndexSchema], terms: &[(ColumnSlot, BinaryOp, RecordValue)]) -> Option<Lookup> {
+    if !schema.without_rowid {
+        let is_rowid = |slot: ColumnSlot| match slot {
+            ColumnSlot::RowId => true,
+            ColumnSlot::Value(index) => schema.columns[index].is_rowid_alias,
+        };
+        let mut lower = Bound::Unbounded;
+        let mut upper = Bound::Unbounded;
+        for (_, op, value) in terms.iter().filter(|(slot, _, _)| is_rowid(*slot)) {
+            let (low, high) = rowid_bounds(*op, value);
+            if matches!(lower, Bound::Unbounded) {
+                lower = low;
+            }
+            if matches!(upper, Bound::Unbounded) {
+                upper = high;
+            }
+        }
+        if !matches!((&lower, &upper), (Bound::Unbounded, Bound::Unbounded)) {
+            return Some(Lookup::RowIds(lower, upper));
+        }
+    } else if let Some((_, (lower, upper))) = key_range(schema, &schema.primary_key, terms) {
+        return Some(Lookup::PrimaryKey(lower, upper));
+    }
+
+    let mut best: Option<(usize, Lookup)> = None;
+    for index in indexes {
+        // A partial index leaves rows out
+        if index.where_clause.is_some() {
+            continue;
+        }
+        if let Some((score, (lower, upper))) = key_range(schema, &index.columns, terms) {
+            if !best.as_ref().is_some_and(|(best_score, _)| *best_score >= score) {
+                best = Some((score, Lookup::Index { name: index.name.clone(), lower, upper }));
+            }
+        }
+    }
+    best.map(|(_, lookup)| lookup)
+}
+
+/// The rowids `rowid op value` allows, as a lower and an upper bound.
+/// Rowids are integers, so a real bound is rounded inwards; a comparison
+/// with text or a blob gives no bounds and is left to the scan.
+fn rowid_bounds(op: BinaryOp, value: &RecordValue) -> (Bound<i64>, Bound<i64>) {
+    let (at_least, at_most) = match value {
+        RecordValue::Int(n) => (*n, *n),
+        RecordValue::Float(f) => (f.ceil() as i64, f.floor() as i64),
+        _ => return (Bound::Unbounded, Bound::Unbounded),
+    };
+    match op {
+        BinaryOp::Eq => (Bound::Included(at_least), Bound::Included(at_most)),
+        BinaryOp::Gt if at_least == at_most => (Bound::Excluded(at_least), Bound::Unbounded),
+        BinaryOp::Gt | BinaryOp::GtEq => (Bound::Included(at_least), Bound::Unbounded),
+        BinaryOp::Lt if at_least == at_most => (Bound::Unbounded, Bound::Excluded(at_most)),
+        BinaryOp::Lt | BinaryOp::LtEq => (Bound::Unbounded, Bound::Included(at_most)),
+        _ => (Bound::Unbounded, Bound::Unbounded),
+    }
+}
+
+/// The range of a B-tree keyed by `key_columns` that `terms` confine the
+/// rows to: equality terms on its leading columns, then at most a pair of
+/// range terms on the next one. The score counts the columns used, ranking
+/// an equality above a range. WHERE compares values as BINARY, so a column
+/// keyed with another collation ends the usable part of the key.
+fn key_range(
+    schema: &TableSchema,
+    key_columns: &[IndexedColumn],
+    terms: &[(ColumnSlot, BinaryOp, RecordValue)],
+) -> Option<(usize, KeyBounds)> {
+    let mut prefix = Vec::new();
+    let mut range = None;
+    for key in key_columns {
+        let Some(index) = schema.columns.iter().position(|c| c.name.eq_ignore_ascii_case(&key.name)) else {
+            break;
+        };
+        let collation = key.collation.as_ref().or(schema.columns[index].collation.as_ref());
+        if !collation.map_or(true, |collation| collation.eq_ignore_ascii_case("binary")) {
+            break;
+        }
+
+        let on_column = || terms.iter().filter(|(slot, _, _)| *slot == ColumnSlot::Value(index));
+        if let Some((_, _, value)) = on_column().find(|(_, op, _)| *op == BinaryOp::Eq) {
+            prefix.push(value.clone());
+            continue;
+        }
+        let low = on_column().find_map(|(_, op, value)| match op {
+            BinaryOp::Gt => Some((value.clone(), false)),
+            BinaryOp::GtEq => Some((value.clone(), true)),
+            _ => None,
+        });
+        let high = on_column().find_map(|(_, op, value)| match op {
+            BinaryOp::Lt => Some((value.clone(), false)),
+            BinaryOp::LtEq => Some((value.clone(), true)),
+            _ => None,
+        });
+        if low.is_some() || high.is_some() {
+            // NULL sorts first but never satisfies a comparison
+            let low = low.or(Some((RecordValue::Null, false)));
+            range = Some(if key.descending { (high, low) } else { (low, high) });
+        }
+        break;
+    }
+
+    if prefix.is_empty() && range.is_none() {
+        return None;
+    }
+    let score = prefix.len() * 2 + usize::from(range.is_some());
+    let (low, high) = range.unwrap_or((None, None));
+    let bound = |end: Option<(RecordValue, bool)>| match end {
+        Some((value, inclusive)) => {
+            let mut key = prefix.clone();
+            key.push(value);
+            if inclusive { Bound::Included(key) } else { Bound::Excluded(key) }
+        }
+        None if prefix.is_empty() => Bound::Unbounded,
+        None => Bound::Included(prefix.clone()),
+    };
+    Some((score, (bound(low), bound(high))))
+}
+
+/// Visit the rows a lookup selects until `visit` returns false: an index
+/// range in index order when `in_index_order` is set and otherwise in table
+/// order, and a rowid or index range reversed when `backwards` is set
+fn visit_lookup(
+    db: &mut Database,
+    table_name: &str,
+    schema: &TableSchema,
+    lookup: Lookup,
+    in_index_order: bool,
+    backwards: bool,
+    visit: &mut dyn FnMut(TableRow) -> Result<bool>,
+) -> Result<()> {
+    match lookup {
+        Lookup::RowIds(lower, upper) => visit_rowid_range(db, table_name, lower, upper, backwards, visit),
+        Lookup::PrimaryKey(lower, upper) => {
+            let storage_order = schema.storage_order();
+            let mut cursor = db.primary_key_cursor(table_name)?;
+            cursor.set_range(lower, upper);
+            for entry in cursor {
+                if !visit(Database::without_rowid_row(&storage_order, entry?.key))? {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        Lookup::Index { name, lower, upper } if schema.without_rowid => {
+            let Some(index) = db.get_index_schemas(table_name)?.into_iter().find(|index| index.name == name) else {
+                bail!("Index {} not found", name);
+            };
+            // The table has no order of its own, so its rows come in index order
+            let primary_key = schema.primary_key_positions(&schema.index_key_columns(&index));
+            visit_index_batches(db, &name, (lower, upper), backwards, &mut |db, entries| {
+                visit_primary_keys(db, table_name, schema, &primary_key, entries, visit)
+            })
+        }
+        Lookup::Index { name, lower, upper } => {
+            visit_index_range(db, table_name, &name, (lower, upper), in_index_order, backwards, visit)
+        }
+    }
+}
+
+/// Visit the rows of a rowid table whose rowids lie between the bounds,
+/// in rowid order or in reverse, until `visit` returns false
+fn visit_rowid_range(
+    db: &mut Database,
+    table_name: &str,
+    lower: Bound<i64>,
+    upper: Bound<i64>,
+    backwards: bool,
+    visit: &mut dyn FnMut(TableRow) -> Result<bool>,
+) -> Result<()> {
+    let mut cursor = db.table_cursor(table_name)?;
+    let mut row = if backwards {
+        // Seek just past the upper bound, then step back from there
+        let past_upper = match upper {
+            Bound::Included(rowid) if rowid < i64::MAX => Some(rowid + 1),
+            Bound::Excluded(rowid) => Some(rowid),
+            _ => None,
+        };
+        match past_upper {
+            Some(rowid) => {
+                cursor.seek(rowid)?;
+                cursor.prev().transpose()?
+            }
+            None => cursor.seek_last()?,
+        }
+    } else {
+        match lower {
+            Bound::Included(rowid) => cursor.seek(rowid)?,
+            Bound::Excluded(rowid) if rowid < i64::MAX => cursor.seek(rowid + 1)?,
+            Bound::Excluded(_) => None,
+            Bound::Unbounded => cursor.seek_first()?,
+        }
+    };
+
+    while let Some(current) = row {
+        let rowid = current.row_id as i64;
+        let in_range = if backwards {
+            match lower {
+                Bound::Included(low) => rowid >= low,
+                Bound::Excluded(low) => rowid > low,
+                Bound::Unbounded => true,
+            }
+        } else {
+            match upper {
+                Bound::Included(high) => rowid <= high,
+                Bound::Excluded(high) => rowid < high,
+                Bound::Unbounded => true,
+            }
+        };
+        if !in_range || !visit(current)? {
+            break;
+        }
+        row = if backwards { cursor.prev() } else { cursor.next() }.transpose()?;
+    }
+    Ok(())
+}
+
+/// Index entries [`visit_index_range`] reads before fetching their rows.
+/// The first batch is small for the sake of LIMIT; later ones double up to
+/// the maximum so a long scan seldom has to find its place in the index again.
+const FIRST_INDEX_BATCH: usize = 16;
+const MAX_INDEX_BATCH: usize = 4096;
+
+/// Visit the rows whose index entries lie in a range, in index order or
+/// (sorted by rowid) in table order, either one reversed by `backwards`,
+/// until `visit` returns false
+fn visit_index_range(
+    db: &mut Database,
+    table_name: &str,
+    index_name: &str,
+    (lower, upper): KeyBounds,
+    in_index_order: bool,
+    backwards: bool,
+    visit: &mut dyn FnMut(TableRow) -> Result<bool>,
+) -> Result<()> {
+    if !in_index_order {
+        // Table order needs every rowid in the range before the first row
+        let mut row_ids = Vec::new();
+        let mut cursor = db.index_cursor(index_name)?;
+        cursor.set_range(lower, upper);
+        for entry in cursor {
+            row_ids.push(entry?.row_id as i64);
+        }
+        row_ids.sort_unstable();
+        if backwards {
+            row_ids.reverse();
+        }
+        return visit_row_ids(db, table_name, &row_ids, visit).map(|_| ());
+    }
+
+    visit_index_batches(db, index_name, (lower, upper), backwards, &mut |db, entries| {
+        let row_ids: Vec<_> = entries.iter().map(|key| key.row_id as i64).collect();
+        visit_row_ids(db, table_name, &row_ids, visit)
+    })
+}
+
+/// Read the entries of an index range in order, or in reverse, a batch at
+/// a time, handing each batch to `visit_batch` until it returns false
+fn visit_index_batches(
+    db: &mut Database,
+    index_name: &str,
+    (mut lower, mut upper): KeyBounds,
+    backwards: bool,
+    visit_batch: &mut dyn FnMut(&mut Database, &[IndexCell]) -> Result<bool>,
+) -> Result<()> {
+    // The cursors borrow the database, so alternate between reading a batch
+    // of entries and their rows, resuming past the last entry read. With the
+    // rowid or primary key included, an entry's key is unique.
+    let mut batch_size = FIRST_INDEX_BATCH;
+    loop {
+        let mut entries = Vec::with_capacity(batch_size);
+        let mut last_key = None;
+        let mut cursor = db.index_cursor(index_name)?;
+        cursor.set_range(lower.clone(), upper.clone());
+        let has_rowid = cursor.has_rowid();
+        let mut entry = if backwards { cursor.seek_last()?.map(Ok) } else { cursor.next() };
+        while let Some(found) = entry {
+            let found = found?;
+            if entries.len() + 1 == batch_size {
+                let mut key = found.key.clone();
+                if has_rowid {
+                    key.push(RecordValue::Int(found.row_id as i64));
+                }
+                last_key = Some(key);
+                entries.push(found);
+                break;
+            }
+            entries.push(found);
+            entry = if backwards { cursor.prev() } else { cursor.next() };
+        }
+
+        if !visit_batch(db, &entries)? {
+            return Ok(());
+        }
+        batch_size = (batch_size * 2).min(MAX_INDEX_BATCH);
+        match last_key {
+            Some(key) if backwards => upper = Bound::Excluded(key),
+            Some(key) => lower = Bound::Excluded(key),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Visit the rows of a WITHOUT ROWID table that index entries point to,
+/// reading each one's primary key from the `primary_key` positions of its
+/// key. Returns false if `visit` asked to stop.
+fn visit_primary_keys(
+    db: &mut Database,
+    table_name: &str,
+    schema: &TableSchema,
+    primary_key: &[usize],
+    entries: &[IndexCell],
+    visit: &mut dyn FnMut(TableRow) -> Result<bool>,
+) -> Result<bool> {
+    let storage_order = schema.storage_order();
+    let mut cursor = db.primary_key_cursor(table_name)?;
+    for entry in entries {
+        let key: Vec<_> = primary_key.iter().map(|&position| entry.key[position].clone()).collect();
+        cursor.set_range(Bound::Included(key.clone()), Bound::Included(key));
+        if let Some(found) = cursor.next().transpose()? {
+            if !visit(Database::without_rowid_row(&storage_order, found.key))? {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Visit the rows with the given rowids in turn, skipping any that are
+/// missing. Returns false if `visit` asked to stop.
+fn visit_row_ids(db: &mut Database, table_name: &str, row_ids: &[i64], visit: &mut dyn FnMut(TableRow) -> Result<bool>) -> Result<bool> {
+    let mut cursor = db.table_cursor(table_name)?;
+    for &row_id in row_ids {
+        if let Some(row) = cursor.seek(row_id)?.filter(|row| row.row_id as i64 == row_id) {
+            if !visit(row)? {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// The rows of the tables in FROM that the joins and the WHERE clause keep,
+/// each as a row of every source in turn, so `sources.len()` entries per
+/// joined row. The tables are read in FROM order, the later ones once for
+/// each combination of rows of those before them, and reading stops once
+/// `limit` joined rows are found.
+fn join_rows(
+    db: &mut Database,
+    sources: &[Source],
+    schemas: &[TableSchema],
+    joins: &[Join],
+    where_clause: Option<&Expr>,
+    limit: Option<usize>,
+) -> Result<Vec<Option<TableRow>>> {
+    let levels = plan_join(db, sources, joins, where_clause)?;
+    let mut joined = Vec::new();
+    if limit != Some(0) {
+        let nested_loop = NestedLoop { sources, schemas, levels };
+        nested_loop.extend(db, &mut Vec::with_capacity(sources.len()), &mut |row| {
+            joined.extend(row.iter().cloned());
+            Ok(!limit.is_some_and(|limit| joined.len() >= limit * row.len()))
+        })?;
+    }
+    Ok(joined)
+}
+
+/// Hand the rows [`join_rows`] would return to `found` one at a time, as
+/// they are joined, until it returns false
+fn visit_joined_rows(
+    db: &mut Database,
+    sources: &[Source],
+    schemas: &[TableSchema],
+    joins: &[Join],
+    where_clause: Option<&Expr>,
+    found: &mut dyn FnMut(&[Option<TableRow>]) -> Result<bool>,
+) -> Result<()> {
+    let levels = plan_join(db, sources, joins, where_clause)?;
+    let nested_loop = NestedLoop { sources, schemas, levels };
+    nested_loop.extend(db, &mut Vec::with_capacity(sources.len()), found)?;
+    Ok(())
+}
+
+/// How the nested loop reads one table of a join
+struct JoinLevel<'a> {
+    kind: JoinKind,
+    /// Terms a row of this table must satisfy to join the rows before it:
+    /// the ON clause of a LEFT JOIN, or every ON and WHERE term of an inner
+    /// join that reads this table last
+    conditions: Vec<&'a Expr>,
+    /// WHERE terms that read a LEFT JOIN's table last, which also apply to
+    /// the row of NULLs it adds when nothing matches
+    filters: Vec<&'a Expr>,
+    /// Comparisons in `conditions` of this table's columns with values known
+    /// before it is read, which can narrow it to a B-tree range
+    keys: Vec<JoinKey<'a>>,
+    indexes: Vec<IndexSchema>,
+    /// Every row of the table, read once up front when it has no keys
+    all_rows: Option<Vec<TableRow>>,
+}
+
+/// A `column op value` term usable for looking up one table of a join
+struct JoinKey<'a> {
+    slot: ColumnSlot,
+    op: BinaryOp,
+    /// Reads only the tables before this one
+    value: &'a Expr,
+    /// The column's affinity, which its stored keys have
+    affinity: Affinity,
+}
+
+/// Give each AND term of the ON and WHERE clauses to the table it is checked
+/// at, and find the terms each table can be looked up by
+fn plan_join<'a>(db: &mut Database, sources: &[Source], joins: &'a [Join], where_clause: Option<&'a Expr>) -> Result<Vec<JoinLevel<'a>>> {
+    let mut levels: Vec<_> = std::iter::once(JoinKind::Inner)
+        .chain(joins.iter().map(|join| join.kind))
+        .map(|kind| JoinLevel { kind, conditions: Vec::new(), filters: Vec::new(), keys: Vec::new(), indexes: Vec::new(), all_rows: None })
+        .collect();
+
+    // An inner join's ON clause filters the same rows its WHERE clause would
+    let mut terms = Vec::new();
+    for (position, join) in joins.iter().enumerate() {
+        let Some(on) = &join.on else {
+            continue;
+        };
+        if let Some(name) = aggregate::find_aggregate(on) {
+            bail!("misuse of aggregate function {}()", name);
+        }
+        if join.kind == JoinKind::Inner {
+            and_terms(on, &mut terms);
+            continue;
+        }
+        let mut on_terms = Vec::new();
+        and_terms(on, &mut on_terms);
+        for term in on_terms {
+            if last_source(term, sources)?.is_some_and(|last| last > position + 1) {
+                bail!("ON clause references tables to its right");
+            }
+            levels[position + 1].conditions.push(term);
+        }
+    }
+    if let Some(where_clause) = where_clause {
+        and_terms(where_clause, &mut terms);
+    }
+    for term in terms {
+        // Each term is checked as soon as every table it reads has a row
+        let level = &mut levels[last_source(term, sources)?.unwrap_or(0)];
+        match level.kind {
+            JoinKind::Inner => level.conditions.push(term),
+            JoinKind::Left => level.filters.push(term),
+        }
+    }
+
+    for (position, level) in levels.iter_mut().enumerate() {
+        let source = &sources[position];
+        for condition in &level.conditions {
+            for term in eval::column_terms(condition) {
+                let Ok((column_source, slot)) = eval::resolve_column(sources, term.table, term.column) else {
+                    continue;
+                };
+                if column_source != position || last_source(term.value, sources)?.is_some_and(|last| last >= position) {
+                    continue;
+                }
+                let affinity = match slot {
+                    ColumnSlot::Value(index) => source.columns[index].affinity,
+                    ColumnSlot::RowId => Affinity::Integer,
+                };
+                // Against a numeric column a text column compares as text, which the
+                // keys of a text column would not find every match for
+                let value_affinity = match term.value {
+                    Expr::Column { table, name } => match eval::resolve_column(sources, table.as_deref(), name)? {
+                        (value_source, ColumnSlot::Value(index)) => Some(sources[value_source].columns[index].affinity),
+                        (_, ColumnSlot::RowId) => Some(Affinity::Integer),
+                    },
+                    _ => None,
+                };
+                if value_affinity.is_some_and(Affinity::is_numeric) && !affinity.is_numeric() {
+                    continue;
+                }
+                level.keys.push(JoinKey { slot, op: term.op, value: term.value, affinity });
+            }
+        }
+        if level.keys.is_empty() {
+            level.all_rows = Some(scan_table(db, source.table, None)?.rows);
+        } else {
+            level.indexes = db.get_index_schemas(&source.table.name)?;
+        }
+    }
+    Ok(levels)
+}
+
+/// Add the terms joined by the top-level ANDs of `expr` to `terms`
+fn and_terms<'a>(expr: &'a Expr, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary { left, op: BinaryOp::And, right } => {
+            and_terms(left, terms);
+            and_terms(right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+/// The position in FROM of the last table `expr` reads a column of, if it
+/// reads any
+fn last_source(expr: &Expr, sources: &[Source]) -> Result<Option<usize>> {
+    Ok(match expr {
+        Expr::Column { table, name } => Some(eval::resolve_column(sources, table.as_deref(), name)?.0),
+        Expr::Unary { expr, .. } => last_source(expr, sources)?,
+        Expr::Binary { left, right, .. } => last_source(left, sources)?.max(last_source(right, sources)?),
+        Expr::Between { expr, low, high, .. } => {
+            last_source(expr, sources)?.max(last_source(low, sources)?).max(last_source(high, sources)?)
+        }
+        Expr::Function { args, .. } => {
+            let mut last = None;
+            for arg in args {
+                last = last.max(last_source(arg, sources)?);
+            }
+            last
+        }
+        Expr::Literal(_) | Expr::Parameter(_) => None,
+    })
+}
+
+/// A planned join, read one table inside another
+struct NestedLoop<'a> {
+    sources: &'a [Source<'a>],
+    schemas: &'a [TableSchema],
+    levels: Vec<JoinLevel<'a>>,
+}
+
+impl NestedLoop<'_> {
+    /// Join each row of the next table that matches `row`, which holds a row
+    /// of every table before it, and go on to the tables after it. A LEFT
+    /// JOIN with no match joins a row of NULLs instead. Each joined row goes
+    /// to `found`; returns false once that asks to stop.
+    fn extend(
+        &self,
+        db: &mut Database,
+        row: &mut Vec<Option<TableRow>>,
+        found: &mut dyn FnMut(&[Option<TableRow>]) -> Result<bool>,
+    ) -> Result<bool> {
+        let position = row.len();
+        if position == self.sources.len() {
+            return found(row);
+        }
+
+        let level = &self.levels[position];
+        let looked_up;
+        let candidates = match &level.all_rows {
+            Some(rows) => rows,
+            None => {
+                looked_up = self.lookup(db, row)?;
+                &looked_up
+            }
+        };
+        let sources = &self.sources[..=position];
+        let encoding = db.text_encoding();
+        let holds = |terms: &[&Expr], row: &[Option<TableRow>]| -> Result<bool> {
+            let scope = RowScope { sources, rows: row, aggregates: &[], encoding };
+            for term in terms {
+                if !eval::matches(term, &scope)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+
+        let mut matched = false;
+        for candidate in candidates {
+            row.push(Some(candidate.clone()));
+            let mut more = true;
+            if holds(&level.conditions, row)? {
+                matched = true;
+                more = !holds(&level.filters, row)? || self.extend(db, row, found)?;
+            }
+            row.pop();
+            if !more {
+                return Ok(false);
+            }
+        }
+        if level.kind == JoinKind::Left && !matched {
+            row.push(None);
+            let more = !holds(&level.filters, row)? || self.extend(db, row, found)?;
+            row.pop();
+            return Ok(more);
+        }
+        Ok(true)
+    }
+
+    /// The rows of the next table within the B-tree range its keys give
+    /// for the rows before it, or all of its rows if they give none
+    fn lookup(&self, db: &mut Database, row: &[Option<TableRow>]) -> Result<Vec<TableRow>> {
+        let position = row.len();
+        let level = &self.levels[position];
+        let encoding = db.text_encoding();
+        let scope = RowScope { sources: &self.sources[..position], rows: row, aggregates: &[], encoding };
+        let mut terms = Vec::with_capacity(level.keys.len());
+        for key in &level.keys {
+            match eval::evaluate(key.value, &scope)? {
+                // A comparison with NULL holds for no row
+                RecordValue::Null => return Ok(Vec::new()),
+                value => terms.push((key.slot, key.op, key.affinity.apply(&value))),
+            }
+        }
+
+        let table_name = &self.sources[position].table.name;
+        let schema = &self.schemas[position];
+        let mut rows = Vec::new();
+        let mut visit = |row: TableRow| -> Result<bool> {
+            rows.push(row);
+            Ok(true)
+        };
+        match choose_lookup(schema, &level.indexes, &terms) {
+            Some(lookup) => visit_lookup(db, table_name, schema, lookup, false, false, &mut visit)?,
+            None => db.visit_table_rows(table_name, false, &mut visit)?,
+        }
+        Ok(rows)
+    }
+}
+
+fn run_write(db: &mut Database, write: impl FnOnce(&mut Database) -> Result<()>) -> Result<()> {
+    let explicit = db.in_transaction();
+    let result = write(db).and_then(|()| if explicit { Ok(()) } else { db.commit() });
+    result.map_err(|e| {
+        // A failed commit leaves its changes pending for this to undo
+        db.rollback();
+        if explicit {
+            e.context("transaction rolled back")
+        } else {
+            e
+        }
+    })
+}
+
+/// Rowids of the rows in `table_name` matching the optional WHERE clause
+fn matching_row_ids(db: &mut Database, table_name: &str, where_clause: Option<&Expr>) -> Result<Vec<u64>> {
+    let table = TableRef { name: table_name.to_string(), alias: None };
+    let matching = scan_table(db, &table, where_clause)?;
+    Ok(matching.rows.iter().map(|row| row.row_id).collect())
+}
+
+fn execute_insert(db: &mut Database, insert: &InsertStatement) -> Result<()> {
+    for values in &insert.rows {
+        let values = values.iter().map(|value| eval::evaluate_constant(value, db.text_encoding())).collect::<Result<Vec<_>>>()?;
+        db.insert_row(&insert.table_name, insert.columns.as_deref(), values)?;
+    }
+    Ok(())
+}
+
+fn execute_delete(db: &mut Database, delete: &DeleteStatement) -> Result<()> {
+    let row_ids = matching_row_ids(db, &delete.table_name, delete.where_clause.as_ref())?;
+    for row_id in row_ids {
+        db.delete_row(&delete.table_name, row_id as i64)?;
+    }
+    Ok(())
+}
+
+fn execute_update(db: &mut Database, update: &UpdateStatement) -> Result<()> {
+    let table = TableRef { name: update.table_name.clone(), alias: None };
+    let schema = db.get_table_schema(&table.name)?;
+    let encoding = db.text_encoding();
+    let sources = [Source::new(&table, &schema)];
+    for (_, value) in &update.assignments {
+        eval::check_columns(value, &sources)?;
+        if let Some(name) = aggregate::find_aggregate(value) {
+            bail!("misuse of aggregate function {}()", name);
+        }
+    }
+
+    let matching = scan_table(db, &table, update.where_clause.as_ref())?;
+    for row in matching.rows {
+        let row_id = row.row_id as i64;
+        // Every assignment sees the old row, whichever columns the others change
+        let row = [Some(row)];
+        let scope = RowScope { sources: &sources, rows: &row, aggregates: &[], encoding };
+        let assignments = update.assignments.iter()
+            .map(|(column, value)| Ok((column.clone(), eval::evaluate(value, &scope)?)))
+            .collect::<Result<Vec<_>>>()?;
+        db.update_row(&table.name, row_id, &assignments)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::affinity::value_to_text;
+    use crate::database::test_support::{add_index, add_without_rowid_table, image_with_encoding, image_with_tables, SharedMemory};
+    use crate::database::{Page, PageSource};
+    use crate::query::parser;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const PAGE_SIZE: usize = 1024;
+    const ROWS: i64 = 20000;
+
+    /// Counts the reads that reach a shared memory image
+    struct CountingReads {
+        image: SharedMemory,
+        reads: Rc<Cell<usize>>,
+    }
+
+    impl PageSource for CountingReads {
+        fn size(&self) -> Result<u64> {
+            self.image.size()
+        }
+
+        fn read_at(&mut self, offset: u64, len: usize) -> Result<Page> {
+            self.reads.set(self.reads.get() + 1);
+            self.image.read_at(offset, len)
+        }
+
+        fn is_zero_copy(&self) -> bool {
+            self.image.is_zero_copy()
+        }
+    }
+
+    /// Index key of the row with rowid `id`: distinct, in a different order
+    fn key(id: i64) -> i64 {
+        id * 7919 % 20011
+    }
+
+    /// A table whose index on `k` orders its rows differently from their rowids
+    fn indexed_table() -> SharedMemory {
+        let image = SharedMemory::new(image_with_tables(PAGE_SIZE, &[("t", "CREATE TABLE t(id INTEGER PRIMARY KEY, k INTEGER)")]));
+        let mut db = image.open().unwrap();
+        for id in 1..=ROWS {
+            db.insert_row("t", None, vec![RecordValue::Int(id), RecordValue::Int(key(id))]).unwrap();
+        }
+        db.commit().unwrap();
+
+        let mut keys: Vec<_> = (1..=ROWS).map(|id| (key(id), id)).collect();
+        keys.sort_unstable();
+        let entries = keys.into_iter().map(|(k, id)| vec![RecordValue::Int(k), RecordValue::Int(id)]).collect();
+        let mut bytes = image.bytes();
+        add_index(&mut bytes, PAGE_SIZE, ("tk", "t", "CREATE INDEX tk ON t(k)"), entries);
+        SharedMemory::new(bytes)
+    }
+
+    /// The (id, k) rows of a query and the number of reads it took, not
+    /// counting those that load the schema
+    fn run(image: &SharedMemory, sql: &str) -> (Vec<(i64, i64)>, usize) {
+        let reads = Rc::new(Cell::new(0));
+        let mut db = Database::from_source(CountingReads { image: image.clone(), reads: Rc::clone(&reads) }).unwrap();
+        db.get_index_schemas("t").unwrap();
+        reads.set(0);
+
+        let Statement::Select(select) = parser::parse_statement(sql).unwrap() else {
+            panic!("not a SELECT: {}", sql);
+        };
+        let result = execute_select(&mut db, &select).unwrap();
+        let int = |value: &RecordValue| match value {
+            RecordValue::Int(i) => *i,
+            RecordValue::Zero => 0,
+            RecordValue::One => 1,
+            other => panic!("expected an integer, got {:?}", other),
+        };
+        let rows = result.rows.iter().map(|row| (int(&row[0]), int(&row[1]))).collect();
+        (rows, reads.get())
+    }
+
+    /// The rows of a query, each as its values joined with `|`
+    fn texts(db: &mut Database, sql: &str) -> Vec<String> {
+        let Statement::Select(select) = parser::parse_statement(sql).unwrap() else {
+            panic!("not a SELECT: {}", sql);
+        };
+        let result = execute_select(db, &select).unwrap();
+        result.rows.iter().map(|row| row.iter().map(value_to_text).collect::<Vec<_>>().join("|")).collect()
+    }
+
+    #[test]
+    fn index_range_scan_under_limit_reads_a_bounded_number_of_pages() {
+        let image = indexed_table();
+        let (all, all_reads) = run(&image, "SELECT id, k FROM t ORDER BY k");
+        let mut by_key: Vec<_> = (1..=ROWS).map(|id| (id, key(id))).collect();
+        by_key.sort_unstable_by_key(|&(_, k)| k);
+        assert_eq!(all, by_key);
+
+        let cases = [
+            ("SELECT id, k FROM t ORDER BY k LIMIT 5", by_key[..5].to_vec()),
+            ("SELECT id, k FROM t ORDER BY k DESC LIMIT 5", by_key.iter().rev().take(5).copied().collect()),
+            ("SELECT id, k FROM t WHERE k > 2500 ORDER BY k LIMIT 5 OFFSET 20", by_key.iter().filter(|row| row.1 > 2500).skip(20).take(5).copied().collect()),
+            ("SELECT id, k FROM t WHERE k >= 100 LIMIT 3", by_key.iter().filter(|row| row.1 >= 100).take(3).copied().collect()),
+        ];
+        for (sql, expected) in cases {
+            let (rows, reads) = run(&image, sql);
+            assert_eq!(rows, expected, "{}", sql);
+            assert!(reads < 100, "{} read {} pages, a full scan {}", sql, reads, all_reads);
+        }
+    }
+
+    #[test]
+    fn count_star_counts_matching_rows() {
+        let image = indexed_table();
+        let mut db = image.open().unwrap();
+        let cases = [
+            ("SELECT count(*) FROM t", ROWS),
+            ("SELECT count(*) FROM t WHERE k < 1000", (1..=ROWS).filter(|&id| key(id) < 1000).count() as i64),
+            ("SELECT count(*) FROM t WHERE id % 3 = 0", ROWS / 3),
+        ];
+        for (sql, expected) in cases {
+            let Statement::Select(select) = parser::parse_statement(sql).unwrap() else {
+                panic!("not a SELECT: {}", sql);
+            };
+            let result = execute_select(&mut db, &select).unwrap();
+            let [ref row] = result.rows[..] else {
+                panic!("{} returned {:?}", sql, result.rows);
+            };
+            assert!(matches!(row[..], [RecordValue::Int(count)] if count == expected), "{} returned {:?}", sql, row);
+        }
+    }
+
+    #[test]
+    fn without_rowid_table_rows_are_found_through_a_secondary_index() {
+        let key = |i: usize| format!("k{:04}", i);
+        let value = |i: usize| format!("v{}", i % 7);
+        let text = |s: String| RecordValue::Text(s);
+        let mut image = image_with_tables(PAGE_SIZE, &[]);
+        let rows = (0..500).map(|i| vec![text(key(i)), text(value(i))]).collect();
+        add_without_rowid_table(&mut image, PAGE_SIZE, ("kv", "CREATE TABLE kv(k PRIMARY KEY, v) WITHOUT ROWID"), rows);
+        let mut entries: Vec<_> = (0..500).map(|i| (value(i), key(i))).collect();
+        entries.sort_unstable();
+        let entries = entries.into_iter().map(|(v, k)| vec![text(v), text(k)]).collect();
+        add_index(&mut image, PAGE_SIZE, ("kv_v", "kv", "CREATE INDEX kv_v ON kv(v)"), entries);
+        let mut db = SharedMemory::new(image).open().unwrap();
+        let mut query = |sql: &str| texts(&mut db, sql);
+
+        let threes: Vec<_> = (0..500).filter(|i| i % 7 == 3).map(key).collect();
+        assert_eq!(query("SELECT k FROM kv WHERE v = 'v3'"), threes);
+        assert_eq!(query("SELECT count(*) FROM kv WHERE v = 'v3'"), [threes.len().to_string()]);
+
+        let mut joined = query("SELECT a.k, b.k FROM kv a JOIN kv b ON b.v = a.v WHERE a.k < 'k0002'");
+        joined.sort_unstable();
+        let expected: Vec<_> = (0..2)
+            .flat_map(|a| (0..500).filter(move |b| b % 7 == a % 7).map(move |b| format!("{}|{}", key(a), key(b))))
+            .collect();
+        assert_eq!(joined, expected);
+    }
+
+    #[test]
+    fn aggregates_are_folded_into_their_groups() {
+        let mut db = indexed_table().open().unwrap();
+        let in_group = |group: i64| (1..=ROWS).filter(move |id| key(*id) % 3 == group);
+        let expected: Vec<_> = (0..3)
+            .map(|group| format!("{}|{}|{}|{}", group, in_group(group).count(), in_group(group).min().unwrap(), in_group(group).map(key).sum::<i64>()))
+            .collect();
+        assert_eq!(texts(&mut db, "SELECT k % 3, count(*), min(id), sum(k) FROM t GROUP BY k % 3"), expected);
+
+        // The one group of a query without GROUP BY is there even with no rows
+        assert_eq!(texts(&mut db, "SELECT count(*), sum(k), max(k) FROM t WHERE id < 0"), ["0|NULL|NULL"]);
+        // Bare columns come from the row holding the maximum
+        let max_id = (1..=ROWS).max_by_key(|id| key(*id)).unwrap();
+        assert_eq!(texts(&mut db, "SELECT id, max(k) FROM t"), [format!("{}|{}", max_id, key(max_id))]);
+    }
+
+    #[test]
+    fn utf16le_index_is_searched_in_its_byte_order() {
+        let image = SharedMemory::new(image_with_encoding(PAGE_SIZE, TextEncoding::Utf16le, &[("t", "CREATE TABLE t(id INTEGER PRIMARY KEY, s TEXT)")]));
+        let mut db = image.open().unwrap();
+        let values = ["a", "b", "z", "é", "Ā", "ā", "ĉ", "ab", "b"];
+        for (id, value) in (1..).zip(values) {
+            db.insert_row("t", None, vec![RecordValue::Int(id), RecordValue::Text(value.to_string())]).unwrap();
+        }
+        db.commit().unwrap();
+
+        // BINARY compares the little-endian bytes, so 'Ā' (00 01) sorts before 'a' (61 00)
+        let utf16le = |s: &str| s.encode_utf16().map(u16::to_le_bytes).collect::<Vec<_>>();
+        let mut entries: Vec<_> = (1..).zip(values).collect();
+        entries.sort_by_key(|&(id, value)| (utf16le(value), id));
+        let entries = entries.into_iter().map(|(id, value)| vec![RecordValue::Text(value.to_string()), RecordValue::Int(id)]).collect();
+        let mut bytes = image.bytes();
+        add_index(&mut bytes, PAGE_SIZE, ("t_s", "t", "CREATE INDEX t_s ON t(s)"), entries);
+        let mut db = SharedMemory::new(bytes).open().unwrap();
+
+        assert_eq!(texts(&mut db, "SELECT s FROM t WHERE s = 'a'"), ["a"]);
+        assert_eq!(texts(&mut db, "SELECT id FROM t WHERE s = 'b'"), ["2", "9"]);
+        assert_eq!(texts(&mut db, "SELECT s FROM t WHERE s > 'b'"), ["z", "é"]);
+        assert_eq!(texts(&mut db, "SELECT s FROM t WHERE s < 'b'"), ["Ā", "ā", "ĉ", "a", "ab"]);
+        assert_eq!(texts(&mut db, "SELECT min(s), max(s) FROM t"), ["Ā|é"]);
+    }
+}
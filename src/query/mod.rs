@@ -1,4 +1,10 @@
+pub mod aggregate;
 pub mod commands;
+pub mod eval;
+pub mod executor;
+pub mod parser;
 
 // Re-export main types for convenience
-pub use commands::{execute_command, WhereCondition, ComparisonOperator}; 
\ No newline at end of file
+pub use commands::{execute_command, execute_sql, execute_statement};
+pub use executor::{execute, execute_select, ResultSet};
+pub use parser::{parse, parse_statement, Statement, Select, Expr, InsertStatement, DeleteStatement, UpdateStatement};
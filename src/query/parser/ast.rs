@@ -0,0 +1,183 @@
+use crate::RecordValue;
+
+/// One parsed SQL statement
+#[derive(Debug, Clone)]
+pub enum Statement {
+    Select(Box<Select>),
+    Insert(InsertStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
+    Begin,
+    Commit,
+    Rollback,
+}
+
+/// `SELECT columns [FROM table [joins]] [WHERE expr] [GROUP BY expr, ...
+/// [HAVING expr]] [ORDER BY term, ...] [LIMIT expr [OFFSET expr]]`
+#[derive(Debug, Clone)]
+pub struct Select {
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<TableRef>,
+    /// Tables joined to the one in `from`, in the order written
+    pub joins: Vec<Join>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Expr>,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ResultColumn {
+    /// `*`
+    Star,
+    /// `table.*`
+    TableStar(String),
+    /// An expression, with the SQL text it was written as for the column header
+    Expr { expr: Expr, alias: Option<String>, text: String },
+}
+
+/// `expr [ASC|DESC] [NULLS FIRST|NULLS LAST]` in ORDER BY
+#[derive(Debug, Clone)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// Set by an explicit NULLS FIRST or NULLS LAST
+    pub nulls_first: Option<bool>,
+}
+
+impl OrderingTerm {
+    /// Whether NULLs sort before other values. Without NULLS FIRST/LAST they
+    /// are the smallest values: first ascending and last descending.
+    pub fn nulls_first(&self) -> bool {
+        self.nulls_first.unwrap_or(!self.descending)
+    }
+}
+
+/// A table named in FROM, optionally with an alias
+#[derive(Debug, Clone)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    /// Whether `qualifier` in `qualifier.column` refers to this table
+    pub fn is_named(&self, qualifier: &str) -> bool {
+        match &self.alias {
+            Some(alias) => alias.eq_ignore_ascii_case(qualifier),
+            None => self.name.eq_ignore_ascii_case(qualifier),
+        }
+    }
+}
+
+/// `[LEFT [OUTER]] JOIN table [ON expr]` after the first table in FROM. A
+/// comma, `CROSS JOIN` and `INNER JOIN` are inner joins too.
+#[derive(Debug, Clone)]
+pub struct Join {
+    pub kind: JoinKind,
+    pub table: TableRef,
+    pub on: Option<Expr>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinKind {
+    Inner,
+    /// Keeps each row of the tables to its left that no row matches, with
+    /// NULLs for this table's columns
+    Left,
+}
+
+/// Parsed form of `INSERT INTO table [(columns)] VALUES (...), (...)`
+#[derive(Debug, Clone)]
+pub struct InsertStatement {
+    pub table_name: String,
+    pub columns: Option<Vec<String>>,
+    pub rows: Vec<Vec<Expr>>,
+}
+
+/// Parsed form of `DELETE FROM table [WHERE expr]`
+#[derive(Debug, Clone)]
+pub struct DeleteStatement {
+    pub table_name: String,
+    pub where_clause: Option<Expr>,
+}
+
+/// Parsed form of `UPDATE table SET column = expr, ... [WHERE expr]`
+#[derive(Debug, Clone)]
+pub struct UpdateStatement {
+    pub table_name: String,
+    /// Each expression is evaluated against the row as it was before the update
+    pub assignments: Vec<(String, Expr)>,
+    pub where_clause: Option<Expr>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(RecordValue),
+    Column { table: Option<String>, name: String },
+    Parameter(String),
+    Unary { op: UnaryOp, expr: Box<Expr> },
+    Binary { left: Box<Expr>, op: BinaryOp, right: Box<Expr> },
+    /// `expr [NOT] BETWEEN low AND high`
+    Between { expr: Box<Expr>, low: Box<Expr>, high: Box<Expr>, negated: bool },
+    /// A function call; `COUNT(*)` has `star` set and no arguments
+    Function { name: String, args: Vec<Expr>, distinct: bool, star: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Concat,
+}
+
+impl Expr {
+    pub fn binary(left: Expr, op: BinaryOp, right: Expr) -> Self {
+        Expr::Binary { left: Box::new(left), op, right: Box::new(right) }
+    }
+
+    pub fn unary(op: UnaryOp, expr: Expr) -> Self {
+        Expr::Unary { op, expr: Box::new(expr) }
+    }
+}
+
+impl Select {
+    /// Whether this is `SELECT COUNT(*) FROM table ...` with nothing else
+    /// selected, which gives a single count
+    pub fn is_count_star(&self) -> bool {
+        self.joins.is_empty()
+            && self.group_by.is_empty()
+            && self.having.is_none()
+            && matches!(
+                &self.columns[..],
+                [ResultColumn::Expr { expr: Expr::Function { name, star: true, .. }, .. }] if name == "count"
+            )
+    }
+}
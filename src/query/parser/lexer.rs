@@ -0,0 +1,239 @@
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Identifier(String),       // bare word; keywords are recognised by the parser
+    QuotedIdentifier(String), // "name", [name] or `name`
+    String(String),
+    Blob(Vec<u8>),
+    Number(String),           // numeric literal as written, converted by the parser
+    Parameter(String),        // ?, ?NNN, :name, @name or $name as written
+    LeftParen,
+    RightParen,
+    Comma,
+    Semicolon,
+    Dot,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Concat,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// A token together with the byte range of the SQL text it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    /// Whether this is the bare word `keyword`, ignoring case
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Identifier(word) if word.eq_ignore_ascii_case(keyword))
+    }
+}
+
+/// Split SQL text into tokens, skipping whitespace and comments
+pub fn tokenize(sql: &str) -> Result<Vec<SpannedToken>> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+
+        let token = match c {
+            b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' => {
+                pos += 1;
+                continue;
+            }
+            b'-' if bytes.get(pos + 1) == Some(&b'-') => {
+                pos = sql[pos..].find('\n').map_or(bytes.len(), |n| pos + n + 1);
+                continue;
+            }
+            b'/' if bytes.get(pos + 1) == Some(&b'*') => {
+                // An unterminated block comment runs to the end of the input
+                pos = sql[pos + 2..].find("*/").map_or(bytes.len(), |n| pos + 2 + n + 2);
+                continue;
+            }
+            b'\'' => {
+                let (text, end) = read_quoted(sql, pos, '\'')?;
+                pos = end;
+                Token::String(text)
+            }
+            b'"' | b'`' => {
+                let (text, end) = read_quoted(sql, pos, c as char)?;
+                pos = end;
+                Token::QuotedIdentifier(text)
+            }
+            b'[' => {
+                let end = sql[pos..].find(']')
+                    .ok_or_else(|| anyhow::anyhow!("unrecognized token: \"{}\"", &sql[pos..]))?;
+                let name = sql[pos + 1..pos + end].to_string();
+                pos += end + 1;
+                Token::QuotedIdentifier(name)
+            }
+            b'x' | b'X' if bytes.get(pos + 1) == Some(&b'\'') => {
+                let (hex, end) = read_quoted(sql, pos + 1, '\'')?;
+                pos = end;
+                Token::Blob(decode_hex(&hex).ok_or_else(|| anyhow::anyhow!("unrecognized token: \"{}\"", &sql[start..end]))?)
+            }
+            b'0'..=b'9' => {
+                pos = number_end(bytes, pos);
+                Token::Number(sql[start..pos].to_string())
+            }
+            b'.' if bytes.get(pos + 1).is_some_and(u8::is_ascii_digit) => {
+                pos = number_end(bytes, pos);
+                Token::Number(sql[start..pos].to_string())
+            }
+            b'?' => {
+                pos += 1;
+                while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                Token::Parameter(sql[start..pos].to_string())
+            }
+            b':' | b'@' | b'$' => {
+                pos = identifier_end(bytes, pos + 1);
+                if pos == start + 1 {
+                    bail!("unrecognized token: \"{}\"", c as char);
+                }
+                Token::Parameter(sql[start..pos].to_string())
+            }
+            c if is_identifier_start(c) => {
+                pos = identifier_end(bytes, pos);
+                Token::Identifier(sql[start..pos].to_string())
+            }
+            _ => {
+                let (token, len) = read_operator(&bytes[pos..])
+                    .ok_or_else(|| anyhow::anyhow!("unrecognized token: \"{}\"", sql[pos..].chars().next().unwrap_or(' ')))?;
+                pos += len;
+                token
+            }
+        };
+
+        tokens.push(SpannedToken { token, start, end: pos });
+    }
+
+    Ok(tokens)
+}
+
+/// Read a literal enclosed in `quote`, where a doubled quote stands for
+/// itself. Returns the unescaped text and the position after the closing quote.
+fn read_quoted(sql: &str, start: usize, quote: char) -> Result<(String, usize)> {
+    let mut text = String::new();
+    let mut chars = sql[start + 1..].char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if c != quote {
+            text.push(c);
+        } else if chars.peek().is_some_and(|&(_, next)| next == quote) {
+            text.push(quote);
+            chars.next();
+        } else {
+            return Ok((text, start + 1 + i + 1));
+        }
+    }
+
+    bail!("unrecognized token: \"{}\"", &sql[start..])
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn number_end(bytes: &[u8], mut pos: usize) -> usize {
+    if bytes[pos] == b'0' && matches!(bytes.get(pos + 1), Some(b'x' | b'X')) && bytes.get(pos + 2).is_some_and(u8::is_ascii_hexdigit) {
+        pos += 2;
+        while pos < bytes.len() && bytes[pos].is_ascii_hexdigit() {
+            pos += 1;
+        }
+        return pos;
+    }
+
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if bytes.get(pos) == Some(&b'.') {
+        pos += 1;
+        while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+            pos += 1;
+        }
+    }
+    if matches!(bytes.get(pos), Some(b'e' | b'E')) {
+        let mut exp = pos + 1;
+        if matches!(bytes.get(exp), Some(b'+' | b'-')) {
+            exp += 1;
+        }
+        if bytes.get(exp).is_some_and(u8::is_ascii_digit) {
+            pos = exp;
+            while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    pos
+}
+
+fn is_identifier_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c >= 0x80
+}
+
+fn identifier_end(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && (is_identifier_start(bytes[pos]) || bytes[pos].is_ascii_digit() || bytes[pos] == b'$') {
+        pos += 1;
+    }
+    pos
+}
+
+fn read_operator(bytes: &[u8]) -> Option<(Token, usize)> {
+    let two = |second: u8| bytes.get(1) == Some(&second);
+    Some(match bytes[0] {
+        b'(' => (Token::LeftParen, 1),
+        b')' => (Token::RightParen, 1),
+        b',' => (Token::Comma, 1),
+        b';' => (Token::Semicolon, 1),
+        b'.' => (Token::Dot, 1),
+        b'*' => (Token::Star, 1),
+        b'+' => (Token::Plus, 1),
+        b'-' => (Token::Minus, 1),
+        b'/' => (Token::Slash, 1),
+        b'%' => (Token::Percent, 1),
+        b'~' => (Token::BitNot, 1),
+        b'&' => (Token::BitAnd, 1),
+        b'|' if two(b'|') => (Token::Concat, 2),
+        b'|' => (Token::BitOr, 1),
+        b'=' if two(b'=') => (Token::Eq, 2),
+        b'=' => (Token::Eq, 1),
+        b'!' if two(b'=') => (Token::NotEq, 2),
+        b'<' if two(b'>') => (Token::NotEq, 2),
+        b'<' if two(b'=') => (Token::LtEq, 2),
+        b'<' if two(b'<') => (Token::ShiftLeft, 2),
+        b'<' => (Token::Lt, 1),
+        b'>' if two(b'=') => (Token::GtEq, 2),
+        b'>' if two(b'>') => (Token::ShiftRight, 2),
+        b'>' => (Token::Gt, 1),
+        _ => return None,
+    })
+}
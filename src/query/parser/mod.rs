@@ -0,0 +1,676 @@
+pub mod ast;
+pub mod lexer;
+
+use anyhow::{bail, Result};
+use crate::RecordValue;
+
+pub use ast::*;
+use lexer::{tokenize, SpannedToken, Token};
+
+/// Keywords that can't be used as bare column names or aliases, because
+/// they would make the grammar ambiguous
+const RESERVED: &[&str] = &[
+    "all", "and", "as", "asc", "between", "by", "case", "collate", "cross", "desc", "distinct",
+    "else", "end", "escape", "except", "exists", "from", "glob", "group", "having", "in",
+    "inner", "intersect", "is", "isnull", "join", "left", "like", "limit", "natural", "not",
+    "notnull", "null", "offset", "on", "or", "order", "outer", "select", "set", "then",
+    "union", "using", "values", "when", "where",
+];
+
+/// Parse `;`-separated SQL statements
+pub fn parse(sql: &str) -> Result<Vec<Statement>> {
+    let mut parser = Parser::new(sql)?;
+    let mut statements = Vec::new();
+
+    loop {
+        while parser.eat(&Token::Semicolon) {}
+        if parser.at_end() {
+            break;
+        }
+        statements.push(parser.parse_statement()?);
+        if !parser.at_end() && !parser.eat(&Token::Semicolon) {
+            return Err(parser.error());
+        }
+    }
+
+    Ok(statements)
+}
+
+/// Parse SQL text holding exactly one statement
+pub fn parse_statement(sql: &str) -> Result<Statement> {
+    let mut statements = parse(sql)?;
+    match statements.len() {
+        1 => Ok(statements.remove(0)),
+        0 => bail!("No SQL statement given"),
+        n => bail!("Expected a single statement, got {}", n),
+    }
+}
+
+/// Parse a standalone expression, such as the text of a WHERE clause
+pub fn parse_expr(sql: &str) -> Result<Expr> {
+    let mut parser = Parser::new(sql)?;
+    let expr = parser.parse_expr()?;
+    if !parser.at_end() {
+        return Err(parser.error());
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    sql: &'a str,
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(sql: &'a str) -> Result<Self> {
+        Ok(Self { sql, tokens: tokenize(sql)?, pos: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.token)
+    }
+
+    fn peek_at(&self, ahead: usize) -> Option<&Token> {
+        self.tokens.get(self.pos + ahead).map(|t| &t.token)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).map(|t| t.token.clone());
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|t| t.is_keyword(keyword))
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if self.at_keyword(keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.eat_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Syntax error at the current token, worded like SQLite's
+    fn error(&self) -> anyhow::Error {
+        match self.tokens.get(self.pos) {
+            Some(t) => anyhow::anyhow!("near \"{}\": syntax error", &self.sql[t.start..t.end]),
+            None => anyhow::anyhow!("incomplete input"),
+        }
+    }
+
+    /// SQL text from the token at `start` up to the previous token
+    fn text_since(&self, start: usize) -> String {
+        match (self.tokens.get(start), self.tokens.get(self.pos.saturating_sub(1))) {
+            (Some(first), Some(last)) if self.pos > start => self.sql[first.start..last.end].to_string(),
+            _ => String::new(),
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement> {
+        let statement = match self.peek() {
+            Some(t) if t.is_keyword("select") => Statement::Select(Box::new(self.parse_select()?)),
+            Some(t) if t.is_keyword("insert") => Statement::Insert(self.parse_insert()?),
+            Some(t) if t.is_keyword("update") => Statement::Update(self.parse_update()?),
+            Some(t) if t.is_keyword("delete") => Statement::Delete(self.parse_delete()?),
+            Some(t) if t.is_keyword("begin") => {
+                self.advance();
+                let _ = self.eat_keyword("deferred") || self.eat_keyword("immediate") || self.eat_keyword("exclusive");
+                self.eat_keyword("transaction");
+                Statement::Begin
+            }
+            Some(t) if t.is_keyword("commit") || t.is_keyword("end") => {
+                self.advance();
+                self.eat_keyword("transaction");
+                Statement::Commit
+            }
+            Some(t) if t.is_keyword("rollback") => {
+                self.advance();
+                self.eat_keyword("transaction");
+                Statement::Rollback
+            }
+            _ => return Err(self.error()),
+        };
+        Ok(statement)
+    }
+
+    fn parse_select(&mut self) -> Result<Select> {
+        self.expect_keyword("select")?;
+
+        let mut columns = vec![self.parse_result_column()?];
+        while self.eat(&Token::Comma) {
+            columns.push(self.parse_result_column()?);
+        }
+
+        let mut from = None;
+        let mut joins = Vec::new();
+        if self.eat_keyword("from") {
+            from = Some(self.parse_table_ref()?);
+            while let Some(kind) = self.parse_join_operator()? {
+                let table = self.parse_table_ref()?;
+                let on = if self.eat_keyword("on") { Some(self.parse_expr()?) } else { None };
+                joins.push(Join { kind, table, on });
+            }
+        }
+
+        let where_clause = if self.eat_keyword("where") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut group_by = Vec::new();
+        let mut having = None;
+        if self.eat_keyword("group") {
+            self.expect_keyword("by")?;
+            group_by.push(self.parse_expr()?);
+            while self.eat(&Token::Comma) {
+                group_by.push(self.parse_expr()?);
+            }
+        }
+        if self.eat_keyword("having") {
+            having = Some(self.parse_expr()?);
+        }
+
+        let mut order_by = Vec::new();
+        if self.eat_keyword("order") {
+            self.expect_keyword("by")?;
+            order_by.push(self.parse_ordering_term()?);
+            while self.eat(&Token::Comma) {
+                order_by.push(self.parse_ordering_term()?);
+            }
+        }
+
+        let (mut limit, mut offset) = (None, None);
+        if self.eat_keyword("limit") {
+            let first = self.parse_expr()?;
+            if self.eat_keyword("offset") {
+                offset = Some(self.parse_expr()?);
+                limit = Some(first);
+            } else if self.eat(&Token::Comma) {
+                // `LIMIT offset, count`
+                limit = Some(self.parse_expr()?);
+                offset = Some(first);
+            } else {
+                limit = Some(first);
+            }
+        }
+
+        Ok(Select { columns, from, joins, where_clause, group_by, having, order_by, limit, offset })
+    }
+
+    fn parse_ordering_term(&mut self) -> Result<OrderingTerm> {
+        let expr = self.parse_expr()?;
+        let descending = if self.eat_keyword("desc") {
+            true
+        } else {
+            self.eat_keyword("asc");
+            false
+        };
+        let nulls_first = if self.eat_keyword("nulls") {
+            if self.eat_keyword("first") {
+                Some(true)
+            } else {
+                self.expect_keyword("last")?;
+                Some(false)
+            }
+        } else {
+            None
+        };
+        Ok(OrderingTerm { expr, descending, nulls_first })
+    }
+
+    fn parse_result_column(&mut self) -> Result<ResultColumn> {
+        if self.eat(&Token::Star) {
+            return Ok(ResultColumn::Star);
+        }
+        if self.peek_at(1) == Some(&Token::Dot) && self.peek_at(2) == Some(&Token::Star) {
+            let table = self.parse_name()?;
+            self.pos += 2;
+            return Ok(ResultColumn::TableStar(table));
+        }
+
+        let start = self.pos;
+        let expr = self.parse_expr()?;
+        let text = self.text_since(start);
+        let alias = self.parse_alias()?;
+        Ok(ResultColumn::Expr { expr, alias, text })
+    }
+
+    /// `[AS] alias` after a result column or table name
+    fn parse_alias(&mut self) -> Result<Option<String>> {
+        if self.eat_keyword("as") {
+            return Ok(Some(self.parse_name()?));
+        }
+        match self.peek() {
+            Some(Token::Identifier(word)) if !is_reserved(word) => Ok(Some(self.parse_name()?)),
+            Some(Token::QuotedIdentifier(_)) => Ok(Some(self.parse_name()?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse_table_ref(&mut self) -> Result<TableRef> {
+        let name = self.parse_name()?;
+        // `RIGHT` and `FULL` are not reserved, but here they start a join
+        let alias = if self.at_outer_join() { None } else { self.parse_alias()? };
+        Ok(TableRef { name, alias })
+    }
+
+    /// The operator before the next table in FROM: `,`, `[INNER] JOIN`,
+    /// `CROSS JOIN` or `LEFT [OUTER] JOIN`
+    fn parse_join_operator(&mut self) -> Result<Option<JoinKind>> {
+        if self.at_outer_join() {
+            bail!("RIGHT and FULL OUTER JOINs are not supported");
+        }
+        if self.eat(&Token::Comma) {
+            return Ok(Some(JoinKind::Inner));
+        }
+        let kind = if self.eat_keyword("left") {
+            self.eat_keyword("outer");
+            JoinKind::Left
+        } else if self.eat_keyword("inner") || self.eat_keyword("cross") || self.at_keyword("join") {
+            JoinKind::Inner
+        } else {
+            return Ok(None);
+        };
+        self.expect_keyword("join")?;
+        Ok(Some(kind))
+    }
+
+    /// Whether the next words are `RIGHT [OUTER] JOIN` or `FULL [OUTER] JOIN`
+    fn at_outer_join(&self) -> bool {
+        (self.at_keyword("right") || self.at_keyword("full"))
+            && self.peek_at(1).is_some_and(|next| next.is_keyword("join") || next.is_keyword("outer"))
+    }
+
+    /// A table, column or alias name, bare or quoted
+    fn parse_name(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Identifier(word)) if !is_reserved(word) => {
+                let word = word.clone();
+                self.pos += 1;
+                Ok(word)
+            }
+            Some(Token::QuotedIdentifier(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn parse_insert(&mut self) -> Result<InsertStatement> {
+        self.expect_keyword("insert")?;
+        self.expect_keyword("into")?;
+        let table_name = self.parse_name()?;
+
+        let columns = if self.eat(&Token::LeftParen) {
+            let mut columns = vec![self.parse_name()?];
+            while self.eat(&Token::Comma) {
+                columns.push(self.parse_name()?);
+            }
+            self.expect(&Token::RightParen)?;
+            Some(columns)
+        } else {
+            None
+        };
+
+        self.expect_keyword("values")?;
+        let mut rows = Vec::new();
+        loop {
+            self.expect(&Token::LeftParen)?;
+            let mut values = vec![self.parse_expr()?];
+            while self.eat(&Token::Comma) {
+                values.push(self.parse_expr()?);
+            }
+            self.expect(&Token::RightParen)?;
+            rows.push(values);
+
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+
+        Ok(InsertStatement { table_name, columns, rows })
+    }
+
+    fn parse_update(&mut self) -> Result<UpdateStatement> {
+        self.expect_keyword("update")?;
+        let table_name = self.parse_name()?;
+        self.expect_keyword("set")?;
+
+        let mut assignments = Vec::new();
+        loop {
+            let column = self.parse_name()?;
+            self.expect(&Token::Eq)?;
+            assignments.push((column, self.parse_expr()?));
+            if !self.eat(&Token::Comma) {
+                break;
+            }
+        }
+
+        let where_clause = if self.eat_keyword("where") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        Ok(UpdateStatement { table_name, assignments, where_clause })
+    }
+
+    fn parse_delete(&mut self) -> Result<DeleteStatement> {
+        self.expect_keyword("delete")?;
+        self.expect_keyword("from")?;
+        let table_name = self.parse_name()?;
+
+        let where_clause = if self.eat_keyword("where") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        Ok(DeleteStatement { table_name, where_clause })
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_or()
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            left = Expr::binary(left, BinaryOp::Or, self.parse_and()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            left = Expr::binary(left, BinaryOp::And, self.parse_not()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expr> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::unary(UnaryOp::Not, self.parse_not()?));
+        }
+        self.parse_equality()
+    }
+
+    fn parse_equality(&mut self) -> Result<Expr> {
+        let mut left = self.parse_comparison()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Eq) => BinaryOp::Eq,
+                Some(Token::NotEq) => BinaryOp::NotEq,
+                Some(t) if t.is_keyword("is") => {
+                    self.advance();
+                    let op = if self.eat_keyword("not") { BinaryOp::IsNot } else { BinaryOp::Is };
+                    left = Expr::binary(left, op, self.parse_comparison()?);
+                    continue;
+                }
+                Some(t) if t.is_keyword("isnull") => {
+                    self.advance();
+                    left = Expr::binary(left, BinaryOp::Is, Expr::Literal(RecordValue::Null));
+                    continue;
+                }
+                Some(t) if t.is_keyword("notnull") => {
+                    self.advance();
+                    left = Expr::binary(left, BinaryOp::IsNot, Expr::Literal(RecordValue::Null));
+                    continue;
+                }
+                Some(t) if t.is_keyword("not") && self.peek_at(1).is_some_and(|t| t.is_keyword("null")) => {
+                    self.pos += 2;
+                    left = Expr::binary(left, BinaryOp::IsNot, Expr::Literal(RecordValue::Null));
+                    continue;
+                }
+                Some(t) if t.is_keyword("between") => {
+                    self.advance();
+                    left = self.parse_between(left, false)?;
+                    continue;
+                }
+                Some(t) if t.is_keyword("not") && self.peek_at(1).is_some_and(|t| t.is_keyword("between")) => {
+                    self.pos += 2;
+                    left = self.parse_between(left, true)?;
+                    continue;
+                }
+                _ => break,
+            };
+            self.advance();
+            left = Expr::binary(left, op, self.parse_comparison()?);
+        }
+        Ok(left)
+    }
+
+    /// The `low AND high` of a BETWEEN; its AND binds tighter than logical AND
+    fn parse_between(&mut self, expr: Expr, negated: bool) -> Result<Expr> {
+        let low = self.parse_comparison()?;
+        self.expect_keyword("and")?;
+        let high = self.parse_comparison()?;
+        Ok(Expr::Between { expr: Box::new(expr), low: Box::new(low), high: Box::new(high), negated })
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let mut left = self.parse_bitwise()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Lt) => BinaryOp::Lt,
+                Some(Token::LtEq) => BinaryOp::LtEq,
+                Some(Token::Gt) => BinaryOp::Gt,
+                Some(Token::GtEq) => BinaryOp::GtEq,
+                _ => break,
+            };
+            self.advance();
+            left = Expr::binary(left, op, self.parse_bitwise()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_bitwise(&mut self) -> Result<Expr> {
+        let mut left = self.parse_additive()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::BitAnd) => BinaryOp::BitAnd,
+                Some(Token::BitOr) => BinaryOp::BitOr,
+                Some(Token::ShiftLeft) => BinaryOp::ShiftLeft,
+                Some(Token::ShiftRight) => BinaryOp::ShiftRight,
+                _ => break,
+            };
+            self.advance();
+            left = Expr::binary(left, op, self.parse_additive()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_additive(&mut self) -> Result<Expr> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Plus) => BinaryOp::Add,
+                Some(Token::Minus) => BinaryOp::Subtract,
+                _ => break,
+            };
+            self.advance();
+            left = Expr::binary(left, op, self.parse_multiplicative()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expr> {
+        let mut left = self.parse_concat()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Star) => BinaryOp::Multiply,
+                Some(Token::Slash) => BinaryOp::Divide,
+                Some(Token::Percent) => BinaryOp::Remainder,
+                _ => break,
+            };
+            self.advance();
+            left = Expr::binary(left, op, self.parse_concat()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_concat(&mut self) -> Result<Expr> {
+        let mut left = self.parse_unary()?;
+        while self.eat(&Token::Concat) {
+            left = Expr::binary(left, BinaryOp::Concat, self.parse_unary()?);
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Minus) => {
+                self.advance();
+                // Negative literals are folded so that -9223372036854775808 stays an integer
+                if let Some(Token::Number(text)) = self.peek() {
+                    if !text.starts_with("0x") && !text.starts_with("0X") {
+                        let value = number_literal(&format!("-{}", text))?;
+                        self.advance();
+                        return Ok(Expr::Literal(value));
+                    }
+                }
+                Ok(Expr::unary(UnaryOp::Negate, self.parse_unary()?))
+            }
+            Some(Token::Plus) => {
+                self.advance();
+                Ok(Expr::unary(UnaryOp::Plus, self.parse_unary()?))
+            }
+            Some(Token::BitNot) => {
+                self.advance();
+                Ok(Expr::unary(UnaryOp::BitNot, self.parse_unary()?))
+            }
+            _ => self.parse_primary(),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(self.error());
+        };
+
+        match token {
+            Token::Number(text) => {
+                self.advance();
+                Ok(Expr::Literal(number_literal(&text)?))
+            }
+            Token::String(text) => {
+                self.advance();
+                Ok(Expr::Literal(RecordValue::Text(text)))
+            }
+            Token::Blob(bytes) => {
+                self.advance();
+                Ok(Expr::Literal(RecordValue::Blob(bytes)))
+            }
+            Token::Parameter(name) => {
+                self.advance();
+                Ok(Expr::Parameter(name))
+            }
+            Token::LeftParen => {
+                self.advance();
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::Identifier(ref word) if word.eq_ignore_ascii_case("null") => {
+                self.advance();
+                Ok(Expr::Literal(RecordValue::Null))
+            }
+            Token::Identifier(_) if self.peek_at(1) == Some(&Token::LeftParen) => self.parse_function(),
+            Token::Identifier(_) | Token::QuotedIdentifier(_) => {
+                let first = self.parse_name()?;
+                if self.eat(&Token::Dot) {
+                    let name = self.parse_name()?;
+                    Ok(Expr::Column { table: Some(first), name })
+                } else {
+                    Ok(Expr::Column { table: None, name: first })
+                }
+            }
+            _ => Err(self.error()),
+        }
+    }
+
+    fn parse_function(&mut self) -> Result<Expr> {
+        let name = match self.advance() {
+            Some(Token::Identifier(name)) => name.to_lowercase(),
+            _ => return Err(self.error()),
+        };
+        self.expect(&Token::LeftParen)?;
+
+        if self.eat(&Token::Star) {
+            self.expect(&Token::RightParen)?;
+            return Ok(Expr::Function { name, args: Vec::new(), distinct: false, star: true });
+        }
+
+        let distinct = self.eat_keyword("distinct");
+        let mut args = Vec::new();
+        if !self.eat(&Token::RightParen) {
+            args.push(self.parse_expr()?);
+            while self.eat(&Token::Comma) {
+                args.push(self.parse_expr()?);
+            }
+            self.expect(&Token::RightParen)?;
+        }
+
+        Ok(Expr::Function { name, args, distinct, star: false })
+    }
+}
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED.iter().any(|k| k.eq_ignore_ascii_case(word))
+}
+
+/// Convert a numeric literal. Integers too large for 64 bits become reals,
+/// as in SQLite; hex literals are 64-bit two's complement.
+fn number_literal(text: &str) -> Result<RecordValue> {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return match u64::from_str_radix(hex, 16) {
+            Ok(value) => Ok(RecordValue::Int(value as i64)),
+            Err(_) => bail!("hex literal too big: {}", text),
+        };
+    }
+
+    if let Ok(value) = text.parse::<i64>() {
+        return Ok(RecordValue::Int(value));
+    }
+    match text.parse::<f64>() {
+        Ok(value) => Ok(RecordValue::Float(value)),
+        Err(_) => bail!("unrecognized token: \"{}\"", text),
+    }
+}
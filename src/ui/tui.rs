@@ -20,7 +20,7 @@ use std::{
     time::{Duration, Instant},
 };
 
-use crate::{Database, TableRows};
+use crate::{ColumnInfo, Database, TableRows};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AppMode {
@@ -76,7 +76,8 @@ pub struct App {
 
 impl App {
     pub fn new(database_path: String) -> Result<Self> {
-        let mut database = Database::new(&database_path)?;
+        // Fall back to read-only access when the file is not writable
+        let mut database = Database::open_rw(&database_path).or_else(|_| Database::new(&database_path))?;
         let tables = database.get_table_names()?;
         
         let mut app = App {
@@ -241,19 +242,7 @@ impl App {
         // Handle different types of queries and capture their actual results
         let query_trimmed = query.trim();
         
-        if query_trimmed.to_lowercase().starts_with("select count(*) from ") {
-            // Handle COUNT queries specifically
-            let parts: Vec<&str> = query_trimmed.split_whitespace().collect();
-            if parts.len() >= 4 && parts[2].to_lowercase() == "from" {
-                let table_name = parts[3];
-                match self.database.count_table_rows(table_name) {
-                    Ok(count) => Ok(QueryResult::Text(count.to_string())),
-                    Err(e) => Err(e),
-                }
-            } else {
-                Err(anyhow::anyhow!("Invalid COUNT query format"))
-            }
-        } else if query_trimmed == ".tables" {
+        if query_trimmed == ".tables" {
             // Handle .tables command
             match self.database.get_table_names() {
                 Ok(tables) => Ok(QueryResult::Text(tables.join("\n"))),
@@ -263,7 +252,13 @@ impl App {
             // Handle .dbinfo command
             let page_size = self.database.get_page_size();
             match self.database.get_num_tables() {
-                Ok(num_tables) => Ok(QueryResult::Text(format!("database page size: {}\nnumber of tables: {}", page_size, num_tables))),
+                Ok(num_tables) => {
+                    let stats = self.database.cache_stats();
+                    Ok(QueryResult::Text(format!(
+                        "database page size: {}\nnumber of tables: {}\npage cache: {} hits, {} misses ({}/{} pages)",
+                        page_size, num_tables, stats.hits, stats.misses, stats.cached_pages, stats.capacity
+                    )))
+                }
                 Err(e) => Err(e),
             }
         } else if query_trimmed.starts_with(".schema") {
@@ -281,260 +276,28 @@ impl App {
                 },
                 Err(e) => Err(e),
             }
-        } else if query_trimmed.to_lowercase().starts_with("select") {
-            // Handle other SELECT queries properly
-            match self.execute_select_query_in_tui(query_trimmed) {
-                Ok(result) => Ok(result),
-                Err(e) => Err(e),
-            }
-        } else {
-            // For other commands, use the original execute_command
-            match crate::query::execute_command(&self.database_path, query) {
-                Ok(_) => Ok(QueryResult::Text("Command executed successfully".to_string())),
-                Err(e) => Err(e),
-            }
-        }
-    }
-    
-    /// Execute SELECT queries in TUI and format results for display
-    fn execute_select_query_in_tui(&mut self, query: &str) -> Result<QueryResult> {
-        let query_lower = query.to_lowercase();
-        
-        // Check if this query has a WHERE clause - if so, use the CLI command system which handles it properly
-        if query_lower.contains(" where ") {
-            // Use the CLI command system for WHERE clause queries since it handles filtering correctly
-            return self.execute_query_via_cli(query);
-        }
-        
-        // Parse SELECT query to determine what to do (simple queries without WHERE)
-        if query_lower.starts_with("select * from ") {
-            // Handle SELECT * FROM table queries (without WHERE)
-            let parts: Vec<&str> = query.split_whitespace().collect();
-            if parts.len() >= 4 {
-                let table_name = parts[3];
-                match self.database.get_table_rows(table_name) {
-                    Ok(table_data) => {
-                        Ok(QueryResult::Table {
-                            headers: table_data.columns.iter().map(|col| col.name.clone()).collect(),
-                            rows: table_data.rows.iter().map(|row| {
-                                row.values.iter().map(|val| val.to_display_string()).collect()
-                            }).collect(),
-                        })
-                    }
-                    Err(e) => Err(e),
-                }
-            } else {
-                Err(anyhow::anyhow!("Invalid SELECT * FROM query format"))
-            }
-        } else if query_lower.contains(" from ") {
-            // Handle SELECT specific_columns FROM table queries (without WHERE)
-            let from_pos = query_lower.find(" from ").unwrap();
-            let columns_part = query[..from_pos].trim();
-            let from_part = query[from_pos + 6..].trim();
-            
-            // Extract column names
-            let select_pos = if columns_part.to_lowercase().starts_with("select ") {
-                7 // Length of "select "
-            } else {
-                return Err(anyhow::anyhow!("Query must start with SELECT"));
-            };
-            
-            let columns_str = columns_part[select_pos..].trim();
-            let column_names: Vec<&str> = columns_str
-                .split(',')
-                .map(|s| s.trim())
-                .collect();
-            
-            // Extract table name (simple case, no WHERE)
-            let table_name = from_part.split_whitespace().next().unwrap_or("");
-            
-            // Get the data
-            match self.database.get_column_values(table_name, &column_names) {
-                Ok(column_data) => {
-                    Ok(QueryResult::Table {
-                        headers: column_names.iter().map(|s| s.to_string()).collect(),
-                        rows: column_data.iter().map(|row| {
-                            row.iter().map(|val| val.to_display_string()).collect()
-                        }).collect(),
-                    })
-                }
-                Err(e) => Err(e),
-            }
-        } else {
-            // For complex queries we don't handle yet, fall back to CLI
-            self.execute_query_via_cli(query)
-        }
-    }
-    
-    fn execute_query_via_cli(&mut self, query: &str) -> Result<QueryResult> {
-        // Execute the query through our command system that handles WHERE clauses
-        self.execute_query_and_capture_result(query)
-    }
-    
-    fn execute_query_and_capture_result(&mut self, query: &str) -> Result<QueryResult> {
-        // Use the command module's handle_select_query logic directly
-        let query_trimmed = query.trim();
-        if query_trimmed.to_lowercase().starts_with("select") {
-            // Parse and execute using our database methods with WHERE clause support
-            self.execute_select_with_where_support(query_trimmed)
-        } else {
-            Err(anyhow::anyhow!("Unsupported query type in TUI"))
-        }
-    }
-    
-    fn execute_select_with_where_support(&mut self, query: &str) -> Result<QueryResult> {
-        // Skip the "SELECT" keyword (case insensitive)
-        let query_after_select = if query.to_lowercase().starts_with("select ") {
-            &query[7..] // Skip "SELECT "
-        } else {
-            return Err(anyhow::anyhow!("Query must start with SELECT"));
-        };
-        
-        let query_lower = query_after_select.to_lowercase();
-        
-        if query_lower.starts_with("*") {
-            // Handle SELECT * FROM table [WHERE condition]
-            let mut parts = query_after_select.split_whitespace();
-            parts.next(); // skip "*"
-            if let Some(from_keyword) = parts.next() {
-                if from_keyword.to_lowercase() == "from" {
-                    let remaining_parts: Vec<&str> = parts.collect();
-                    let (table_name, where_condition) = self.parse_table_and_where(&remaining_parts)?;
-                    
-                    let table_data = self.database.get_table_rows(table_name)?;
-                    
-                    // Apply WHERE filter if present
-                    let final_data = if let Some(condition) = where_condition {
-                        self.apply_where_filter(&table_data, &condition)?
-                    } else {
-                        table_data
-                    };
-                    
-                    Ok(QueryResult::Table {
-                        headers: final_data.columns.iter().map(|col| col.name.clone()).collect(),
-                        rows: final_data.rows.iter().map(|row| {
-                            row.values.iter().map(|val| val.to_display_string()).collect()
-                        }).collect(),
-                    })
-                } else {
-                    Err(anyhow::anyhow!("Expected FROM after SELECT *"))
-                }
-            } else {
-                Err(anyhow::anyhow!("Incomplete SELECT * query"))
-            }
-        } else if query_lower.contains(" from ") {
-            // Handle SELECT column1, column2, ... FROM table [WHERE condition]
-            let from_pos = query_lower.find(" from ").unwrap();
-            let columns_part = query_after_select[..from_pos].trim();
-            let from_part = query_after_select[from_pos + 6..].trim(); // Skip " from "
-            
-            // Parse column names (handle comma-separated)
-            let column_names: Vec<&str> = columns_part
-                .split(',')
-                .map(|s| s.trim())
-                .collect();
-            
-            // Parse table name and WHERE condition from the remaining part
-            let from_parts: Vec<&str> = from_part.split_whitespace().collect();
-            let (table_name, where_condition) = self.parse_table_and_where(&from_parts)?;
-            
-            // Get full table data first
-            let table_data = self.database.get_table_rows(table_name)?;
-            
-            // Apply WHERE filter if present
-            let filtered_data = if let Some(condition) = where_condition {
-                self.apply_where_filter(&table_data, &condition)?
-            } else {
-                table_data
-            };
-            
-            // Extract specific columns
-            let result_data = self.extract_columns(&filtered_data, &column_names)?;
-            
-            Ok(QueryResult::Table {
-                headers: column_names.iter().map(|s| s.to_string()).collect(),
-                rows: result_data.iter().map(|row| {
-                    row.iter().map(|val| val.to_display_string()).collect()
-                }).collect(),
-            })
         } else {
-            Err(anyhow::anyhow!("Complex SELECT queries not yet supported in TUI"))
-        }
-    }
-    
-    fn parse_table_and_where<'a>(&self, parts: &'a [&str]) -> Result<(&'a str, Option<crate::query::WhereCondition>)> {
-        if parts.is_empty() {
-            return Err(anyhow::anyhow!("Missing table name in SELECT query"));
-        }
-        
-        // Convert to lowercase for keyword matching but preserve original case
-        let parts_lower: Vec<String> = parts.iter().map(|s| s.to_lowercase()).collect();
-        
-        // Check if there's a WHERE clause (case-insensitive)
-        if let Some(where_pos) = parts_lower.iter().position(|part| part == "where") {
-            if where_pos == 0 {
-                return Err(anyhow::anyhow!("Missing table name before WHERE clause"));
+            // Statements run on the open database, so an explicit
+            // transaction stays open across queries until COMMIT or ROLLBACK
+            let mut last_result = None;
+            for statement in crate::query::parse(query)? {
+                last_result = crate::query::execute(&mut self.database, &statement)?;
             }
-            let table_name = parts[0];
-            let where_clause = parts[where_pos + 1..].join(" ");
-            let condition = crate::query::WhereCondition::parse(&where_clause)?;
-            Ok((table_name, Some(condition)))
-        } else {
-            Ok((parts[0], None))
-        }
-    }
-    
-    fn apply_where_filter(&self, table_data: &crate::TableRows, condition: &crate::query::WhereCondition) -> Result<crate::TableRows> {
-        // Find the column index for the condition
-        let column_index = table_data.columns.iter()
-            .position(|col| col.name.eq_ignore_ascii_case(&condition.column_name))
-            .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in table", condition.column_name))?;
-        
-        // Filter rows based on the condition
-        let filtered_rows: Vec<crate::database::TableRow> = table_data.rows.iter()
-            .filter(|row| {
-                if let Some(value) = row.values.get(column_index) {
-                    condition.matches(value)
-                } else {
-                    false
-                }
-            })
-            .cloned()
-            .collect();
-        
-        Ok(crate::TableRows {
-            columns: table_data.columns.clone(),
-            rows: filtered_rows,
-        })
-    }
-    
-    /// Extract specific columns from table data
-    fn extract_columns(&self, table_data: &crate::TableRows, column_names: &[&str]) -> Result<Vec<Vec<crate::RecordValue>>> {
-        let mut column_indices = Vec::new();
-        
-        // Find indices for the requested columns
-        for col_name in column_names {
-            let index = table_data.columns.iter()
-                .position(|col| col.name.eq_ignore_ascii_case(col_name))
-                .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in table", col_name))?;
-            column_indices.push(index);
-        }
-        
-        // Extract values for the specified columns
-        let mut results = Vec::new();
-        for row in &table_data.rows {
-            let mut row_values = Vec::new();
-            for &col_index in &column_indices {
-                if let Some(value) = row.values.get(col_index) {
-                    row_values.push(value.clone());
-                } else {
-                    row_values.push(crate::RecordValue::Null);
+            self.tables = self.database.get_table_names()?;
+
+            match last_result {
+                Some(result) => Ok(QueryResult::Table {
+                    headers: result.columns,
+                    rows: result.rows.iter().map(|row| {
+                        row.iter().map(|val| val.to_display_string()).collect()
+                    }).collect(),
+                }),
+                None if self.database.in_transaction() => {
+                    Ok(QueryResult::Text("Command executed successfully (transaction open)".to_string()))
                 }
+                None => Ok(QueryResult::Text("Command executed successfully".to_string())),
             }
-            results.push(row_values);
         }
-        
-        Ok(results)
     }
     
     pub fn add_char_to_query(&mut self, c: char) {
@@ -667,7 +430,15 @@ impl App {
                     if let Some(sql) = &obj.sql {
                         content.push_str(&format!("-- {}: {}\n", obj.object_type.to_uppercase(), obj.name));
                         content.push_str(sql);
-                        content.push_str("\n\n");
+                        content.push('\n');
+                        if obj.object_type == "table" {
+                            if let Ok(columns) = self.database.get_col_names(&obj.name) {
+                                for column in &columns {
+                                    content.push_str(&format!("--   {}\n", column_summary(column)));
+                                }
+                            }
+                        }
+                        content.push('\n');
                     }
                 }
                 if content.is_empty() {
@@ -722,10 +493,8 @@ fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<()>
             if let Event::Key(key) = event::read()? {
                 // Only handle key press events, ignore repeat and release events
                 // This fixes the double input issue on Windows
-                if key.kind == KeyEventKind::Press {
-                    if handle_key_event(app, key)? {
-                        return Ok(());
-                    }
+                if key.kind == KeyEventKind::Press && handle_key_event(app, key)? {
+                    return Ok(());
                 }
             }
         }
@@ -1289,6 +1058,41 @@ fn render_query_view(f: &mut Frame, area: Rect, app: &App) {
     }
 }
 
+/// One-line description of a column for the schema view, e.g.
+/// `name VARCHAR(20) (TEXT affinity) NOT NULL DEFAULT 'x'`
+fn column_summary(column: &ColumnInfo) -> String {
+    let mut summary = column.name.clone();
+    if !column.declared_type.is_empty() {
+        summary.push_str(&format!(" {}", column.declared_type));
+    }
+    summary.push_str(&format!(" ({} affinity)", column.affinity));
+    if column.is_primary_key {
+        summary.push_str(" PRIMARY KEY");
+    }
+    if column.not_null {
+        summary.push_str(" NOT NULL");
+    }
+    if column.is_unique {
+        summary.push_str(" UNIQUE");
+    }
+    if let Some(default) = &column.default_value {
+        summary.push_str(&format!(" DEFAULT {}", default));
+    }
+    if let Some(collation) = &column.collation {
+        summary.push_str(&format!(" COLLATE {}", collation));
+    }
+    for check in &column.checks {
+        summary.push_str(&format!(" CHECK ({})", check));
+    }
+    if let Some(references) = &column.references {
+        summary.push_str(&format!(" REFERENCES {}", references.table));
+        if !references.columns.is_empty() {
+            summary.push_str(&format!("({})", references.columns.join(", ")));
+        }
+    }
+    summary
+}
+
 fn render_schema_view(f: &mut Frame, area: Rect, app: &App) {
     if app.schema_content.is_empty() {
         let help_text = vec![